@@ -0,0 +1,66 @@
+//! Reads and rewrites a synthetic session directory with the I/O of before --io-buffer and with several buffer
+//! sizes: `cargo bench -p reptool-core --bench io_buffer`
+
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use reptool_core::file::{read_buffered, write_atomic, DEFAULT_IO_BUFFER};
+
+const SMALL_FILES: usize = 500;
+const LARGE_FILES: usize = 10;
+const LARGE_FILE_LEN: usize = 4 * 1024 * 1024;
+
+fn main() {
+    let dir = std::env::temp_dir().join(format!("reptool-bench-{}", std::process::id()));
+    let file_paths = synthetic_session(&dir);
+
+    println!("{} files of ~1 KiB and {} of {} MiB", SMALL_FILES, LARGE_FILES, LARGE_FILE_LEN / 1024 / 1024);
+    report("unbuffered (before --io-buffer)", time(&file_paths, |file_path| {
+        // fs::read, then the temporary file written in one unbuffered call and renamed over the original
+        let content = fs::read(file_path).unwrap();
+        let tmp_path = file_path.with_extension("reptool.tmp");
+        let mut file = File::create(&tmp_path).unwrap();
+        file.write_all(&content).unwrap();
+        file.sync_all().unwrap();
+        fs::set_permissions(&tmp_path, fs::metadata(file_path).unwrap().permissions()).unwrap();
+        fs::rename(&tmp_path, file_path).unwrap();
+    }));
+    for io_buffer in [DEFAULT_IO_BUFFER, 64 * 1024, 1024 * 1024] {
+        report(&format!("--io-buffer {}", io_buffer), time(&file_paths, |file_path| {
+            let content = read_buffered(file_path, io_buffer).unwrap();
+            write_atomic(file_path, &content, io_buffer).unwrap();
+        }));
+    }
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+fn synthetic_session(dir: &Path) -> Vec<PathBuf> {
+    fs::create_dir_all(dir).unwrap();
+    let mut file_paths = Vec::new();
+    for index in 0..SMALL_FILES + LARGE_FILES {
+        let padding = if index < SMALL_FILES { 900 } else { LARGE_FILE_LEN };
+        let file_path = dir.join(format!("{:040X}.torrent.rtorrent", index));
+        let content = format!("d9:directory23:/mnt/data/torrents/{:04}7:padding{}:{}e", index % 10_000, padding, "x".repeat(padding));
+        fs::write(&file_path, content).unwrap();
+        file_paths.push(file_path);
+    }
+    file_paths
+}
+
+/// Best of five passes over every file
+fn time(file_paths: &[PathBuf], mut rewrite: impl FnMut(&Path)) -> Duration {
+    (0..5).map(|_| {
+        let started = Instant::now();
+        for file_path in file_paths {
+            rewrite(file_path);
+        }
+        started.elapsed()
+    }).min().unwrap()
+}
+
+fn report(name: &str, elapsed: Duration) {
+    println!("{:<34} {:>8.1} ms", name, elapsed.as_secs_f64() * 1000.0);
+}
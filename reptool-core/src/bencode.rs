@@ -0,0 +1,211 @@
+use std::collections::BTreeMap;
+
+use anyhow::{bail, Context, Result};
+
+// Guard against stack exhaustion on maliciously nested input
+pub(crate) const MAX_DEPTH: usize = 256;
+
+/// A decoded bencode value
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    Integer(i64),
+    Bytes(Vec<u8>),
+    List(Vec<Value>),
+    Dict(BTreeMap<Vec<u8>, Value>),
+}
+
+impl Value {
+    pub fn as_dict(&self) -> Option<&BTreeMap<Vec<u8>, Value>> {
+        match self {
+            Value::Dict(dict) => Some(dict),
+            _ => None,
+        }
+    }
+}
+
+/// Decode a complete bencoded buffer, rejecting trailing garbage
+pub fn decode(data: &[u8]) -> Result<Value> {
+    let mut decoder = Decoder { data, pos: 0 };
+    let value = decoder.parse_value(0)?;
+    if decoder.pos != data.len() {
+        bail!("Unexpected trailing data at offset {}", decoder.pos);
+    }
+    Ok(value)
+}
+
+/// The bytes of the top level `key` value exactly as they are in `data`, e.g. the `info` dictionary to hash
+pub fn raw_field<'a>(data: &'a [u8], key: &[u8]) -> Result<Option<&'a [u8]>> {
+    let mut decoder = Decoder { data, pos: 0 };
+    if decoder.peek()? != b'd' {
+        bail!("Top level value is not a dictionary");
+    }
+    decoder.pos += 1;
+    while decoder.peek()? != b'e' {
+        let item_key = decoder.parse_bytes()?;
+        let start = decoder.pos;
+        decoder.parse_value(1)?;
+        if item_key == key {
+            return Ok(Some(&data[start..decoder.pos]));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Encode a value, dictionary keys are written in sorted order as bencode requires
+pub fn encode(value: &Value) -> Vec<u8> {
+    let mut out = Vec::new();
+    encode_into(value, &mut out);
+    out
+}
+
+fn encode_into(value: &Value, out: &mut Vec<u8>) {
+    match value {
+        Value::Integer(number) => {
+            out.push(b'i');
+            out.extend_from_slice(number.to_string().as_bytes());
+            out.push(b'e');
+        }
+        Value::Bytes(bytes) => encode_bytes(bytes, out),
+        Value::List(list) => {
+            out.push(b'l');
+            for item in list {
+                encode_into(item, out);
+            }
+            out.push(b'e');
+        }
+        Value::Dict(dict) => {
+            out.push(b'd');
+            for (key, item) in dict {
+                encode_bytes(key, out);
+                encode_into(item, out);
+            }
+            out.push(b'e');
+        }
+    }
+}
+
+fn encode_bytes(bytes: &[u8], out: &mut Vec<u8>) {
+    out.extend_from_slice(bytes.len().to_string().as_bytes());
+    out.push(b':');
+    out.extend_from_slice(bytes);
+}
+
+struct Decoder<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Decoder<'a> {
+    fn peek(&self) -> Result<u8> {
+        self.data.get(self.pos).copied().with_context(|| format!("Unexpected end of data at offset {}", self.pos))
+    }
+
+    fn parse_value(&mut self, depth: usize) -> Result<Value> {
+        if depth > MAX_DEPTH {
+            bail!("Nesting too deep at offset {}", self.pos);
+        }
+
+        match self.peek()? {
+            b'i' => {
+                self.pos += 1;
+                let number = self.read_until(b'e')?;
+                let number = std::str::from_utf8(number)?;
+                let number = number.parse::<i64>().with_context(|| format!("Invalid integer {:?}", number))?;
+                Ok(Value::Integer(number))
+            }
+            b'l' => {
+                self.pos += 1;
+                let mut list = Vec::new();
+                while self.peek()? != b'e' {
+                    list.push(self.parse_value(depth + 1)?);
+                }
+                self.pos += 1;
+                Ok(Value::List(list))
+            }
+            b'd' => {
+                self.pos += 1;
+                let mut dict = BTreeMap::new();
+                while self.peek()? != b'e' {
+                    let key = self.parse_bytes()?.to_vec();
+                    let value = self.parse_value(depth + 1)?;
+                    dict.insert(key, value);
+                }
+                self.pos += 1;
+                Ok(Value::Dict(dict))
+            }
+            b'0'..=b'9' => Ok(Value::Bytes(self.parse_bytes()?.to_vec())),
+            other => bail!("Unexpected byte {:?} at offset {}", other as char, self.pos),
+        }
+    }
+
+    fn parse_bytes(&mut self) -> Result<&'a [u8]> {
+        let start = self.pos;
+        let len = self.read_until(b':')?;
+        let len = std::str::from_utf8(len)?;
+        let len: usize = len.parse().with_context(|| format!("Invalid string length at offset {}", start))?;
+        let end = self.pos.checked_add(len).filter(|&end| end <= self.data.len())
+            .with_context(|| format!("String at offset {} overruns the data", start))?;
+        let bytes = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn read_until(&mut self, delimiter: u8) -> Result<&'a [u8]> {
+        let start = self.pos;
+        let len = self.data[start..].iter().position(|&b| b == delimiter)
+            .with_context(|| format!("Missing {:?} after offset {}", delimiter as char, start))?;
+        self.pos = start + len + 1;
+        Ok(&self.data[start..start + len])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_canonical_data() {
+        let data = b"d8:announce13:http://tr/ann4:infod5:filesld4:pathl1:a1:beee6:lengthi42e4:name5:moviee4:listli-7e0:ee";
+        assert_eq!(encode(&decode(data).unwrap()), data);
+    }
+
+    #[test]
+    fn nested_dicts_are_decoded() {
+        let value = decode(b"d1:ad1:bd1:ci1eeee").unwrap();
+        let inner = value.as_dict().unwrap()[b"a".as_slice()].as_dict().unwrap()[b"b".as_slice()].as_dict().unwrap();
+        assert_eq!(inner[b"c".as_slice()], Value::Integer(1));
+    }
+
+    #[test]
+    fn unsorted_keys_are_sorted_on_encode() {
+        let value = decode(b"d1:bi2e1:ai1ee").unwrap();
+        assert_eq!(encode(&value), b"d1:ai1e1:bi2ee");
+    }
+
+    #[test]
+    fn non_canonical_integers_are_read_and_written_canonical() {
+        assert_eq!(decode(b"i007e").unwrap(), Value::Integer(7));
+        assert_eq!(decode(b"i-0e").unwrap(), Value::Integer(0));
+        assert_eq!(encode(&decode(b"li007ei-0ee").unwrap()), b"li7ei0ee");
+        assert!(decode(b"i1.5e").is_err());
+        assert!(decode(b"ie").is_err());
+    }
+
+    #[test]
+    fn raw_field_keeps_the_original_bytes() {
+        let data = b"d4:infod1:bi007e1:ai1ee4:name1:xe";
+        assert_eq!(raw_field(data, b"info").unwrap(), Some(&b"d1:bi007e1:ai1ee"[..]));
+        assert_eq!(raw_field(data, b"missing").unwrap(), None);
+    }
+
+    #[test]
+    fn rejects_malformed_data() {
+        assert!(decode(b"d1:ai1ee trailing").is_err());
+        assert!(decode(b"5:abc").is_err());
+        assert!(decode(b"l").is_err());
+        assert!(decode(b"di1ei2ee").is_err());
+        let deep = [vec![b'l'; MAX_DEPTH + 2], vec![b'e'; MAX_DEPTH + 2]].concat();
+        assert!(decode(&deep).is_err());
+    }
+}
@@ -0,0 +1,68 @@
+use anyhow::{bail, Result};
+
+/// How the bytes of a session file map to the text the search and replace strings are matched against
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+pub enum Encoding {
+    Utf8,
+    /// ISO-8859-1, every byte is one character, for sessions created before UTF-8 was common
+    Latin1,
+}
+
+impl Encoding {
+    pub fn decode(self, bytes: &[u8]) -> Result<String> {
+        match self {
+            Encoding::Utf8 => Ok(String::from_utf8(bytes.to_vec())?),
+            Encoding::Latin1 => Ok(bytes.iter().map(|&byte| byte as char).collect()),
+        }
+    }
+
+    pub fn encode(self, text: &str) -> Result<Vec<u8>> {
+        match self {
+            Encoding::Utf8 => Ok(text.as_bytes().to_vec()),
+            Encoding::Latin1 => text.chars().map(|c| match u8::try_from(c) {
+                Ok(byte) => Ok(byte),
+                Err(_) => bail!("Character {:?} cannot be encoded as latin1", c),
+            }).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bencode;
+    use crate::session_file::{Anchor, ReplaceOptions, Rule, SessionFile};
+
+    #[test]
+    fn latin1_round_trips_every_byte() {
+        let bytes: Vec<u8> = (0..=255).collect();
+        assert_eq!(Encoding::Latin1.encode(&Encoding::Latin1.decode(&bytes).unwrap()).unwrap(), bytes);
+        assert!(Encoding::Utf8.decode(b"caf\xe9").is_err());
+        assert!(Encoding::Latin1.encode("\u{20ac}").is_err());
+    }
+
+    #[test]
+    fn latin1_replace_recomputes_the_length_and_keeps_the_other_bytes() {
+        // "/mnt/donn\xe9es/caf\xe9" is not UTF-8, only the search string is rewritten
+        let mut file = SessionFile::decode("test.torrent.rtorrent", b"d9:directory17:/mnt/donn\xe9es/caf\xe9e").unwrap();
+        let rules = [Rule { find: "/mnt/données".to_owned(), replace: "/srv/été".to_owned(), ignore_case: false }];
+        let options = ReplaceOptions {
+            rules: &rules,
+            patterns: &[],
+            encoding: Encoding::Latin1,
+            only_from: &[],
+            base_only: false,
+            all_occurrences: false,
+            anchor: Anchor::Anywhere,
+            ignore_case: false,
+            path_style: None,
+            normalize: None,
+        };
+        let report = file.replace(&["directory".to_owned()], &options).unwrap();
+
+        assert_eq!(report.modifications[0].new_value, "/srv/été/café");
+        assert_eq!((report.modifications[0].old_length, report.modifications[0].new_length), (17, 13));
+        assert_eq!(bencode::encode(file.value()), b"d9:directory13:/srv/\xe9t\xe9/caf\xe9e");
+    }
+}
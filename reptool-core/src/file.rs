@@ -0,0 +1,82 @@
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+// Same as the standard library default, session files are small
+pub const DEFAULT_IO_BUFFER: usize = 8 * 1024;
+
+/// Write through a temporary file renamed over the target, so the file holds either the old or the new content
+pub fn write_atomic(file_path: &Path, content: &[u8], io_buffer: usize) -> Result<()> {
+    let mut writer = AtomicWriter::create(file_path, io_buffer)?;
+    writer.write_all(content)?;
+    writer.commit()
+}
+
+/// The new content of a file written piece by piece to a temporary file next to it. `commit` renames it over the
+/// file, dropping the writer without committing leaves the file untouched.
+pub struct AtomicWriter {
+    file_path: PathBuf,
+    tmp_path: PathBuf,
+    writer: Option<BufWriter<fs::File>>,
+}
+
+impl AtomicWriter {
+    pub fn create(file_path: &Path, io_buffer: usize) -> Result<Self> {
+        let mut tmp_name = OsString::from(".");
+        tmp_name.push(file_path.file_name().context("Missing file name")?);
+        tmp_name.push(".reptool.tmp");
+        let tmp_path = file_path.with_file_name(tmp_name);
+
+        let file = fs::File::create(&tmp_path).with_context(|| format!("Failed to create file: {:?}", tmp_path))?;
+        Ok(AtomicWriter { file_path: file_path.to_path_buf(), tmp_path, writer: Some(BufWriter::with_capacity(io_buffer, file)) })
+    }
+
+    pub fn commit(mut self) -> Result<()> {
+        let writer = self.writer.take().expect("Writer taken on commit only");
+        let file = writer.into_inner().map_err(|err| err.into_error())?;
+        file.sync_all()?;
+        if let Ok(metadata) = fs::metadata(&self.file_path) {
+            fs::set_permissions(&self.tmp_path, metadata.permissions())?;
+        }
+        fs::rename(&self.tmp_path, &self.file_path).with_context(|| format!("Failed to replace file: {:?}", self.file_path))?;
+
+        Ok(())
+    }
+
+    /// What was written so far as read back from the temporary file, to be checked before the commit
+    pub fn read_back(&mut self, io_buffer: usize) -> Result<Vec<u8>> {
+        self.writer.as_mut().expect("Writer taken on commit only").flush()?;
+        read_buffered(&self.tmp_path, io_buffer).with_context(|| format!("Failed to read back file: {:?}", self.tmp_path))
+    }
+}
+
+impl Write for AtomicWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writer.as_mut().expect("Writer taken on commit only").write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.as_mut().expect("Writer taken on commit only").flush()
+    }
+}
+
+impl Drop for AtomicWriter {
+    fn drop(&mut self) {
+        // Left behind when not committed or when the rename failed, closed first for systems that cannot remove open files
+        drop(self.writer.take());
+        if self.tmp_path.exists() {
+            let _ = fs::remove_file(&self.tmp_path);
+        }
+    }
+}
+
+/// Read a whole file through a buffer of `io_buffer` bytes
+pub fn read_buffered(file_path: &Path, io_buffer: usize) -> io::Result<Vec<u8>> {
+    let file = fs::File::open(file_path)?;
+    let mut content = Vec::with_capacity(file.metadata().map_or(0, |metadata| metadata.len() as usize));
+    BufReader::with_capacity(io_buffer, file).read_to_end(&mut content)?;
+    Ok(content)
+}
@@ -0,0 +1,44 @@
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+
+use crate::bencode::{self, Value};
+
+/// The info-hashes of a .torrent as uppercase hex, computed over the `info` dictionary exactly as it is in the file
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct InfoHash {
+    /// SHA-1, the hash rtorrent names its session files after, missing for v2 only torrents
+    pub v1: Option<String>,
+    /// SHA-256 of v2 and hybrid torrents
+    pub v2: Option<String>,
+}
+
+impl InfoHash {
+    /// Hash the `info` dictionary of the bencoded .torrent in `data`
+    pub fn of_torrent(data: &[u8]) -> Result<Self> {
+        let info = bencode::raw_field(data, b"info")?.context("Missing info dictionary")?;
+        let Value::Dict(info_dict) = bencode::decode(info)? else {
+            bail!("The info field is not a dictionary");
+        };
+
+        let is_v2 = matches!(info_dict.get(b"meta version".as_slice()), Some(Value::Integer(2)));
+        let is_v1 = !is_v2 || info_dict.contains_key(b"pieces".as_slice());
+        Ok(InfoHash {
+            v1: is_v1.then(|| to_hex(&Sha1::digest(info))),
+            v2: is_v2.then(|| to_hex(&Sha256::digest(info))),
+        })
+    }
+
+    /// Whether `hash` is one of these hashes, in any case. A v2 hash also matches cut to 40 digits, the form
+    /// v1 only tools use for v2 torrents.
+    pub fn matches(&self, hash: &str) -> bool {
+        let hash = hash.to_uppercase();
+        self.v1.as_ref().is_some_and(|v1| *v1 == hash)
+            || self.v2.as_ref().is_some_and(|v2| *v2 == hash || (hash.len() == 40 && v2.starts_with(&hash)))
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02X}", byte)).collect()
+}
@@ -0,0 +1,137 @@
+//! Lossless conversion between bencode and JSON for inspecting and hand editing session files.
+//!
+//! Integers become numbers, lists arrays and dictionaries objects. Strings that are valid UTF-8 become JSON
+//! strings, the others `{"$hex": "..."}` or `{"$base64": "..."}`. Dictionary keys starting with `$` get one more
+//! `$` so they are never taken for such a marker, keys that are not valid UTF-8 are written as `$hex:...`.
+
+use std::collections::BTreeMap;
+
+use anyhow::{bail, Context, Result};
+use serde_json::{Map, Number};
+
+use crate::bencode::Value;
+
+const HEX_MARKER: &str = "$hex";
+const BASE64_MARKER: &str = "$base64";
+const BASE64_ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// How strings that are not valid UTF-8 are written
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+pub enum Binary {
+    #[default]
+    Hex,
+    Base64,
+}
+
+pub fn to_json(value: &Value, binary: Binary) -> serde_json::Value {
+    match value {
+        Value::Integer(integer) => serde_json::Value::Number(Number::from(*integer)),
+        Value::Bytes(bytes) => match std::str::from_utf8(bytes) {
+            Ok(text) => serde_json::Value::String(text.to_owned()),
+            Err(_) => {
+                let (marker, text) = match binary {
+                    Binary::Hex => (HEX_MARKER, to_hex(bytes)),
+                    Binary::Base64 => (BASE64_MARKER, to_base64(bytes)),
+                };
+                serde_json::Value::Object(Map::from_iter([(marker.to_owned(), serde_json::Value::String(text))]))
+            }
+        },
+        Value::List(list) => serde_json::Value::Array(list.iter().map(|item| to_json(item, binary)).collect()),
+        Value::Dict(dict) => serde_json::Value::Object(dict.iter().map(|(key, item)| (json_key(key), to_json(item, binary))).collect()),
+    }
+}
+
+pub fn from_json(json: &serde_json::Value) -> Result<Value> {
+    Ok(match json {
+        serde_json::Value::Number(number) => Value::Integer(number.as_i64().with_context(|| format!("Not a 64 bit integer: {}", number))?),
+        serde_json::Value::String(text) => Value::Bytes(text.as_bytes().to_vec()),
+        serde_json::Value::Array(list) => Value::List(list.iter().map(from_json).collect::<Result<_>>()?),
+        serde_json::Value::Object(object) => match binary_marker(object) {
+            Some((HEX_MARKER, text)) => Value::Bytes(from_hex(text)?),
+            Some((_, text)) => Value::Bytes(from_base64(text)?),
+            None => {
+                let mut dict = BTreeMap::new();
+                for (key, item) in object {
+                    dict.insert(bencode_key(key)?, from_json(item)?);
+                }
+                Value::Dict(dict)
+            }
+        },
+        serde_json::Value::Bool(_) | serde_json::Value::Null => bail!("Bencode has no booleans or null: {}", json),
+    })
+}
+
+/// `{"$hex": "..."}` or `{"$base64": "..."}`, escaped dictionary keys never match
+fn binary_marker(object: &Map<String, serde_json::Value>) -> Option<(&'static str, &str)> {
+    let (key, serde_json::Value::String(text)) = object.iter().next().filter(|_| object.len() == 1)? else {
+        return None;
+    };
+    [HEX_MARKER, BASE64_MARKER].into_iter().find(|marker| key == marker).map(|marker| (marker, text.as_str()))
+}
+
+fn json_key(key: &[u8]) -> String {
+    match std::str::from_utf8(key) {
+        Ok(key) if key.starts_with('$') => format!("${}", key),
+        Ok(key) => key.to_owned(),
+        Err(_) => format!("{}:{}", HEX_MARKER, to_hex(key)),
+    }
+}
+
+fn bencode_key(key: &str) -> Result<Vec<u8>> {
+    if let Some(hex) = key.strip_prefix(HEX_MARKER).and_then(|rest| rest.strip_prefix(':')) {
+        return from_hex(hex);
+    }
+    match key.strip_prefix('$') {
+        Some(escaped) if escaped.starts_with('$') => Ok(escaped.as_bytes().to_vec()),
+        Some(_) => bail!("Unknown key marker: {:?}, a literal $ is written $$", key),
+        None => Ok(key.as_bytes().to_vec()),
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(text: &str) -> Result<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        bail!("Odd number of hex digits: {:?}", text);
+    }
+    (0..text.len()).step_by(2)
+        .map(|index| u8::from_str_radix(&text[index..index + 2], 16).with_context(|| format!("Invalid hex: {:?}", text)))
+        .collect()
+}
+
+fn to_base64(bytes: &[u8]) -> String {
+    let mut text = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0u32, |group, (index, &byte)| group | u32::from(byte) << (16 - 8 * index));
+        for index in 0..4 {
+            if index <= chunk.len() {
+                text.push(char::from(BASE64_ALPHABET[(group >> (18 - 6 * index) & 0x3f) as usize]));
+            } else {
+                text.push('=');
+            }
+        }
+    }
+
+    text
+}
+
+fn from_base64(text: &str) -> Result<Vec<u8>> {
+    let digits = text.trim_end_matches('=').as_bytes();
+    let mut bytes = Vec::with_capacity(digits.len() * 3 / 4);
+    for chunk in digits.chunks(4) {
+        let mut group = 0u32;
+        for (index, &digit) in chunk.iter().enumerate() {
+            let value = BASE64_ALPHABET.iter().position(|&letter| letter == digit).with_context(|| format!("Invalid base64: {:?}", text))?;
+            group |= (value as u32) << (18 - 6 * index);
+        }
+        if chunk.len() == 1 {
+            bail!("Truncated base64: {:?}", text);
+        }
+        bytes.extend_from_slice(&group.to_be_bytes()[1..chunk.len()]);
+    }
+
+    Ok(bytes)
+}
@@ -0,0 +1,18 @@
+//! Session file editing shared by the reptool front ends: bencode, text encodings, atomic writes and the
+//! search and replace of the directory field, a JSON view of any bencoded file, the piece check of the data and
+//! streaming rewrites of files too large to decode whole.
+
+pub mod bencode;
+pub mod encoding;
+pub mod file;
+pub mod info_hash;
+pub mod json;
+pub mod pieces;
+pub mod report;
+mod session_file;
+pub mod stream;
+
+pub use encoding::Encoding;
+pub use info_hash::InfoHash;
+pub use report::ModifyReport;
+pub use session_file::{Anchor, Normalize, PathStyle, ReplaceOptions, ReplaceReport, Rule, SessionFile};
@@ -0,0 +1,142 @@
+//! Check the payload of a torrent against the SHA-1 piece hashes of its .torrent.
+
+use std::fs::{self, File};
+use std::io::{BufReader, Read};
+use std::path::PathBuf;
+
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+use sha1::{Digest, Sha1};
+
+/// What a check found, a piece counts as done when all its files are there with the right size and, when hashed,
+/// its data matches
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PieceCheck {
+    pub pieces: usize,
+    pub pieces_ok: usize,
+    pub missing_files: Vec<PathBuf>,
+    pub wrong_size: Vec<PathBuf>,
+}
+
+impl PieceCheck {
+    pub fn percent(&self) -> f64 {
+        match self.pieces {
+            0 => 100.0,
+            pieces => self.pieces_ok as f64 * 100.0 / pieces as f64,
+        }
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.pieces_ok == self.pieces
+    }
+}
+
+/// Check the (path, length) `files` of a torrent, in the order of the .torrent, against the concatenated SHA-1
+/// `pieces` of `piece_length` bytes. Without `hash_data` only the presence and size of the files is checked.
+pub fn check(files: &[(PathBuf, u64)], piece_length: u64, pieces: &[u8], hash_data: bool, io_buffer: usize) -> Result<PieceCheck> {
+    if piece_length == 0 || !pieces.len().is_multiple_of(20) {
+        bail!("Invalid piece length or pieces");
+    }
+    let total: u64 = files.iter().map(|(_, length)| length).sum();
+    let piece_count = pieces.len() / 20;
+    if total.div_ceil(piece_length) != piece_count as u64 {
+        bail!("{} pieces do not cover {} bytes", piece_count, total);
+    }
+
+    let mut report = PieceCheck { pieces: piece_count, ..Default::default() };
+    let mut is_good = vec![true; piece_count];
+    let mut is_file_good = Vec::with_capacity(files.len());
+    let mut offset = 0;
+    for (path, length) in files {
+        let is_file_ok = match fs::metadata(path) {
+            Err(_) => {
+                report.missing_files.push(path.clone());
+                false
+            }
+            Ok(metadata) if metadata.len() != *length => {
+                report.wrong_size.push(path.clone());
+                false
+            }
+            Ok(_) => true,
+        };
+        if !is_file_ok && *length > 0 {
+            for index in offset / piece_length..=(offset + length - 1) / piece_length {
+                is_good[index as usize] = false;
+            }
+        }
+        is_file_good.push(is_file_ok);
+        offset += length;
+    }
+
+    if hash_data {
+        hash_pieces(files, &is_file_good, piece_length, pieces, &mut is_good, io_buffer)?;
+    }
+    report.pieces_ok = is_good.iter().filter(|is_good| **is_good).count();
+
+    Ok(report)
+}
+
+/// Hash the pieces still marked good, the bytes of bad files are skipped as their pieces are bad already
+fn hash_pieces(files: &[(PathBuf, u64)], is_file_good: &[bool], piece_length: u64, pieces: &[u8], is_good: &mut [bool], io_buffer: usize) -> Result<()> {
+    let piece_size = piece_length as usize;
+    // Always holds the first `offset % piece_length` bytes of the current piece
+    let mut buffer = Vec::with_capacity(piece_size);
+    let mut offset = 0u64;
+    let finish_piece = |index: u64, data: &[u8], is_good: &mut [bool]| {
+        let index = index as usize;
+        if is_good[index] {
+            is_good[index] = Sha1::digest(data).as_slice() == &pieces[index * 20..index * 20 + 20];
+        }
+    };
+
+    for ((path, length), is_file_ok) in files.iter().zip(is_file_good) {
+        let end = offset + length;
+        if !is_file_ok {
+            if end / piece_length == offset / piece_length {
+                buffer.resize(buffer.len() + *length as usize, 0);
+            } else {
+                buffer.clear();
+                buffer.resize((end % piece_length) as usize, 0);
+            }
+            offset = end;
+            continue;
+        }
+
+        let file = File::open(path).with_context(|| format!("Failed to open file: {:?}", path))?;
+        let mut reader = BufReader::with_capacity(io_buffer, file).take(*length);
+        loop {
+            let start = buffer.len();
+            buffer.resize(piece_size, 0);
+            let read = read_full(&mut reader, &mut buffer[start..]).with_context(|| format!("Failed to read file: {:?}", path))?;
+            buffer.truncate(start + read);
+            offset += read as u64;
+            if buffer.len() == piece_size {
+                finish_piece(offset / piece_length - 1, &buffer, is_good);
+                buffer.clear();
+            }
+            if read < piece_size - start {
+                break;
+            }
+        }
+        if offset != end {
+            bail!("{:?} changed while it was hashed", path);
+        }
+    }
+    if !buffer.is_empty() {
+        finish_piece(offset / piece_length, &buffer, is_good);
+    }
+
+    Ok(())
+}
+
+/// Read until `buffer` is full or the end of `reader`
+fn read_full(reader: &mut impl Read, buffer: &mut [u8]) -> std::io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..])? {
+            0 => break,
+            read => filled += read,
+        }
+    }
+    Ok(filled)
+}
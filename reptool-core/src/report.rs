@@ -0,0 +1,231 @@
+use std::time::Duration;
+
+use serde::Serialize;
+use serde_json::json;
+
+/// Bumped whenever a report field is renamed, removed or changes meaning. Fields are added without a bump, the
+/// schema allows unknown ones and consumers must ignore them. 2 keeps only the noteworthy file reports of a replace
+/// run, with the fields added since 1.
+pub const REPORT_VERSION: u32 = 2;
+
+/// One rewritten string field
+#[derive(Debug, Clone, Serialize)]
+pub struct ModifyReport {
+    pub key: String,
+    pub old_value: String,
+    pub new_value: String,
+    pub old_length: usize,
+    pub new_length: usize,
+}
+
+/// Outcome of processing one file
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct FileReport {
+    pub file: String,
+    pub matched: bool,
+    pub timed_out: bool,
+    pub protected: bool,
+    pub archived_to: Option<String>,
+    pub modifications: Vec<ModifyReport>,
+    /// Matching values left alone because they are not under an --only-from root
+    pub outside_roots: Vec<String>,
+    /// Data paths the new values point to that do not exist, filled by --check-data
+    pub missing_data: Vec<String>,
+    /// The session file has no field matching the keywords, common for magnet stubs
+    pub missing_keyword: bool,
+    /// Skipped without being decoded, none of the search strings is in the file
+    pub skipped_no_match: bool,
+    /// Left alone because its fields already hold what the rules write, a run repeated with the same rules
+    pub already_migrated: bool,
+    /// Why the file could not be processed, the run carries on with the next file
+    pub error: Option<String>,
+    /// Size of the rewritten session file, 0 when it was left alone
+    pub bytes_written: u64,
+    /// Size of the data moved or copied to the new location
+    pub data_moved: u64,
+}
+
+/// Totals of a whole run plus the per file reports
+#[derive(Debug, Clone, Serialize)]
+pub struct RunSummary {
+    pub version: u32,
+    pub files_scanned: usize,
+    pub files_matched: usize,
+    pub files_timed_out: usize,
+    pub files_protected: usize,
+    pub files_outside_roots: usize,
+    pub files_missing_data: usize,
+    pub files_missing_keyword: usize,
+    pub files_skipped_no_match: usize,
+    pub files_already_migrated: usize,
+    pub files_failed: usize,
+    pub fields_rewritten: usize,
+    pub bytes_written: u64,
+    pub data_moved: u64,
+    /// Wall time of the run, 0 when not measured
+    pub elapsed_ms: u64,
+    pub files_per_second: f64,
+    /// The file reports, a replace run only keeps the noteworthy ones
+    pub files: Vec<FileReport>,
+}
+
+impl FileReport {
+    /// Whether the file has more to say than being counted: changed, failed, timed out or left alone for a reason
+    /// the user may want to act on
+    pub fn is_noteworthy(&self) -> bool {
+        self.matched || self.error.is_some() || self.timed_out || self.protected || self.already_migrated
+            || self.archived_to.is_some() || !self.outside_roots.is_empty() || !self.missing_data.is_empty()
+    }
+}
+
+impl Default for RunSummary {
+    fn default() -> Self {
+        RunSummary {
+            version: REPORT_VERSION,
+            files_scanned: 0,
+            files_matched: 0,
+            files_timed_out: 0,
+            files_protected: 0,
+            files_outside_roots: 0,
+            files_missing_data: 0,
+            files_missing_keyword: 0,
+            files_skipped_no_match: 0,
+            files_already_migrated: 0,
+            files_failed: 0,
+            fields_rewritten: 0,
+            bytes_written: 0,
+            data_moved: 0,
+            elapsed_ms: 0,
+            files_per_second: 0.0,
+            files: Vec::new(),
+        }
+    }
+}
+
+impl RunSummary {
+    /// The totals of `files`, every report kept
+    pub fn new(files: Vec<FileReport>) -> Self {
+        let mut summary = RunSummary::default();
+        for file in files {
+            summary.count(&file);
+            summary.files.push(file);
+        }
+        summary
+    }
+
+    /// Count `file` in the totals and keep its report only when it is noteworthy, so a long run holds the reports of
+    /// the files it changed or failed on rather than of every file it scanned
+    pub fn add(&mut self, file: FileReport) {
+        self.count(&file);
+        if file.is_noteworthy() {
+            self.files.push(file);
+        }
+    }
+
+    /// Add the totals and the reports of another run, e.g. of the next input directory
+    pub fn merge(&mut self, other: RunSummary) {
+        self.files_scanned += other.files_scanned;
+        self.files_matched += other.files_matched;
+        self.files_timed_out += other.files_timed_out;
+        self.files_protected += other.files_protected;
+        self.files_outside_roots += other.files_outside_roots;
+        self.files_missing_data += other.files_missing_data;
+        self.files_missing_keyword += other.files_missing_keyword;
+        self.files_skipped_no_match += other.files_skipped_no_match;
+        self.files_already_migrated += other.files_already_migrated;
+        self.files_failed += other.files_failed;
+        self.fields_rewritten += other.fields_rewritten;
+        self.bytes_written += other.bytes_written;
+        self.data_moved += other.data_moved;
+        self.files.extend(other.files);
+    }
+
+    fn count(&mut self, file: &FileReport) {
+        self.files_scanned += 1;
+        self.files_matched += usize::from(file.matched);
+        self.files_timed_out += usize::from(file.timed_out);
+        self.files_protected += usize::from(file.protected);
+        self.files_outside_roots += usize::from(!file.outside_roots.is_empty());
+        self.files_missing_data += usize::from(!file.missing_data.is_empty());
+        self.files_missing_keyword += usize::from(file.missing_keyword);
+        self.files_skipped_no_match += usize::from(file.skipped_no_match);
+        self.files_already_migrated += usize::from(file.already_migrated);
+        self.files_failed += usize::from(file.error.is_some());
+        self.fields_rewritten += file.modifications.len();
+        self.bytes_written += file.bytes_written;
+        self.data_moved += file.data_moved;
+    }
+
+    /// The same summary for a run that took `elapsed`
+    pub fn timed(mut self, elapsed: Duration) -> Self {
+        self.elapsed_ms = elapsed.as_millis() as u64;
+        self.files_per_second = if elapsed.is_zero() { 0.0 } else { self.files_scanned as f64 / elapsed.as_secs_f64() };
+        self
+    }
+}
+
+/// JSON Schema of the reports above, keep in sync with the struct definitions. Objects stay open to new fields.
+pub fn json_schema() -> serde_json::Value {
+    json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "$id": format!("https://github.com/sontran223/reptool/schema/report-v{}.json", REPORT_VERSION),
+        "title": "RunSummary",
+        "$ref": "#/$defs/RunSummary",
+        "$defs": {
+            "RunSummary": {
+                "type": "object",
+                "required": ["version", "files_scanned", "files_matched", "files_timed_out", "files_protected", "files_outside_roots", "files_missing_data", "files_missing_keyword", "files_skipped_no_match", "files_already_migrated", "files_failed", "fields_rewritten", "bytes_written", "data_moved", "elapsed_ms", "files_per_second", "files"],
+                "properties": {
+                    "version": { "const": REPORT_VERSION },
+                    "files_scanned": { "type": "integer", "minimum": 0 },
+                    "files_matched": { "type": "integer", "minimum": 0 },
+                    "files_timed_out": { "type": "integer", "minimum": 0 },
+                    "files_protected": { "type": "integer", "minimum": 0 },
+                    "files_outside_roots": { "type": "integer", "minimum": 0 },
+                    "files_missing_data": { "type": "integer", "minimum": 0 },
+                    "files_missing_keyword": { "type": "integer", "minimum": 0 },
+                    "files_skipped_no_match": { "type": "integer", "minimum": 0 },
+                    "files_already_migrated": { "type": "integer", "minimum": 0 },
+                    "files_failed": { "type": "integer", "minimum": 0 },
+                    "fields_rewritten": { "type": "integer", "minimum": 0 },
+                    "bytes_written": { "type": "integer", "minimum": 0 },
+                    "data_moved": { "type": "integer", "minimum": 0 },
+                    "elapsed_ms": { "type": "integer", "minimum": 0 },
+                    "files_per_second": { "type": "number", "minimum": 0 },
+                    "files": { "type": "array", "items": { "$ref": "#/$defs/FileReport" } }
+                }
+            },
+            "FileReport": {
+                "type": "object",
+                "required": ["file", "matched", "timed_out", "protected", "archived_to", "modifications", "outside_roots", "missing_data", "missing_keyword", "skipped_no_match", "already_migrated", "error", "bytes_written", "data_moved"],
+                "properties": {
+                    "file": { "type": "string" },
+                    "matched": { "type": "boolean" },
+                    "timed_out": { "type": "boolean" },
+                    "protected": { "type": "boolean" },
+                    "archived_to": { "type": ["string", "null"] },
+                    "modifications": { "type": "array", "items": { "$ref": "#/$defs/ModifyReport" } },
+                    "outside_roots": { "type": "array", "items": { "type": "string" } },
+                    "missing_data": { "type": "array", "items": { "type": "string" } },
+                    "missing_keyword": { "type": "boolean" },
+                    "skipped_no_match": { "type": "boolean" },
+                    "already_migrated": { "type": "boolean" },
+                    "error": { "type": ["string", "null"] },
+                    "bytes_written": { "type": "integer", "minimum": 0 },
+                    "data_moved": { "type": "integer", "minimum": 0 }
+                }
+            },
+            "ModifyReport": {
+                "type": "object",
+                "required": ["key", "old_value", "new_value", "old_length", "new_length"],
+                "properties": {
+                    "key": { "type": "string" },
+                    "old_value": { "type": "string" },
+                    "new_value": { "type": "string" },
+                    "old_length": { "type": "integer", "minimum": 0 },
+                    "new_length": { "type": "integer", "minimum": 0 }
+                }
+            }
+        }
+    })
+}
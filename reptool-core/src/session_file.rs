@@ -0,0 +1,903 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use memchr::memmem;
+use regex::bytes::Regex;
+use unicode_normalization::UnicodeNormalization;
+
+use crate::bencode::{self, Value};
+use crate::encoding::Encoding;
+use crate::file::{read_buffered, write_atomic, AtomicWriter};
+use crate::report::ModifyReport;
+
+/// One search and replace pair
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rule {
+    pub find: String,
+    pub replace: String,
+    /// Match `find` whatever its case, as with `ReplaceOptions::ignore_case` for this rule only
+    pub ignore_case: bool,
+}
+
+impl Rule {
+    /// Parse `OLD=NEW`, split at the first `=`, or a sed expression `s#OLD#NEW#FLAGS` with any delimiter but `=`
+    pub fn parse(mapping: &str) -> Result<Self> {
+        if let Some(rule) = Self::parse_sed(mapping)? {
+            return Ok(rule);
+        }
+        let Some((find, replace)) = mapping.split_once('=') else {
+            bail!("Missing = in mapping {:?}, expected OLD=NEW or s#OLD#NEW#", mapping);
+        };
+        if find.is_empty() {
+            bail!("Empty search string in mapping {:?}", mapping);
+        }
+        Ok(Rule { find: find.to_owned(), replace: replace.to_owned(), ignore_case: false })
+    }
+
+    /// `None` when `expression` is not shaped like `s#OLD#NEW#FLAGS`. A backslash before the delimiter makes it part
+    /// of OLD or NEW. The flags are `i` to ignore the case and `g`, accepted for sed scripts, every occurrence being
+    /// replaced unless --first-only.
+    fn parse_sed(expression: &str) -> Result<Option<Self>> {
+        let mut chars = expression.chars();
+        let (Some('s'), Some(delimiter)) = (chars.next(), chars.next()) else {
+            return Ok(None);
+        };
+        if delimiter.is_alphanumeric() || delimiter.is_whitespace() || matches!(delimiter, '\\' | '=') {
+            return Ok(None);
+        }
+
+        let mut parts = vec![String::new()];
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' if chars.clone().next() == Some(delimiter) => {
+                    parts.last_mut().expect("Never empty").push(delimiter);
+                    chars.next();
+                }
+                c if c == delimiter => parts.push(String::new()),
+                c => parts.last_mut().expect("Never empty").push(c),
+            }
+        }
+        let Ok([find, replace, flags]) = <[String; 3]>::try_from(parts) else {
+            return Ok(None);
+        };
+        if find.is_empty() {
+            return Ok(None);
+        }
+
+        let mut ignore_case = false;
+        for flag in flags.chars() {
+            match flag {
+                'i' | 'I' => ignore_case = true,
+                'g' => {}
+                _ => bail!("Unknown flag {:?} in expression {:?}, expected i or g", flag, expression),
+            }
+        }
+        Ok(Some(Rule { find, replace, ignore_case }))
+    }
+}
+
+/// Where the search string may match in a value
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+pub enum Anchor {
+    /// Anywhere in the value, as a plain substring
+    #[default]
+    Anywhere,
+    /// At the start of the value and ending on a path component, `/data` matches neither `/mnt/data` nor `/database`
+    Prefix,
+}
+
+/// Path separators the rewritten values are converted to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+pub enum PathStyle {
+    /// `/` separators, a drive prefix like `D:` becomes `/mnt/d`
+    Unix,
+    /// `\` separators, a prefix like `/mnt/d` becomes `D:`
+    Windows,
+    /// The style of the replacement string of the rule that matched, values are left alone when it has no separator
+    Auto,
+}
+
+impl PathStyle {
+    /// `value` with the separators and drive prefix of this style, `replace` is the replacement string of the rule
+    /// that matched
+    pub fn convert(self, value: &[u8], replace: &[u8]) -> Vec<u8> {
+        let style = match self {
+            PathStyle::Auto if has_drive(replace) || replace.contains(&b'\\') => PathStyle::Windows,
+            PathStyle::Auto if replace.contains(&b'/') => PathStyle::Unix,
+            PathStyle::Auto => return value.to_vec(),
+            style => style,
+        };
+
+        let mut converted = Vec::with_capacity(value.len() + 4);
+        match style {
+            PathStyle::Windows => {
+                let rest = match value {
+                    [b'/', b'm', b'n', b't', b'/', drive, rest @ ..] if drive.is_ascii_alphabetic() && matches!(rest.first(), None | Some(b'/')) => {
+                        converted.extend_from_slice(&[drive.to_ascii_uppercase(), b':']);
+                        rest
+                    }
+                    _ => value,
+                };
+                converted.extend(rest.iter().map(|&byte| if byte == b'/' { b'\\' } else { byte }));
+            }
+            _ => {
+                let rest = if has_drive(value) {
+                    converted.extend_from_slice(b"/mnt/");
+                    converted.push(value[0].to_ascii_lowercase());
+                    &value[2..]
+                } else {
+                    value
+                };
+                converted.extend(rest.iter().map(|&byte| if byte == b'\\' { b'/' } else { byte }));
+            }
+        }
+        converted
+    }
+}
+
+/// `value` with duplicate and trailing slashes removed and `.` and `..` resolved, `..` never goes above the root
+fn normalize_path(value: &[u8]) -> Vec<u8> {
+    if value.is_empty() {
+        return Vec::new();
+    }
+    let is_absolute = value[0] == b'/';
+    let mut components: Vec<&[u8]> = Vec::new();
+    for component in value.split(|&byte| byte == b'/') {
+        match component {
+            b"" | b"." => {}
+            b".." if components.last().is_some_and(|last| *last != b"..") => {
+                components.pop();
+            }
+            b".." if is_absolute => {}
+            component => components.push(component),
+        }
+    }
+
+    let mut normalized = if is_absolute { b"/".to_vec() } else { Vec::new() };
+    normalized.extend(components.join(&b'/'));
+    if normalized.is_empty() {
+        normalized.push(b'.');
+    }
+    normalized
+}
+
+/// Whether the backslashes of `value` are escaped, the leading pair of a UNC path like `\\nas\share` is not an escape
+fn is_escaped(value: &[u8]) -> bool {
+    value.get(2..).is_some_and(|rest| rest.windows(2).any(|pair| pair == b"\\\\"))
+}
+
+/// `value` with every run of backslashes made a single one, but the two leading ones of an escaped UNC path
+fn collapse_backslashes(value: &[u8]) -> Vec<u8> {
+    let mut collapsed = value.to_vec();
+    collapsed.dedup_by(|byte, previous| *byte == b'\\' && *previous == b'\\');
+    if value.starts_with(b"\\\\\\\\") {
+        collapsed.insert(0, b'\\');
+    }
+    collapsed
+}
+
+/// Whether `value` starts with a drive like `D:` or `D:\`
+fn has_drive(value: &[u8]) -> bool {
+    matches!(value, [drive, b':', rest @ ..] if drive.is_ascii_alphabetic() && matches!(rest.first(), None | Some(b'\\' | b'/')))
+}
+
+/// Clean up of the values, on top of the rules or on its own
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Normalize {
+    /// Also compose the characters of UTF-8 values (NFC), so an `é` typed on macOS matches the one typed elsewhere
+    pub nfc: bool,
+}
+
+impl Normalize {
+    /// Collapse duplicate slashes, strip trailing slashes and resolve `.` and `..` without looking at the disk
+    pub fn apply(self, value: &[u8], encoding: Encoding) -> Vec<u8> {
+        let mut normalized = normalize_path(value);
+        if let (true, Encoding::Utf8, Ok(text)) = (self.nfc, encoding, std::str::from_utf8(&normalized)) {
+            normalized = text.nfc().collect::<String>().into_bytes();
+        }
+        normalized
+    }
+}
+
+/// How the matching values are rewritten, the same for every file of a run
+#[derive(Debug, Clone, Copy)]
+pub struct ReplaceOptions<'a> {
+    /// Tried in order, the first rule found in a value is applied to it
+    pub rules: &'a [Rule],
+    /// The search strings of the rules as regular expressions, one per rule, empty to search them as plain strings.
+    /// `$1` or `${name}` in a replace string then insert a capture group.
+    pub patterns: &'a [Regex],
+    pub encoding: Encoding,
+    /// Values outside these roots are left alone, empty means everywhere
+    pub only_from: &'a [String],
+    /// Only replace within the last component of the path
+    pub base_only: bool,
+    /// Replace every occurrence of the matching rule instead of only the first one
+    pub all_occurrences: bool,
+    pub anchor: Anchor,
+    /// Match the search strings whatever the case of their ASCII letters, all letters with regular expressions
+    pub ignore_case: bool,
+    /// Convert the rewritten values to these path separators
+    pub path_style: Option<PathStyle>,
+    /// Clean up every value, also those no rule matches
+    pub normalize: Option<Normalize>,
+}
+
+impl ReplaceOptions<'_> {
+    /// The value with the first matching rule applied, `None` when no rule matches
+    pub fn apply(&self, value: &str) -> Option<String> {
+        let new_value = self.apply_bytes(&self.encoding.encode(value).ok()?)?;
+        self.encoding.decode(&new_value).ok()
+    }
+
+    /// Whether a file of `content` may have a value these options change or already changed, a scan for the search
+    /// and replace strings cheaper than decoding it. Normalizing, converting the path style, a regular expression or
+    /// ignoring the case may change any value, with them every file may.
+    pub fn may_match(&self, content: &[u8]) -> bool {
+        let ignores_case = self.ignore_case || self.rules.iter().any(|rule| rule.ignore_case);
+        if self.normalize.is_some() || self.path_style.is_some() || !self.patterns.is_empty() || ignores_case {
+            return true;
+        }
+        self.rules.iter()
+            .flat_map(|rule| [&rule.find, &rule.replace])
+            .filter_map(|string| self.encoding.encode(string).ok())
+            .any(|string| !string.is_empty() && memmem::find(content, &string).is_some())
+    }
+
+    /// Whether `value` already holds what a rule writes: it is the replace string of a rule or below it, the search
+    /// string of that rule is absent or only found within that replace string, and no rule before that one would
+    /// rewrite it. Running the same rules again leaves such values alone, also when the replace string contains the
+    /// search string, while a replace string that is a parent of its search string still rewrites. Never known with
+    /// regular expressions, their replace strings depend on the match.
+    pub fn is_migrated_bytes(&self, value: &[u8]) -> bool {
+        if !self.patterns.is_empty() {
+            return false;
+        }
+        let base = if self.base_only { split_base(value).1 } else { value };
+        for rule in self.rules {
+            let (Ok(find), Ok(replace)) = (self.encoding.encode(&rule.find), self.encoding.encode(&rule.replace)) else {
+                continue;
+            };
+            let ignore_case = self.ignore_case || rule.ignore_case;
+            let finds = self.finds(base, &find, ignore_case);
+            let is_under_replace = !replace.is_empty() && strip_path_prefix(base, &replace, ignore_case).is_some();
+            if is_under_replace && (!finds || self.finds_only_within(base, &find, replace.len(), ignore_case)) {
+                return true;
+            }
+            if finds {
+                return false;
+            }
+        }
+        false
+    }
+
+    /// Same as `apply` on the raw bytes, the bytes around the matches are kept as they are even when
+    /// they are not valid in the encoding
+    pub fn apply_bytes(&self, value: &[u8]) -> Option<Vec<u8>> {
+        let rewritten = if self.is_migrated_bytes(value) { None } else { self.rewrite(value) };
+        let Some(normalize) = self.normalize else {
+            return rewritten;
+        };
+        let normalized = normalize.apply(rewritten.as_deref().unwrap_or(value), self.encoding);
+        (rewritten.is_some() || normalized != value).then_some(normalized)
+    }
+
+    /// The value with the first matching rule and the path style applied, `None` when no rule matches
+    fn rewrite(&self, value: &[u8]) -> Option<Vec<u8>> {
+        // Some exports escape every backslash, with a path style they are matched and written as single separators
+        let unescaped;
+        let value = match self.path_style {
+            Some(_) if is_escaped(value) => {
+                unescaped = collapse_backslashes(value);
+                &unescaped[..]
+            }
+            _ => value,
+        };
+        let (parent, base) = if self.base_only { split_base(value) } else { (&value[..0], value) };
+        let (new_base, replace) = if self.patterns.is_empty() { self.replace_string(base)? } else { self.replace_pattern(base)? };
+
+        let mut new_value = parent.to_vec();
+        new_value.extend(new_base);
+        match self.path_style {
+            Some(path_style) => Some(path_style.convert(&new_value, &replace)),
+            None => Some(new_value),
+        }
+    }
+
+    /// `value` with the first rule found in it applied, and the replace string of that rule
+    fn replace_string(&self, value: &[u8]) -> Option<(Vec<u8>, Vec<u8>)> {
+        let (find, replace, ignore_case) = self.rules.iter()
+            .filter_map(|rule| Some((self.encoding.encode(&rule.find).ok()?, self.encoding.encode(&rule.replace).ok()?, self.ignore_case || rule.ignore_case)))
+            .find(|(find, _, ignore_case)| self.finds(value, find, *ignore_case))?;
+
+        let new_value = match self.anchor {
+            Anchor::Anywhere => {
+                let count = if self.all_occurrences { usize::MAX } else { 1 };
+                replace_bytes(value, &find, &replace, count, ignore_case)
+            }
+            Anchor::Prefix => [replace.as_slice(), strip_path_prefix(value, &find, ignore_case)?].concat(),
+        };
+        Some((new_value, replace))
+    }
+
+    /// Same as `replace_string` with the regular expressions, the capture groups expanded in the replace string
+    fn replace_pattern(&self, value: &[u8]) -> Option<(Vec<u8>, Vec<u8>)> {
+        let (pattern, replace) = self.rules.iter().zip(self.patterns)
+            .filter_map(|(rule, pattern)| Some((pattern, self.encoding.encode(&rule.replace).ok()?)))
+            .find(|(pattern, _)| self.finds_pattern(value, pattern))?;
+
+        let new_value = match self.anchor {
+            Anchor::Anywhere => {
+                let limit = if self.all_occurrences { 0 } else { 1 };
+                pattern.replacen(value, limit, replace.as_slice()).into_owned()
+            }
+            Anchor::Prefix => {
+                let captures = pattern.captures(value)?;
+                let mut new_value = Vec::new();
+                captures.expand(&replace, &mut new_value);
+                new_value.extend_from_slice(&value[captures.get(0)?.end()..]);
+                new_value
+            }
+        };
+        Some((new_value, replace))
+    }
+
+    fn finds(&self, value: &[u8], find: &[u8], ignore_case: bool) -> bool {
+        match self.anchor {
+            Anchor::Anywhere => find_bytes(value, find, ignore_case).is_some(),
+            Anchor::Prefix => strip_path_prefix(value, find, ignore_case).is_some(),
+        }
+    }
+
+    /// Whether every match of `find` in `value` ends within its first `length` bytes
+    fn finds_only_within(&self, value: &[u8], find: &[u8], length: usize, ignore_case: bool) -> bool {
+        match self.anchor {
+            Anchor::Anywhere => (0..=value.len().saturating_sub(find.len()))
+                .filter(|&index| value.get(index..index + find.len()).is_some_and(|window| bytes_eq(window, find, ignore_case)))
+                .all(|index| index + find.len() <= length),
+            Anchor::Prefix => find.len() <= length,
+        }
+    }
+
+    fn finds_pattern(&self, value: &[u8], pattern: &Regex) -> bool {
+        match self.anchor {
+            Anchor::Anywhere => pattern.is_match(value),
+            // The leftmost match, at the start when there is one there
+            Anchor::Prefix => pattern.find(value).is_some_and(|found| found.start() == 0 && strip_path_prefix(value, found.as_bytes(), false).is_some()),
+        }
+    }
+
+    /// Whether `value` is one of the `only_from` roots or below one of them, whole path components only
+    pub fn is_allowed(&self, value: &str) -> bool {
+        self.encoding.encode(value).is_ok_and(|value| self.is_allowed_bytes(&value))
+    }
+
+    pub fn is_allowed_bytes(&self, value: &[u8]) -> bool {
+        self.only_from.is_empty() || self.only_from.iter().any(|root| {
+            let Ok(root) = self.encoding.encode(root.trim_end_matches('/')) else {
+                return false;
+            };
+            strip_path_prefix(value, &root, false).is_some()
+        })
+    }
+}
+
+/// Outcome of `SessionFile::replace`
+#[derive(Debug, Clone, Default)]
+pub struct ReplaceReport {
+    /// Concrete keys the keyword resolved to
+    pub keys: Vec<String>,
+    pub modifications: Vec<ModifyReport>,
+    /// Matching values left alone because they are not under an `only_from` root
+    pub outside_roots: Vec<String>,
+    /// Values left alone because they already hold what the rules write
+    pub already_migrated: Vec<String>,
+}
+
+/// A decoded session file, changes stay in memory until saved
+#[derive(Debug, Clone)]
+pub struct SessionFile {
+    path: PathBuf,
+    value: Value,
+}
+
+impl SessionFile {
+    pub fn open(path: impl Into<PathBuf>, io_buffer: usize) -> Result<Self> {
+        let path = path.into();
+        let content = read_buffered(&path, io_buffer).context("Failed to read file")?;
+        Self::decode(path, &content)
+    }
+
+    /// The session file of `path` from its `content` read already
+    pub fn decode(path: impl Into<PathBuf>, content: &[u8]) -> Result<Self> {
+        let value = bencode::decode(content).context("Failed to decode file")?;
+        Ok(SessionFile { path: path.into(), value })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn value(&self) -> &Value {
+        &self.value
+    }
+
+    pub fn value_mut(&mut self) -> &mut Value {
+        &mut self.value
+    }
+
+    /// The top level value of `key`
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.value.as_dict()?.get(key.as_bytes())
+    }
+
+    /// Concrete top level keys matching `keyword` (a trailing * matches by prefix) whose value is a string
+    pub fn keys_matching(&self, keyword: &str) -> Result<Vec<String>> {
+        let Value::Dict(dict) = &self.value else {
+            bail!("Top level value is not a dictionary");
+        };
+
+        let is_match = |key: &[u8]| match keyword.strip_suffix('*') {
+            Some(prefix) => key.starts_with(prefix.as_bytes()),
+            None => key == keyword.as_bytes(),
+        };
+        Ok(dict.iter()
+            .filter(|(key, item)| is_match(key) && matches!(item, Value::Bytes(_)))
+            .map(|(key, _)| String::from_utf8_lossy(key).into_owned())
+            .collect())
+    }
+
+    /// Union of `keys_matching` over several keywords, in keyword order and without duplicates
+    pub fn keys_matching_any(&self, keywords: &[String]) -> Result<Vec<String>> {
+        let mut keys: Vec<String> = Vec::new();
+        for keyword in keywords {
+            for key in self.keys_matching(keyword)? {
+                if !keys.contains(&key) {
+                    keys.push(key);
+                }
+            }
+        }
+
+        Ok(keys)
+    }
+
+    /// Set the top level string `key` to `value`, `None` when it already holds the value
+    pub fn set(&mut self, key: &str, value: &str, encoding: Encoding) -> Result<Option<ModifyReport>> {
+        let value_bytes = encoding.encode(value)?;
+        let Value::Dict(dict) = &mut self.value else {
+            bail!("Top level value is not a dictionary");
+        };
+        let Some(Value::Bytes(old_value)) = dict.get_mut(key.as_bytes()) else {
+            bail!("Field {:?} is missing or not a string", key);
+        };
+        if *old_value == value_bytes {
+            return Ok(None);
+        }
+
+        let modification = ModifyReport {
+            key: key.to_owned(),
+            old_value: to_text(old_value, encoding),
+            new_value: value.to_owned(),
+            old_length: old_value.len(),
+            new_length: value_bytes.len(),
+        };
+        *old_value = value_bytes;
+
+        Ok(Some(modification))
+    }
+
+    /// Set the top level `key` to a string or integer, adding the key when missing, `None` when it already holds the value.
+    /// A key holding a list or a dictionary is never replaced.
+    pub fn insert(&mut self, key: &str, value: Value, encoding: Encoding) -> Result<Option<ModifyReport>> {
+        if matches!(value, Value::List(_) | Value::Dict(_)) {
+            bail!("Only strings and integers can be set, not {:?}", key);
+        }
+        let Value::Dict(dict) = &mut self.value else {
+            bail!("Top level value is not a dictionary");
+        };
+        let old_value = dict.get(key.as_bytes());
+        if old_value == Some(&value) {
+            return Ok(None);
+        }
+        if matches!(old_value, Some(Value::List(_) | Value::Dict(_))) {
+            bail!("Field {:?} is a list or a dictionary", key);
+        }
+
+        let old_text = old_value.map(|old_value| scalar_text(old_value, encoding)).unwrap_or_default();
+        let new_text = scalar_text(&value, encoding);
+        let modification = ModifyReport {
+            key: key.to_owned(),
+            old_length: old_value.map_or(0, scalar_length),
+            new_length: scalar_length(&value),
+            old_value: old_text,
+            new_value: new_text,
+        };
+        dict.insert(key.as_bytes().to_vec(), value);
+
+        Ok(Some(modification))
+    }
+
+    /// Apply the rules to every string field matching one of `keywords`, each field is rewritten at most once.
+    /// Matching is done on the encoded bytes so values that are not valid in the encoding are handled too.
+    pub fn replace(&mut self, keywords: &[String], options: &ReplaceOptions) -> Result<ReplaceReport> {
+        let encoding = options.encoding;
+        for rule in options.rules {
+            encoding.encode(&rule.find)?;
+            encoding.encode(&rule.replace)?;
+        }
+        let mut report = ReplaceReport { keys: self.keys_matching_any(keywords)?, ..Default::default() };
+
+        let Value::Dict(dict) = &mut self.value else {
+            bail!("Top level value is not a dictionary");
+        };
+        for key in &report.keys {
+            let Some(Value::Bytes(bytes)) = dict.get_mut(key.as_bytes()) else {
+                continue;
+            };
+            let Some(new_bytes) = options.apply_bytes(bytes) else {
+                if options.is_migrated_bytes(bytes) {
+                    report.already_migrated.push(to_text(bytes, encoding));
+                }
+                continue;
+            };
+            if !options.is_allowed_bytes(bytes) {
+                report.outside_roots.push(to_text(bytes, encoding));
+                continue;
+            }
+
+            // The length prefix is recomputed by the encoder from the new bytes
+            report.modifications.push(ModifyReport {
+                key: key.clone(),
+                old_value: to_text(bytes, encoding),
+                new_value: to_text(&new_bytes, encoding),
+                old_length: bytes.len(),
+                new_length: new_bytes.len(),
+            });
+            *bytes = new_bytes;
+        }
+
+        Ok(report)
+    }
+
+    /// Write back to the file it was read from
+    pub fn save(&self, io_buffer: usize) -> Result<()> {
+        self.save_as(&self.path, io_buffer)
+    }
+
+    pub fn save_as(&self, path: &Path, io_buffer: usize) -> Result<()> {
+        write_atomic(path, &bencode::encode(&self.value), io_buffer)
+    }
+
+    /// Write back to the file it was read from once the written bytes check out, see `save_checked_as`
+    pub fn save_checked(&self, modifications: &[ModifyReport], encoding: Encoding, io_buffer: usize) -> Result<u64> {
+        self.save_checked_as(&self.path, modifications, encoding, io_buffer)
+    }
+
+    /// Write to `path` and read the written bytes back before replacing the file: they must have the length of the
+    /// encoded value, decode to the value and hold the new value of every one of `modifications`. The file is left
+    /// as it was when a check fails. Gives the number of bytes written.
+    pub fn save_checked_as(&self, path: &Path, modifications: &[ModifyReport], encoding: Encoding, io_buffer: usize) -> Result<u64> {
+        let encoded = bencode::encode(&self.value);
+        let mut writer = AtomicWriter::create(path, io_buffer)?;
+        writer.write_all(&encoded)?;
+        let written = writer.read_back(io_buffer)?;
+        self.check_written(&written, encoded.len(), modifications, encoding)
+            .with_context(|| format!("Refused to write {:?}", path))?;
+        writer.commit()?;
+
+        Ok(encoded.len() as u64)
+    }
+
+    fn check_written(&self, written: &[u8], length: usize, modifications: &[ModifyReport], encoding: Encoding) -> Result<()> {
+        if written.len() != length {
+            bail!("Wrote {} bytes instead of {}", written.len(), length);
+        }
+        let decoded = bencode::decode(written).context("Written bytes do not decode")?;
+        if decoded != self.value {
+            bail!("Written bytes decode to another value");
+        }
+        let Value::Dict(dict) = &decoded else {
+            bail!("Top level value is not a dictionary");
+        };
+        for modification in modifications {
+            match dict.get(modification.key.as_bytes()) {
+                Some(value) if scalar_text(value, encoding) == modification.new_value && scalar_length(value) == modification.new_length => {}
+                Some(value) => bail!("Field {:?} holds {:?} instead of {:?}", modification.key, scalar_text(value, encoding), modification.new_value),
+                None => bail!("Field {:?} is missing", modification.key),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The value as text for reports, invalid bytes are shown as U+FFFD
+fn to_text(bytes: &[u8], encoding: Encoding) -> String {
+    encoding.decode(bytes).unwrap_or_else(|_| String::from_utf8_lossy(bytes).into_owned())
+}
+
+/// A string or integer as text for reports
+fn scalar_text(value: &Value, encoding: Encoding) -> String {
+    match value {
+        Value::Bytes(bytes) => to_text(bytes, encoding),
+        Value::Integer(integer) => integer.to_string(),
+        Value::List(_) | Value::Dict(_) => String::new(),
+    }
+}
+
+/// The length of a string as written in its prefix, the digits of an integer
+fn scalar_length(value: &Value) -> usize {
+    match value {
+        Value::Bytes(bytes) => bytes.len(),
+        Value::Integer(integer) => integer.to_string().len(),
+        Value::List(_) | Value::Dict(_) => 0,
+    }
+}
+
+/// Split a path before its last component, a trailing slash stays with the component
+fn split_base(path: &[u8]) -> (&[u8], &[u8]) {
+    let trimmed_len = path.len() - path.iter().rev().take_while(|&&byte| byte == b'/').count();
+    match path[..trimmed_len].iter().rposition(|&byte| byte == b'/') {
+        Some(index) => path.split_at(index + 1),
+        None => (&path[..0], path),
+    }
+}
+
+/// What follows `prefix` when `value` starts with it on a path component boundary
+fn strip_path_prefix<'a>(value: &'a [u8], prefix: &[u8], ignore_case: bool) -> Option<&'a [u8]> {
+    let head = value.get(..prefix.len())?;
+    if !bytes_eq(head, prefix, ignore_case) {
+        return None;
+    }
+    let rest = &value[prefix.len()..];
+    let is_boundary = prefix.ends_with(b"/") || rest.first().is_none_or(|&byte| byte == b'/');
+    is_boundary.then_some(rest)
+}
+
+/// Position of the first `needle` in `haystack`, an empty needle never matches
+fn find_bytes(haystack: &[u8], needle: &[u8], ignore_case: bool) -> Option<usize> {
+    if needle.is_empty() {
+        return None;
+    }
+    haystack.windows(needle.len()).position(|window| bytes_eq(window, needle, ignore_case))
+}
+
+fn bytes_eq(left: &[u8], right: &[u8], ignore_case: bool) -> bool {
+    if ignore_case { left.eq_ignore_ascii_case(right) } else { left == right }
+}
+
+/// `haystack` with the first `count` occurrences of `find` replaced
+fn replace_bytes(haystack: &[u8], find: &[u8], replace: &[u8], count: usize, ignore_case: bool) -> Vec<u8> {
+    let mut replaced = Vec::with_capacity(haystack.len());
+    let mut rest = haystack;
+    for _ in 0..count {
+        let Some(index) = find_bytes(rest, find, ignore_case) else {
+            break;
+        };
+        replaced.extend_from_slice(&rest[..index]);
+        replaced.extend_from_slice(replace);
+        rest = &rest[index + find.len()..];
+    }
+    replaced.extend_from_slice(rest);
+
+    replaced
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(rules: &[Rule]) -> ReplaceOptions<'_> {
+        ReplaceOptions {
+            rules,
+            patterns: &[],
+            encoding: Encoding::Utf8,
+            only_from: &[],
+            base_only: false,
+            all_occurrences: false,
+            anchor: Anchor::Anywhere,
+            ignore_case: false,
+            path_style: None,
+            normalize: None,
+        }
+    }
+
+    fn rule(find: &str, replace: &str) -> Rule {
+        Rule { find: find.to_owned(), replace: replace.to_owned(), ignore_case: false }
+    }
+
+    fn session_file(content: &[u8]) -> SessionFile {
+        SessionFile::decode("test.torrent.rtorrent", content).unwrap()
+    }
+
+    #[test]
+    fn replace_only_touches_the_keyword_field() {
+        // The bytes of the directory value are also in loaded_file and in a custom field
+        let mut file = session_file(b"d7:custom210:/mnt/data/9:directory10:/mnt/data/11:loaded_file19:/mnt/data/x.torrente");
+        let rules = [rule("/mnt/data/", "/srv/")];
+        let report = file.replace(&["directory".to_owned()], &options(&rules)).unwrap();
+
+        assert_eq!(report.modifications.len(), 1);
+        assert_eq!(report.modifications[0].key, "directory");
+        assert_eq!(bencode::encode(file.value()), b"d7:custom210:/mnt/data/9:directory5:/srv/11:loaded_file19:/mnt/data/x.torrente");
+    }
+
+    #[test]
+    fn replace_resolves_wildcard_keywords() {
+        let mut file = session_file(b"d9:directory5:/old/13:directory_alt5:/old/8:priorityi1ee");
+        let rules = [rule("/old/", "/new/")];
+        let report = file.replace(&["directory*".to_owned(), "priority".to_owned()], &options(&rules)).unwrap();
+
+        assert_eq!(report.keys, ["directory", "directory_alt"]);
+        assert_eq!(report.modifications.len(), 2);
+    }
+
+    #[test]
+    fn prefix_anchor_needs_a_whole_component() {
+        let rules = [rule("/mnt/data", "/srv")];
+        let prefix = ReplaceOptions { anchor: Anchor::Prefix, ..options(&rules) };
+        for (value, expected) in [("/mnt/data/x", Some("/srv/x")), ("/mnt/data", Some("/srv")), ("/mnt/database", None), ("/backup/mnt/data", None)] {
+            assert_eq!(prefix.apply(value).as_deref(), expected, "{}", value);
+        }
+    }
+
+    #[test]
+    fn only_from_leaves_other_roots_alone() {
+        let mut file = session_file(b"d9:directory11:/other/datae");
+        let rules = [rule("data", "films")];
+        let only_from = ["/mnt".to_owned()];
+        let report = file.replace(&["directory".to_owned()], &ReplaceOptions { only_from: &only_from, ..options(&rules) }).unwrap();
+
+        assert!(report.modifications.is_empty());
+        assert_eq!(report.outside_roots, ["/other/data"]);
+    }
+
+    #[test]
+    fn first_matching_rule_wins() {
+        let rules = [rule("/a/", "/first/"), rule("/a/b/", "/second/")];
+        assert_eq!(options(&rules).apply("/a/b/c").as_deref(), Some("/first/b/c"));
+        assert_eq!(options(&rules).apply("/z/").as_deref(), None);
+    }
+
+    #[test]
+    fn base_only_leaves_the_parent_components_alone() {
+        // movies is in a parent directory and in the last component
+        let mut file = session_file(b"d9:directory24:/mnt/movies/movies-2024/e");
+        let rules = [rule("movies", "films")];
+        let report = file.replace(&["directory".to_owned()], &ReplaceOptions { base_only: true, ..options(&rules) }).unwrap();
+
+        assert_eq!(report.modifications[0].new_value, "/mnt/movies/films-2024/");
+        assert_eq!(split_base(b"/mnt/movies/movies-2024/"), (&b"/mnt/movies/"[..], &b"movies-2024/"[..]));
+
+        // Found only in a parent component, the value is left alone
+        let mut file = session_file(b"d9:directory20:/mnt/movies/2024-hd/e");
+        let report = file.replace(&["directory".to_owned()], &ReplaceOptions { base_only: true, ..options(&rules) }).unwrap();
+        assert!(report.modifications.is_empty());
+    }
+
+    #[test]
+    fn replace_with_a_parent_directory_is_not_migrated() {
+        let mut file = session_file(b"d9:directory10:/mnt/old/xe");
+        let rules = [rule("/mnt/old", "/mnt")];
+        let report = file.replace(&["directory".to_owned()], &options(&rules)).unwrap();
+
+        assert!(report.already_migrated.is_empty());
+        assert_eq!(report.modifications[0].new_value, "/mnt/x");
+    }
+
+    #[test]
+    fn same_rules_run_twice_change_nothing_the_second_time() {
+        for (find, replace) in [("/data", "/data/new"), ("/mnt/old", "/mnt"), ("/a", "/b")] {
+            let mut file = session_file(b"d9:directory11:/data/moviee");
+            let mut file_old = session_file(b"d9:directory10:/mnt/old/xe");
+            let rules = [rule(find, replace)];
+            let keywords = ["directory".to_owned()];
+            for file in [&mut file, &mut file_old] {
+                let first = file.replace(&keywords, &options(&rules)).unwrap();
+                let second = file.replace(&keywords, &options(&rules)).unwrap();
+                assert!(second.modifications.is_empty(), "{} -> {} rewrote {:?} again", find, replace, second.modifications);
+                assert_eq!(second.already_migrated.len(), first.modifications.len());
+            }
+        }
+    }
+
+    #[test]
+    fn parse_reads_pairs_and_sed_expressions() {
+        assert_eq!(Rule::parse("/old=/new=x").unwrap(), rule("/old", "/new=x"));
+        assert_eq!(Rule::parse("s#/old#/new#").unwrap(), rule("/old", "/new"));
+        assert_eq!(Rule::parse("s|/a=b|/c|g").unwrap(), rule("/a=b", "/c"));
+        assert_eq!(Rule::parse("s#/Old#/new#gi").unwrap(), Rule { ignore_case: true, ..rule("/Old", "/new") });
+        // An escaped delimiter is part of the string
+        assert_eq!(Rule::parse(r"s#/a\#b#/c#").unwrap(), rule("/a#b", "/c"));
+        // Not a sed expression, read as OLD=NEW
+        assert_eq!(Rule::parse("s=x").unwrap(), rule("s", "x"));
+        assert_eq!(Rule::parse("sed/x=/y").unwrap(), rule("sed/x", "/y"));
+
+        assert!(Rule::parse("s#/old#/new#x").is_err());
+        assert!(Rule::parse("s#/old#/new").is_err());
+        assert!(Rule::parse("=/new").is_err());
+    }
+
+    #[test]
+    fn sed_flags_apply_to_their_rule_only() {
+        let rules = [Rule::parse("s#/OLD#/new#i").unwrap(), rule("/Keep", "/kept")];
+        assert_eq!(options(&rules).apply("/old/x").as_deref(), Some("/new/x"));
+        assert_eq!(options(&rules).apply("/keep/x").as_deref(), None);
+
+        // g is accepted, every occurrence is replaced only without --first-only
+        let rules = [Rule::parse("s#a#b#g").unwrap()];
+        assert_eq!(options(&rules).apply("/a/a").as_deref(), Some("/b/a"));
+        assert_eq!(ReplaceOptions { all_occurrences: true, ..options(&rules) }.apply("/a/a").as_deref(), Some("/b/b"));
+    }
+
+    #[test]
+    fn path_style_converts_separators_and_drives() {
+        for (style, value, expected) in [
+            (PathStyle::Windows, "/mnt/d/films/x", r"D:\films\x"),
+            (PathStyle::Windows, "/mnt/d", "D:"),
+            (PathStyle::Windows, "/mnt/data/x", r"\mnt\data\x"),
+            (PathStyle::Windows, "//server/share/x", r"\\server\share\x"),
+            (PathStyle::Unix, r"D:\films\x", "/mnt/d/films/x"),
+            (PathStyle::Unix, "d:/films", "/mnt/d/films"),
+            (PathStyle::Unix, r"\\server\share\x", "//server/share/x"),
+            (PathStyle::Unix, "/already/unix", "/already/unix"),
+        ] {
+            assert_eq!(String::from_utf8(style.convert(value.as_bytes(), b"")).unwrap(), expected, "{:?} {}", style, value);
+        }
+
+        // Auto follows the replace string, and leaves the value alone when it has no separator
+        assert_eq!(PathStyle::Auto.convert(b"/mnt/d/x", br"E:\"), br"D:\x");
+        assert_eq!(PathStyle::Auto.convert(br"D:\x", b"/srv"), b"/mnt/d/x");
+        assert_eq!(PathStyle::Auto.convert(br"D:\x/y", b"name"), br"D:\x/y");
+    }
+
+    #[test]
+    fn path_style_rewrites_escaped_and_unc_values() {
+        let rules = [rule(r"D:\data", "/srv")];
+        let unix = ReplaceOptions { path_style: Some(PathStyle::Unix), ..options(&rules) };
+        assert_eq!(unix.apply(r"D:\data\films\x").as_deref(), Some("/srv/films/x"));
+        // An export escaping every backslash is matched as single separators
+        assert_eq!(unix.apply(r"D:\\data\\films").as_deref(), Some("/srv/films"));
+
+        let rules = [rule("/srv", r"\\nas\share")];
+        let windows = ReplaceOptions { path_style: Some(PathStyle::Windows), ..options(&rules) };
+        assert_eq!(windows.apply("/srv/films/x").as_deref(), Some(r"\\nas\share\films\x"));
+
+        let rules = [rule(r"\\nas\share", "/srv")];
+        let unix = ReplaceOptions { path_style: Some(PathStyle::Unix), ..options(&rules) };
+        assert_eq!(unix.apply(r"\\nas\share\films").as_deref(), Some("/srv/films"));
+        assert_eq!(unix.apply(r"\\\\nas\\share\\films").as_deref(), Some("/srv/films"));
+    }
+
+    #[test]
+    fn normalize_path_collapses_slashes_and_dot_components() {
+        for (value, expected) in [
+            ("/mnt//data/", "/mnt/data"),
+            ("/mnt/./data/x/../y", "/mnt/data/y"),
+            ("/../../x", "/x"),
+            ("/a/..", "/"),
+            ("a/../../b", "../b"),
+            ("./", "."),
+            ("", ""),
+        ] {
+            assert_eq!(normalize_path(value.as_bytes()), expected.as_bytes(), "{}", value);
+        }
+    }
+
+    #[test]
+    fn normalize_composes_utf8_only_with_nfc() {
+        // e followed by a combining acute accent, as written on macOS
+        let decomposed = "/films/Ame\u{301}lie//";
+        assert_eq!(Normalize { nfc: false }.apply(decomposed.as_bytes(), Encoding::Utf8), "/films/Ame\u{301}lie".as_bytes());
+        assert_eq!(Normalize { nfc: true }.apply(decomposed.as_bytes(), Encoding::Utf8), "/films/Am\u{e9}lie".as_bytes());
+        assert_eq!(Normalize { nfc: true }.apply(decomposed.as_bytes(), Encoding::Latin1), "/films/Ame\u{301}lie".as_bytes());
+
+        // A value no rule matches is still rewritten when it changes, and left alone when it is already clean
+        let normalize = ReplaceOptions { normalize: Some(Normalize { nfc: true }), ..options(&[]) };
+        assert_eq!(normalize.apply_bytes(decomposed.as_bytes()).as_deref(), Some("/films/Am\u{e9}lie".as_bytes()));
+        assert_eq!(normalize.apply_bytes("/films/Am\u{e9}lie".as_bytes()), None);
+    }
+}
@@ -0,0 +1,148 @@
+//! Token by token reading and writing of bencode, for files too large to decode whole such as the resume data
+//! of torrents with many files. Values are copied through without being held in memory.
+
+use std::io::{self, BufRead, Read, Write};
+
+use anyhow::{bail, Context, Result};
+
+use crate::bencode::MAX_DEPTH;
+
+// Integers and string lengths are short, a longer run without its delimiter is not bencode
+const MAX_NUMBER_LEN: usize = 32;
+
+/// The start of a value, or the end of a list or dictionary. The `length` bytes of a string follow its token.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Token {
+    Integer(i64),
+    Bytes(u64),
+    List,
+    Dict,
+    End,
+}
+
+impl Token {
+    /// Write the token as it is encoded, the bytes of a string are up to the caller
+    pub fn write(&self, writer: &mut impl Write) -> io::Result<()> {
+        match self {
+            Token::Integer(integer) => write!(writer, "i{}e", integer),
+            Token::Bytes(length) => write!(writer, "{}:", length),
+            Token::List => writer.write_all(b"l"),
+            Token::Dict => writer.write_all(b"d"),
+            Token::End => writer.write_all(b"e"),
+        }
+    }
+}
+
+pub struct Reader<R> {
+    reader: R,
+    offset: u64,
+}
+
+impl<R: BufRead> Reader<R> {
+    pub fn new(reader: R) -> Self {
+        Reader { reader, offset: 0 }
+    }
+
+    /// The next token, `None` at the end of the input
+    pub fn next_token(&mut self) -> Result<Option<Token>> {
+        let Some(&first) = self.reader.fill_buf()?.first() else {
+            return Ok(None);
+        };
+        let start = self.offset;
+        let token = match first {
+            b'i' => {
+                self.consume(1);
+                let number = self.read_number(b'e')?;
+                Token::Integer(number.parse().with_context(|| format!("Invalid integer {:?} at offset {}", number, start))?)
+            }
+            b'0'..=b'9' => {
+                let length = self.read_number(b':')?;
+                Token::Bytes(length.parse().with_context(|| format!("Invalid string length at offset {}", start))?)
+            }
+            b'l' | b'd' | b'e' => {
+                self.consume(1);
+                match first {
+                    b'l' => Token::List,
+                    b'd' => Token::Dict,
+                    _ => Token::End,
+                }
+            }
+            other => bail!("Unexpected byte {:?} at offset {}", other as char, start),
+        };
+        Ok(Some(token))
+    }
+
+    /// The next token, failing at the end of the input
+    pub fn expect_token(&mut self) -> Result<Token> {
+        self.next_token()?.with_context(|| format!("Unexpected end of data at offset {}", self.offset))
+    }
+
+    /// The `length` bytes of the string whose token was just read, for keys and other short strings
+    pub fn read_bytes(&mut self, length: u64) -> Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        self.copy_bytes(length, &mut bytes)?;
+        Ok(bytes)
+    }
+
+    /// Copy the `length` bytes of the string whose token was just read to `writer`
+    pub fn copy_bytes(&mut self, length: u64, writer: &mut impl Write) -> Result<()> {
+        let start = self.offset;
+        let copied = io::copy(&mut self.reader.by_ref().take(length), writer)?;
+        self.offset += copied;
+        if copied != length {
+            bail!("String at offset {} overruns the data", start);
+        }
+        Ok(())
+    }
+
+    /// Copy the whole value starting with `token` to `writer`, nested lists and dictionaries included
+    pub fn copy_value(&mut self, token: Token, writer: &mut impl Write) -> Result<()> {
+        let mut token = token;
+        let mut depth = 0usize;
+        loop {
+            token.write(writer)?;
+            match token {
+                Token::Integer(_) => {}
+                Token::Bytes(length) => self.copy_bytes(length, writer)?,
+                Token::List | Token::Dict if depth == MAX_DEPTH => bail!("Nesting too deep at offset {}", self.offset),
+                Token::List | Token::Dict => depth += 1,
+                Token::End if depth == 0 => bail!("Unexpected end of a list or dictionary at offset {}", self.offset),
+                Token::End => depth -= 1,
+            }
+            if depth == 0 {
+                return Ok(());
+            }
+            token = self.expect_token()?;
+        }
+    }
+
+    /// Read past the whole value starting with `token`
+    pub fn skip_value(&mut self, token: Token) -> Result<()> {
+        self.copy_value(token, &mut io::sink())
+    }
+
+    /// Fail unless the input is over
+    pub fn finish(&mut self) -> Result<()> {
+        if !self.reader.fill_buf()?.is_empty() {
+            bail!("Unexpected trailing data at offset {}", self.offset);
+        }
+        Ok(())
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.reader.consume(amount);
+        self.offset += amount as u64;
+    }
+
+    /// The digits up to `delimiter`, which is consumed too
+    fn read_number(&mut self, delimiter: u8) -> Result<String> {
+        let start = self.offset;
+        let mut number = Vec::new();
+        self.reader.by_ref().take(MAX_NUMBER_LEN as u64 + 1).read_until(delimiter, &mut number)?;
+        self.offset += number.len() as u64;
+        if number.pop() != Some(delimiter) {
+            bail!("Missing {:?} after offset {}", delimiter as char, start);
+        }
+        String::from_utf8(number).with_context(|| format!("Invalid number at offset {}", start))
+    }
+}
@@ -0,0 +1,26 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+/// Keep the original content of `file_path` in `archive_dir` under the same relative path.
+/// A hardlink costs no space and survives the atomic rename of the edit, a copy is used across filesystems.
+/// An existing archive is never overwritten so repeated runs keep the oldest original, `None` is returned then.
+pub fn archive_original(file_path: &Path, input_dir: &Path, archive_dir: &Path) -> Result<Option<PathBuf>> {
+    let relative_path = file_path.strip_prefix(input_dir).ok()
+        .or_else(|| file_path.file_name().map(Path::new))
+        .context("Missing file name")?;
+    let archive_path = archive_dir.join(relative_path);
+    if archive_path.exists() {
+        return Ok(None);
+    }
+
+    if let Some(parent) = archive_path.parent() {
+        fs::create_dir_all(parent).with_context(|| format!("Failed to create archive directory: {:?}", parent))?;
+    }
+    if fs::hard_link(file_path, &archive_path).is_err() {
+        fs::copy(file_path, &archive_path).with_context(|| format!("Failed to archive file {:?}", file_path))?;
+    }
+
+    Ok(Some(archive_path))
+}
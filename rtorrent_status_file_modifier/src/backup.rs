@@ -0,0 +1,93 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use reptool_core::file::{read_buffered, write_atomic};
+use serde::{Deserialize, Serialize};
+
+use crate::RepToolOption;
+
+const JOURNAL_NAME: &str = "journal.jsonl";
+const UNDONE_JOURNAL_NAME: &str = "journal.undone.jsonl";
+
+/// One line of the undo journal
+#[derive(Debug, Serialize, Deserialize)]
+struct JournalEntry {
+    original: PathBuf,
+    backup: PathBuf,
+}
+
+/// Where the runs editing `input_dir` in place keep their backups, next to it so scans never pick them up
+pub fn backup_root(input_dir: &Path) -> Result<PathBuf> {
+    let input_dir = input_dir.canonicalize().with_context(|| format!("Failed to resolve input directory: {:?}", input_dir))?;
+    let mut root = input_dir.into_os_string();
+    root.push(".reptool-undo");
+    Ok(PathBuf::from(root))
+}
+
+/// The backup directory of a new run, named after the start time so the last run sorts last.
+/// Nothing is created until the first file is backed up.
+pub fn new_run(input_dir: &Path) -> Result<PathBuf> {
+    let millis = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+    Ok(backup_root(input_dir)?.join(format!("{:013}", millis)))
+}
+
+/// Copy `file_path` into the run's backup directory and journal it, call right before the file is rewritten
+pub fn backup_before_write(option: &RepToolOption, file_path: &Path) -> Result<()> {
+    let Some(run_dir) = &option.backup_run else {
+        return Ok(());
+    };
+
+    let original = file_path.canonicalize().with_context(|| format!("Failed to resolve file: {:?}", file_path))?;
+    let relative_path = file_path.strip_prefix(option.input_dir()?).ok()
+        .or_else(|| file_path.file_name().map(Path::new))
+        .context("Missing file name")?;
+    let backup = run_dir.join(relative_path);
+    if let Some(parent) = backup.parent() {
+        fs::create_dir_all(parent).with_context(|| format!("Failed to create backup directory: {:?}", parent))?;
+    }
+    fs::copy(file_path, &backup).with_context(|| format!("Failed to back up file: {:?}", file_path))?;
+
+    let mut journal = OpenOptions::new().create(true).append(true).open(run_dir.join(JOURNAL_NAME))
+        .with_context(|| format!("Failed to open undo journal in {:?}", run_dir))?;
+    // One write per line so the entries of parallel jobs never interleave
+    let line = format!("{}\n", serde_json::to_string(&JournalEntry { original, backup })?);
+    journal.write_all(line.as_bytes())?;
+
+    Ok(())
+}
+
+/// Restore the files of the last run that has not been undone yet, returns the restored paths
+pub fn undo_last_run(input_dir: &Path, dry_run: bool, io_buffer: usize) -> Result<Vec<PathBuf>> {
+    let root = backup_root(input_dir)?;
+    let mut run_dirs: Vec<PathBuf> = match fs::read_dir(&root) {
+        Ok(entries) => entries.filter_map(|entry| Some(entry.ok()?.path())).filter(|path| path.join(JOURNAL_NAME).is_file()).collect(),
+        Err(_) => Vec::new(),
+    };
+    run_dirs.sort();
+    let Some(run_dir) = run_dirs.pop() else {
+        return Ok(Vec::new());
+    };
+
+    let journal_path = run_dir.join(JOURNAL_NAME);
+    let journal = fs::read_to_string(&journal_path).with_context(|| format!("Failed to read undo journal: {:?}", journal_path))?;
+    let mut restored = Vec::new();
+    for line in journal.lines().filter(|line| !line.trim().is_empty()) {
+        let entry: JournalEntry = serde_json::from_str(line).with_context(|| format!("Invalid undo journal line: {:?}", line))?;
+        if !dry_run {
+            let content = read_buffered(&entry.backup, io_buffer).with_context(|| format!("Failed to read backup: {:?}", entry.backup))?;
+            write_atomic(&entry.original, &content, io_buffer)?;
+        }
+        restored.push(entry.original);
+    }
+
+    // Keep the backups but never restore the same run twice
+    if !dry_run {
+        fs::rename(&journal_path, run_dir.join(UNDONE_JOURNAL_NAME))
+            .with_context(|| format!("Failed to mark run as undone: {:?}", run_dir))?;
+    }
+
+    Ok(restored)
+}
@@ -0,0 +1,105 @@
+use std::path::PathBuf;
+
+use anyhow::{bail, Result};
+use clap::Args;
+use reptool_core::bencode::Value;
+use reptool_core::pieces::{self, PieceCheck};
+use serde_json::json;
+use tracing::{debug, warn};
+
+use crate::glob::FileMatcher;
+use crate::keys::session_files;
+use crate::resume::{info_name, payload_files, torrent_data};
+use crate::{exit, info_hash_of, RepToolOption};
+
+#[derive(Args, Clone)]
+pub struct CheckArgs {
+    /// Session directory
+    pub dir : String,
+
+    /// Read the data and verify it against the piece hashes of the .torrent, instead of only the file sizes
+    #[arg(long)]
+    pub hash_data : bool,
+
+    /// Look for the payload named after the torrent in DIR instead of the directory of the session file
+    #[arg(long, value_name = "DIR")]
+    pub data_dir : Option<PathBuf>,
+
+    /// Only the torrent with this info-hash, repeatable
+    #[arg(long, value_name = "HASH")]
+    pub hash : Vec<String>,
+}
+
+struct TorrentCheck {
+    hash: String,
+    name: String,
+    data_path: PathBuf,
+    result: Result<PieceCheck>,
+}
+
+/// Check the data of every torrent of a session directory and report how complete it is, fails when one is not
+pub fn check(matcher: &FileMatcher, option: &RepToolOption, args: &CheckArgs) -> Result<()> {
+    let mut checks = Vec::new();
+    for session_path in session_files(matcher, option, &args.hash)? {
+        let hash = info_hash_of(&session_path);
+        let (info, data_path) = match torrent_data(&session_path, args.data_dir.as_deref(), option) {
+            Ok(torrent) => torrent,
+            Err(err) => {
+                warn!("Skipped {}: {:#}", session_path.display(), err);
+                checks.push(TorrentCheck { hash, name: String::new(), data_path: PathBuf::new(), result: Err(err) });
+                continue;
+            }
+        };
+        debug!("Checking {}", data_path.display());
+
+        let result = (|| {
+            let (Some(Value::Integer(piece_length)), Some(Value::Bytes(piece_hashes))) = (info.get(b"piece length".as_slice()), info.get(b"pieces".as_slice())) else {
+                bail!("No v1 piece hashes, v2 only torrents are not supported");
+            };
+            let files = payload_files(&info, &data_path)?;
+            pieces::check(&files, u64::try_from(*piece_length)?, piece_hashes, args.hash_data, option.global.io_buffer)
+        })();
+        checks.push(TorrentCheck { hash, name: info_name(&info).unwrap_or_default(), data_path, result });
+    }
+
+    let incomplete = checks.iter().filter(|check| !check.result.as_ref().is_ok_and(PieceCheck::is_complete)).count();
+    if option.global.json {
+        let entries: Vec<_> = checks.iter().map(|check| {
+            let mut entry = json!({
+                "hash": check.hash,
+                "name": check.name,
+                "data_path": check.data_path.display().to_string(),
+            });
+            match &check.result {
+                Ok(result) => {
+                    entry["percent"] = json!(result.percent());
+                    entry["check"] = json!(result);
+                }
+                Err(err) => entry["error"] = json!(format!("{:#}", err)),
+            }
+            entry
+        }).collect();
+        println!("{}", serde_json::to_string_pretty(&json!({ "hash_data": args.hash_data, "torrents": entries }))?);
+    } else {
+        for check in &checks {
+            match &check.result {
+                Ok(result) => {
+                    println!("{} {}: {:.1}% ({}/{} pieces) in {}", check.hash, check.name, result.percent(), result.pieces_ok, result.pieces, check.data_path.display());
+                    for path in &result.missing_files {
+                        println!("  missing {}", path.display());
+                    }
+                    for path in &result.wrong_size {
+                        println!("  wrong size {}", path.display());
+                    }
+                }
+                Err(err) => println!("{} {}: {:#}", check.hash, check.name, err),
+            }
+        }
+        println!("{} of {} torrent(s) complete{}", checks.len() - incomplete, checks.len(), if args.hash_data { "" } else { " by size, --hash-data to verify the content" });
+    }
+
+    if incomplete > 0 {
+        return Err(exit::partial_failure(format!("{} torrent(s) incomplete", incomplete)));
+    }
+    Ok(())
+}
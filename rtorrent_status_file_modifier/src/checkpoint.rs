@@ -0,0 +1,81 @@
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use anyhow::{bail, Context, Result};
+
+/// Name of the progress file in the input directory of an in place run when --checkpoint is not given
+pub const DEFAULT_CHECKPOINT: &str = ".reptool-progress";
+
+// First line of a checkpoint, the rest is one path per line
+const HEADER_PREFIX: &str = "# rules ";
+
+/// Records every finished file so an interrupted run can skip them when restarted. The first line identifies the
+/// rules of the run, the files follow one per line as they finish. Deleted once the whole run completes.
+pub struct Checkpoint {
+    path: PathBuf,
+    fingerprint: String,
+    file: Mutex<Option<File>>,
+}
+
+impl Checkpoint {
+    pub fn new(path: &Path, fingerprint: &str) -> Self {
+        Checkpoint { path: path.to_path_buf(), fingerprint: fingerprint.to_owned(), file: Mutex::new(None) }
+    }
+
+    pub fn exists(&self) -> bool {
+        self.path.exists()
+    }
+
+    /// Files finished by a previous run, empty when there is no checkpoint yet. Fails when that run had other rules.
+    pub fn load(&self) -> Result<HashSet<PathBuf>> {
+        if !self.path.exists() {
+            return Ok(HashSet::new());
+        }
+
+        let content = fs::read_to_string(&self.path).with_context(|| format!("Failed to read checkpoint: {:?}", self.path))?;
+        let mut lines = content.lines().peekable();
+        if let Some(fingerprint) = lines.peek().and_then(|line| line.strip_prefix(HEADER_PREFIX)) {
+            if fingerprint != self.fingerprint {
+                bail!("The checkpoint {:?} was recorded with other rules, run with the same rules or remove it", self.path);
+            }
+            lines.next();
+        }
+        Ok(lines.filter(|line| !line.is_empty()).map(PathBuf::from).collect())
+    }
+
+    /// Append a finished file, the checkpoint is created with its header on the first one
+    pub fn record(&self, file_path: &Path) -> Result<()> {
+        let mut file = self.file.lock().expect("Checkpoint poisoned");
+        if file.is_none() {
+            let is_new = !self.path.exists();
+            let mut opened = fs::OpenOptions::new().create(true).append(true).open(&self.path)
+                .with_context(|| format!("Failed to open checkpoint: {:?}", self.path))?;
+            if is_new {
+                writeln!(opened, "{}{}", HEADER_PREFIX, self.fingerprint)?;
+            }
+            *file = Some(opened);
+        }
+        let file = file.as_mut().expect("Checkpoint opened above");
+        writeln!(file, "{}", file_path.display()).with_context(|| format!("Failed to write checkpoint: {:?}", self.path))
+    }
+
+    /// Make what was recorded so far survive a crash of the system, once per batch
+    pub fn sync(&self) -> Result<()> {
+        if let Some(file) = self.file.lock().expect("Checkpoint poisoned").as_ref() {
+            file.sync_all().with_context(|| format!("Failed to write checkpoint: {:?}", self.path))?;
+        }
+        Ok(())
+    }
+
+    pub fn finish(&self) -> Result<()> {
+        self.file.lock().expect("Checkpoint poisoned").take();
+        if self.path.exists() {
+            fs::remove_file(&self.path).with_context(|| format!("Failed to remove checkpoint: {:?}", self.path))?;
+        }
+
+        Ok(())
+    }
+}
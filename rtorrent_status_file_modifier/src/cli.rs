@@ -0,0 +1,610 @@
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicUsize;
+use std::sync::Arc;
+
+use anyhow::{bail, Context, Result};
+use clap::{ArgAction, Args, Parser, Subcommand};
+use clap_complete::Shell;
+use regex::bytes::Regex;
+use reptool_core::file::DEFAULT_IO_BUFFER;
+use reptool_core::{Anchor, Encoding, Normalize, PathStyle, ReplaceOptions, Rule};
+
+use crate::data::{CheckData, MoveData};
+use crate::diff::DiffStyle;
+use crate::fsops::LinkData;
+use crate::logging::LogFormat;
+#[cfg(feature = "script")]
+use crate::script;
+use crate::timeout::WriteGate;
+use crate::transaction::Transaction;
+use crate::ui::ColorChoice;
+use crate::{check, container, convert, dump, inspect, interactive, keys, label, migrate, resume, retrack, rules, serve, watch};
+
+#[derive(Parser, Clone)]
+#[command(name = "rtorrent_status_file_modifier")]
+#[command(author = "sontran")]
+#[command(version = "1.0")]
+#[command(about = "Replace string for .torrent.rtorrent", long_about = "This program modifies rtorrent's status file to change the download path for an already loaded torrent.")]
+#[command(subcommand_negates_reqs = true, args_conflicts_with_subcommands = true)]
+#[command(after_help = "Exit codes: 0 changes applied (or would be with --dry-run), 1 usage error, 2 no match, 3 partial failure, 4 I/O error, 130 interrupted")]
+pub struct RepToolOption {
+    #[command(subcommand)]
+    pub command : Option<Command>,
+
+    #[command(flatten)]
+    pub global : GlobalOptions,
+
+    /// Without a subcommand the arguments are those of `replace`, as before subcommands existed
+    #[command(flatten)]
+    pub replace : ReplaceArgs,
+
+    /// Every search and replace pair of the run, the positional pair first
+    #[arg(skip)]
+    pub rules : Vec<Rule>,
+
+    /// The search strings of the rules compiled by --regex
+    #[arg(skip)]
+    pub patterns : Vec<Regex>,
+
+    /// The rules of --rules, evaluated per torrent
+    #[arg(skip)]
+    pub rule_set : Option<rules::RuleSet>,
+
+    /// The compiled --script
+    #[cfg(feature = "script")]
+    #[arg(skip)]
+    pub script : Option<Arc<script::Script>>,
+
+    /// Backup directory of this run, set when files are edited in place
+    #[arg(skip)]
+    pub backup_run : Option<PathBuf>,
+
+    /// The files staged by --transactional, shared by the runs over every input directory
+    #[arg(skip)]
+    pub transaction : Option<Arc<Transaction>>,
+
+    /// Answers given so far with --interactive
+    #[arg(skip)]
+    pub confirmations : Arc<interactive::Confirmations>,
+
+    /// Files with changes so far, counted against --max-replacements
+    #[arg(skip)]
+    pub replacements : Arc<AtomicUsize>,
+
+    /// The files listed by --files-from, processed instead of the files of the input directory
+    #[arg(skip)]
+    pub listed_files : Option<Vec<PathBuf>>,
+
+    /// Set on the worker of a file processed with --file-timeout, its writes go through it
+    #[arg(skip)]
+    pub write_gate : Option<Arc<WriteGate>>,
+}
+
+/// Options shared by every command
+#[derive(Args, Clone)]
+pub struct GlobalOptions {
+    /// Encoding of the path bytes in the session files, latin1 for legacy sessions with non UTF-8 paths
+    #[arg(long, global = true, value_enum, default_value_t = Encoding::Utf8)]
+    pub encoding : Encoding,
+
+    /// Log more, -v for what is done, -vv for every file, -vvv for everything
+    #[arg(short, long, global = true, alias = "verbose-mode", action = ArgAction::Count, conflicts_with = "quiet")]
+    pub verbose : u8,
+
+    /// Define keyword to search and replace, repeatable, a trailing * matches every key with that prefix
+    #[arg(short = 'k', long = "keyword", global = true, default_values_t = [String::from("directory")])]
+    pub keywords : Vec<String>,
+
+    /// Also process the session files in subdirectories of the input path
+    #[arg(short, long, global = true)]
+    pub recursive : bool,
+
+    /// Descend at most N levels of subdirectories (implies --recursive)
+    #[arg(long, global = true, value_name = "N")]
+    pub max_depth : Option<usize>,
+
+    /// Also process the session files and directories symlinked into the input path, each one once
+    #[arg(long, global = true)]
+    pub follow_symlinks : bool,
+
+    /// Buffer size in bytes for reading and writing session files
+    #[arg(long, global = true, value_name = "BYTES", default_value_t = DEFAULT_IO_BUFFER)]
+    pub io_buffer : usize,
+
+    /// Show which files would change and how, without writing anything
+    #[arg(long, global = true)]
+    pub dry_run : bool,
+
+    /// Edit the session files even though rtorrent.lock shows rtorrent is running
+    #[arg(long, global = true)]
+    pub force : bool,
+
+    /// Wait for another run editing the same session directory to finish instead of failing, at most SECS
+    #[arg(long, global = true, value_name = "SECS", num_args = 0..=1)]
+    pub wait : Option<Option<u64>>,
+
+    /// Print reports as JSON
+    #[arg(long, global = true)]
+    pub json : bool,
+
+    /// Also write the JSON report of the run to FILE, whatever is printed
+    #[arg(long, global = true, value_name = "FILE")]
+    pub report_file : Option<PathBuf>,
+
+    /// POST the JSON report of the run to this webhook when it is over, or what failed when there is no report
+    #[arg(long, global = true, value_name = "URL")]
+    pub notify_url : Option<String>,
+
+    /// Also POST one message per failed file to --notify-url before the report
+    #[arg(long, global = true, requires = "notify_url")]
+    pub notify_errors : bool,
+
+    /// Only log errors and do not show the progress bar
+    #[arg(short, long, global = true)]
+    pub quiet : bool,
+
+    /// Also append the logs to FILE, with at least every changed value whatever -q or -v
+    #[arg(long, global = true, value_name = "FILE")]
+    pub log_file : Option<PathBuf>,
+
+    /// Format of the log lines, on stderr and in --log-file
+    #[arg(long, global = true, value_enum, default_value_t = LogFormat::Text)]
+    pub log_format : LogFormat,
+
+    /// Color the changes, skipped files and failures, auto only on a terminal and without NO_COLOR set
+    #[arg(long, global = true, value_enum, value_name = "WHEN", default_value_t = ColorChoice::Auto)]
+    pub color : ColorChoice,
+
+    /// Endings of the files to process or copy, comma separated [default: rtorrent,torrent,libtorrent_resume]
+    #[arg(long, global = true, value_name = "EXT", value_delimiter = ',')]
+    pub extensions : Vec<String>,
+
+    /// Only process the files whose name matches GLOB, repeatable
+    #[arg(long, global = true, value_name = "GLOB")]
+    pub include : Vec<String>,
+
+    /// Skip the files whose name matches GLOB, repeatable, wins over --include
+    #[arg(long, global = true, value_name = "GLOB")]
+    pub exclude : Vec<String>,
+
+    /// Read the defaults from FILE instead of ~/.config/reptool/config.toml
+    #[arg(long, global = true, value_name = "FILE")]
+    pub config_file : Option<PathBuf>,
+
+    /// Stop at the first file that fails instead of reporting it and carrying on with the others
+    #[arg(long, global = true)]
+    pub fail_fast : bool,
+
+    /// Ignore the config file
+    #[arg(long, global = true, conflicts_with = "config_file")]
+    pub no_config : bool,
+}
+
+/// Options of the replace command
+#[derive(Args, Clone)]
+pub struct ReplaceArgs {
+    /// Input path contains .torrent.rtorrent, - reads the files to process from stdin like --files-from -,
+    /// session-path of the config file when omitted
+    pub input_path : Option<String>,
+
+    /// Search string, the mappings of the config file apply when no search string, -e or --map-file is given
+    pub search_string : Option<String>,
+
+    /// Replace string, {name}, {hash}, {label} and {tracker_host} are resolved per torrent
+    pub replace_string : Option<String>,
+
+    /// Additional search and replace pair, OLD=NEW or a sed expression s#OLD#NEW#[gi] with any delimiter, i to
+    /// ignore the case. Repeatable, the first pair found in a value is applied
+    #[arg(short = 'e', long = "map", value_name = "OLD=NEW")]
+    pub mapping : Vec<String>,
+
+    /// One more session directory to process in the same run, repeatable, checked and backed up on its own
+    #[arg(long = "input", value_name = "DIR", conflicts_with = "files_from")]
+    pub inputs : Vec<String>,
+
+    /// Process the files listed in FILE, one per line, instead of a directory, - for stdin.
+    /// The positional arguments are then SEARCH_STRING REPLACE_STRING
+    #[arg(long, value_name = "FILE")]
+    pub files_from : Option<String>,
+
+    /// The listed files are separated by NUL bytes, as printed by find -print0
+    #[arg(short = '0', long)]
+    pub null : bool,
+
+    /// Read OLD=NEW pairs or s#OLD#NEW# expressions from FILE, one per line, blank lines and lines starting with # are
+    /// ignored
+    #[arg(long, value_name = "FILE")]
+    pub map_file : Option<String>,
+
+    /// Apply the conditional rules of a TOML file instead of search and replace pairs: each [[rule]] has conditions
+    /// (tracker, label, path-prefix, name) and actions (rewrite, set-label), mode is first-match or all-match
+    #[arg(long = "rules", value_name = "FILE", conflicts_with_all = ["search_string", "mapping", "map_file", "map_to"])]
+    pub rules_file : Option<PathBuf>,
+
+    /// Replace SEARCH_STRING as a path prefix with the directory of each torrent's tracker, matched on the host of
+    /// its announce URLs or a subdomain of it. REPLACE_STRING is then optional, for the torrents of other trackers
+    #[arg(long, value_name = "HOST=DIR", value_delimiter = ',', requires = "search_string",
+        conflicts_with_all = ["mapping", "map_file", "rules_file", "map_to", "regex"])]
+    pub per_tracker_map : Vec<String>,
+
+    /// Run the Rhai script of FILE on every .torrent.rtorrent after the rules, its fn rewrite(session, torrent) gets
+    /// both as maps and returns a map of the top level fields to set, or () (needs the script feature)
+    #[arg(long = "script", value_name = "FILE")]
+    pub script_file : Option<PathBuf>,
+
+    /// Translate between the host and the container view of the data, HOST:CONTAINER as in docker run -v (repeatable)
+    #[arg(long, value_name = "HOST:CONTAINER", requires = "map_to")]
+    pub container_map : Vec<String>,
+
+    /// Read the bind mounts of a docker compose file as --container-map pairs
+    #[arg(long, value_name = "FILE", requires = "map_to")]
+    pub compose_file : Option<PathBuf>,
+
+    /// Only the bind mounts of this service of --compose-file
+    #[arg(long, value_name = "SERVICE", requires = "compose_file")]
+    pub compose_service : Option<String>,
+
+    /// View the container mappings rewrite the values to, they match as path prefixes and turn on --anchor prefix
+    #[arg(long, value_enum)]
+    pub map_to : Option<container::Side>,
+
+    /// Side of the container mappings whose paths must exist, container when running inside it
+    #[arg(long, value_enum, default_value_t = container::Side::Host)]
+    pub check_side : container::Side,
+
+    /// Only edit the torrents whose name matches this regular expression
+    #[arg(long, value_name = "REGEX")]
+    pub name_filter : Option<String>,
+
+    /// Only edit the torrents with an announce URL matching this regular expression, a plain substring works too
+    #[arg(long, value_name = "REGEX")]
+    pub tracker_filter : Option<String>,
+
+    /// Only edit the torrents rtorrent has all the data of, by the complete flag or else the resume data
+    #[arg(long, conflicts_with = "only_incomplete")]
+    pub only_complete : bool,
+
+    /// Only edit the torrents still downloading, e.g. to keep them on the fast disk while the others move
+    #[arg(long)]
+    pub only_incomplete : bool,
+
+    /// Only edit the torrent with this info-hash (repeatable), v1 as in the file names or v2 as computed from the .torrent
+    #[arg(long, value_name = "HASH")]
+    pub hash : Vec<String>,
+
+    /// Only edit the torrents matching this SQL condition over the index, e.g. "label = 'movies' AND size > 1e9"
+    /// (needs the index feature, see the index command)
+    #[arg(long = "where", value_name = "CONDITION")]
+    pub where_condition : Option<String>,
+
+    /// Index queried by --where [default: .reptool-index.sqlite in the input path]
+    #[arg(long, value_name = "FILE", requires = "where_condition")]
+    pub index_db : Option<PathBuf>,
+
+    /// Only edit torrents whose current value starts with this root (repeatable)
+    #[arg(long, value_name = "ROOT")]
+    pub only_from : Vec<String>,
+
+    /// Only replace within the last component of the path, leaving the parent directories untouched
+    #[arg(long)]
+    pub replace_base_only : bool,
+
+    /// Where the search string must match, prefix also requires it to end on a path component
+    #[arg(long, value_enum, default_value_t = Anchor::Anywhere)]
+    pub anchor : Anchor,
+
+    /// Take the search strings as regular expressions, $1 or ${name} in the replace strings insert their capture
+    /// groups (write $$ for a literal $), e.g. '/mnt/disk(\d+)/' '/mnt/pool/disk$1/'
+    #[arg(long, conflicts_with_all = ["map_to", "rules_file"])]
+    pub regex : bool,
+
+    /// Match the search strings whatever their case, e.g. Downloads and downloads from a case-insensitive share.
+    /// Only ASCII letters unless with --regex.
+    #[arg(long)]
+    pub ignore_case : bool,
+
+    /// Convert the separators and drive prefix of the rewritten values, e.g. D:\torrents to /mnt/d/torrents with unix.
+    /// Escaped backslashes in the values count as one, on the command line write them \\ or use --no-escape
+    #[arg(long, value_enum)]
+    pub path_style : Option<PathStyle>,
+
+    /// Clean up the values, also without a search string: collapse duplicate slashes, strip trailing slashes and
+    /// resolve . and .. without following symlinks
+    #[arg(long)]
+    pub normalize : bool,
+
+    /// With --normalize, also compose the Unicode characters of the values (NFC)
+    #[arg(long, requires = "normalize")]
+    pub nfc : bool,
+
+    /// Replace every occurrence of the search string in a value, the default
+    #[arg(long, overrides_with = "first_only")]
+    pub all : bool,
+
+    /// Only replace the first occurrence of the search string in a value, as older releases did
+    #[arg(long, overrides_with = "all")]
+    pub first_only : bool,
+
+    /// Take backslashes in the search and replace strings literally instead of decoding \n, \t, \0, \xNN (ASCII only)
+    /// escapes, e.g. for Windows paths like C:\temp
+    #[arg(long)]
+    pub no_escape : bool,
+
+    /// Define output path to copy and modify, untouch input path files
+    #[arg(short, long, default_value_t = String::from(""))]
+    pub output_path : String,
+
+    /// Ask before writing each file: y apply, n skip, a apply all the next ones, q skip all the next ones
+    #[arg(short, long, conflicts_with_all = ["dry_run", "json", "live"])]
+    pub interactive : bool,
+
+    /// Show the changed fields of every modified file as a diff, alone or with --dry-run
+    #[arg(long, value_enum, value_name = "STYLE", num_args = 0..=1, default_missing_value = "unified")]
+    pub diff : Option<DiffStyle>,
+
+    /// Give the output files the times, permissions and (when allowed, e.g. as root) owner of their input file
+    #[arg(long)]
+    pub preserve : bool,
+
+    /// DANGEROUS: clear the piece state of .libtorrent_resume files to force a full recheck on next load
+    #[arg(long)]
+    pub reset_resume : bool,
+
+    /// Give up on a file after SECS seconds of stuck I/O, report it as failed and move on
+    #[arg(long, value_name = "SECS")]
+    pub file_timeout : Option<u64>,
+
+    /// Set fields to the exact values listed in a JSON manifest of {"file", "directory"} entries instead of searching
+    #[arg(long, value_name = "PLAN")]
+    pub apply_manifest : Option<String>,
+
+    /// Hardlink (or copy across filesystems) each original into DIR before editing it in place
+    #[arg(long, value_name = "DIR", conflicts_with = "output_path")]
+    pub archive_originals_to : Option<String>,
+
+    /// Process the files in batches of N, reporting progress after each batch. Only the totals and the reports of the
+    /// changed, failed or skipped files are kept between batches, and --transactional commits each batch on its own
+    #[arg(long, value_name = "N")]
+    pub batch_size : Option<usize>,
+
+    /// Process N files at the same time
+    #[arg(short, long, value_name = "N", default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..))]
+    pub jobs : u16,
+
+    /// Record the finished files in this file and skip them when the interrupted run is restarted
+    /// [default: .reptool-progress in the input path for in place runs, only read back with --resume]
+    #[arg(long, value_name = "FILE", conflicts_with = "dry_run")]
+    pub checkpoint : Option<String>,
+
+    /// Continue an interrupted in place run with the same rules, skipping the files it finished
+    #[arg(long)]
+    pub resume : bool,
+
+    /// All or nothing: stage every rewritten file next to its original, check it decodes back, and only rename them
+    /// over the originals once every file went through, leaving the session untouched when one failed. With
+    /// --batch-size the batches before the failed one are written
+    #[arg(long, conflicts_with_all = ["dry_run", "output_path", "move_data", "link_data", "reset_resume", "file_timeout", "resume", "checkpoint", "since_last_run"])]
+    pub transactional : bool,
+
+    /// Only process the files changed since the previous run with the same rules, for a mapping applied from cron
+    #[arg(long)]
+    pub since_last_run : bool,
+
+    /// Where --since-last-run keeps what it saw of each file [default: .reptool-state.json in the input path]
+    #[arg(long, value_name = "FILE", requires = "since_last_run")]
+    pub state_file : Option<PathBuf>,
+
+    /// Skip session files of torrents that the running rtorrent reports as active (needs --xmlrpc)
+    #[arg(long, requires = "xmlrpc")]
+    pub protect_running_torrents : bool,
+
+    /// rtorrent XML-RPC endpoint, e.g. http://localhost/RPC2
+    #[arg(long, value_name = "URL")]
+    pub xmlrpc : Option<String>,
+
+    /// Move the torrents of the running rtorrent over --xmlrpc instead of editing session files, pairs come from -e or --map-file
+    #[arg(long, requires = "xmlrpc", conflicts_with = "input_path")]
+    pub live : bool,
+
+    /// Check that the new directory exists before rewriting, skip (the default) or only warn when it does not
+    #[arg(long, value_enum, value_name = "MODE", num_args = 0..=1, default_missing_value = "skip")]
+    pub check_data : Option<CheckData>,
+
+    /// Also move (the default) or copy the data of each torrent to its new directory, before its session file is rewritten.
+    /// The data is not restored by undo
+    #[arg(long, value_enum, value_name = "MODE", num_args = 0..=1, default_missing_value = "move",
+        conflicts_with_all = ["check_data", "output_path", "live"])]
+    pub move_data : Option<MoveData>,
+
+    /// Link the data of each torrent into its new directory instead of moving it, keeping the original for seeding
+    #[arg(long, value_enum, value_name = "MODE", conflicts_with_all = ["move_data", "check_data", "output_path", "live"])]
+    pub link_data : Option<LinkData>,
+
+    /// With --check-data, also check every file listed in the .torrent
+    #[arg(long, requires = "check_data")]
+    pub check_files : bool,
+
+    /// Abort instead of warning when a safety check cannot be performed
+    #[arg(long)]
+    pub strict : bool,
+
+    /// Do not back up the files edited in place, which leaves nothing for the undo command
+    #[arg(long)]
+    pub no_backup : bool,
+
+    /// Treat a run without any match as an expected outcome and do not warn about it
+    #[arg(long)]
+    pub allow_empty : bool,
+
+    /// Modify at most N files, the other matching files are left for the next run, e.g. for a staged rollout.
+    /// Which files come first is only stable with --jobs 1.
+    #[arg(short = 'n', long, value_name = "N", conflicts_with_all = ["interactive", "live"])]
+    pub max_replacements : Option<usize>,
+
+    /// Only print how many files and fields the search strings would change, nothing is written
+    #[arg(long, conflicts_with_all = ["interactive", "diff", "json", "live"])]
+    pub count_only : bool,
+
+    /// Shell command run before the first file is touched, e.g. to stop rtorrent or snapshot the session
+    /// directory, {input} is the input path. The run is aborted when it fails.
+    #[arg(long, value_name = "CMD")]
+    pub pre_cmd : Option<String>,
+
+    /// Shell command run once the run is over, also after a failed one, {input} is the input path and {status} the
+    /// exit code of the run
+    #[arg(long, value_name = "CMD")]
+    pub post_cmd : Option<String>,
+
+    /// Stop this systemd unit before the run, once rtorrent released the session directory, and start it again after
+    #[arg(long, value_name = "UNIT")]
+    pub manage_service : Option<String>,
+
+    /// How long --manage-service waits for rtorrent to release the session directory, in seconds
+    #[arg(long, value_name = "SECS", default_value_t = 60, requires = "manage_service")]
+    pub service_timeout : u64,
+
+    /// Shell command run before every changed file is written, {file} is the file, {old_dir} and {new_dir} the old
+    /// and new value of the keyword. The file is not written when it fails.
+    #[arg(long, value_name = "CMD")]
+    pub pre_file_cmd : Option<String>,
+
+    /// Shell command run after every changed file is written, with the same placeholders as --pre-file-cmd
+    #[arg(long, value_name = "CMD")]
+    pub post_file_cmd : Option<String>,
+
+    /// Print the JSON Schema of the --json run report and exit
+    #[arg(long, exclusive = true)]
+    pub json_schema : bool,
+}
+
+#[derive(Subcommand, Clone)]
+pub enum Command {
+    /// Search and replace in the session files, the default when no command is given
+    Replace(Box<ReplaceArgs>),
+    /// Show the torrents of a session directory with their name, hash, directory, size and completion
+    List(inspect::ListArgs),
+    /// Check a session directory for orphans, missing companions, unparseable files, missing directories and torrents outside the default directory
+    Verify(inspect::VerifyArgs),
+    /// Check the data of the torrents of a session directory against their .torrent and report their completion
+    Check(check::CheckArgs),
+    /// Restore the files changed by the last in place run on a session directory
+    Undo {
+        /// Session directory
+        dir : String,
+    },
+    /// Search the decoded string values of the session files and print the matches with their file and key path
+    Grep(inspect::GrepArgs),
+    /// Print a top level key of every .torrent.rtorrent
+    Get(keys::GetArgs),
+    /// Set a top level key of every .torrent.rtorrent, e.g. custom1, throttle_name or priority
+    Set(keys::SetArgs),
+    /// Print any bencoded file as JSON
+    Dump(dump::DumpArgs),
+    /// Write a JSON file from dump back as bencode
+    Encode(dump::EncodeArgs),
+    /// Mark the torrents of a session directory as fully downloaded in their .libtorrent_resume from the data on disk, so rtorrent skips the hash check
+    FastResume(resume::FastResumeArgs),
+    /// Change the ruTorrent label (custom1) of the torrents of a session directory
+    Relabel(label::RelabelArgs),
+    /// Rewrite the tracker URLs in the .torrent files of a session directory, e.g. a new passkey or domain. Stop
+    /// rtorrent first, it only reads them when loading the session
+    Retrack(retrack::RetrackArgs),
+    /// Re-encode the keyword fields of a session directory from --encoding to another encoding, or write it for another client
+    Convert(convert::ConvertArgs),
+    /// Watch a session directory and apply the rules to every .torrent.rtorrent written to it, until killed
+    Watch(watch::WatchArgs),
+    /// Serve a small REST API over HTTP to list the torrents, preview and apply mappings and fetch the reports
+    Serve(serve::ServeArgs),
+    /// Browse the torrents of a session directory and edit their directory interactively (needs the tui feature)
+    Browse {
+        /// Session directory
+        dir : String,
+    },
+    /// Index the name, hash, directory, tracker, label, size and completion of the torrents of a session directory
+    /// into SQLite, for --where and quick reports (needs the index feature)
+    Index {
+        /// Session directory
+        dir : String,
+        /// Index file [default: .reptool-index.sqlite in the session directory]
+        #[arg(long, value_name = "FILE")]
+        db : Option<PathBuf>,
+        /// Print the result of this SQL query over the torrents table after refreshing, as JSON with --json
+        #[arg(long, value_name = "SQL")]
+        query : Option<String>,
+    },
+    /// Guided migration of a session directory: survey, plan, confirm, apply with backups and verify
+    Migrate(migrate::MigrateArgs),
+    /// Print the completion script of a shell, e.g. `completions bash > /etc/bash_completion.d/rtorrent_status_file_modifier`
+    Completions {
+        shell : Shell,
+    },
+}
+
+impl RepToolOption {
+    /// How many levels of subdirectories are scanned, none unless recursive
+    pub fn max_depth(&self) -> usize {
+        match (self.global.max_depth, self.global.recursive) {
+            (Some(max_depth), _) => max_depth,
+            (None, true) => usize::MAX,
+            (None, false) => 0,
+        }
+    }
+
+    /// A copy working in place on `dir`, for the commands taking the session directory as their argument
+    pub fn with_input(&self, dir : &str) -> RepToolOption {
+        let mut option = self.clone();
+        option.replace.input_path = Some(dir.to_owned());
+        option.replace.output_path.clear();
+        option
+    }
+
+    /// One option per input directory of the replace command, the input path first then every --input.
+    /// With several of them an output path gets a subdirectory per input, named after it.
+    pub fn input_runs(&self) -> Result<Vec<RepToolOption>> {
+        if self.replace.inputs.is_empty() {
+            return Ok(vec![self.clone()]);
+        }
+
+        let input_paths: Vec<&String> = self.replace.input_path.iter().chain(&self.replace.inputs).collect();
+        let mut runs: Vec<RepToolOption> = Vec::with_capacity(input_paths.len());
+        for input_path in input_paths {
+            let mut run = self.clone();
+            run.replace.input_path = Some(input_path.clone());
+            if !self.replace.output_path.is_empty() {
+                let name = Path::new(input_path).file_name().with_context(|| format!("Input path without a name: {:?}", input_path))?;
+                run.replace.output_path = Path::new(&self.replace.output_path).join(name).display().to_string();
+                if runs.iter().any(|other| other.replace.output_path == run.replace.output_path) {
+                    bail!("Two input directories are named {:?}, their files would mix under the output path", name);
+                }
+            }
+            runs.push(run);
+        }
+
+        Ok(runs)
+    }
+
+    /// The first --keyword, for the commands showing a single field per torrent
+    pub fn keyword(&self) -> &str {
+        self.global.keywords.first().map(String::as_str).unwrap_or("directory")
+    }
+
+    /// How the rules of this run rewrite a value
+    pub fn replace_options(&self) -> ReplaceOptions<'_> {
+        ReplaceOptions {
+            rules: &self.rules,
+            patterns: &self.patterns,
+            encoding: self.global.encoding,
+            only_from: &self.replace.only_from,
+            base_only: self.replace.replace_base_only,
+            all_occurrences: self.replace.all || !self.replace.first_only,
+            anchor: if self.replace.map_to.is_some() { Anchor::Prefix } else { self.replace.anchor },
+            ignore_case: self.replace.ignore_case,
+            path_style: self.replace.path_style,
+            normalize: self.replace.normalize.then_some(Normalize { nfc: self.replace.nfc }),
+        }
+    }
+
+    pub fn input_dir(&self) -> Result<&Path> {
+        self.replace.input_path.as_deref().map(Path::new).context("Missing input path")
+    }
+}
@@ -0,0 +1,220 @@
+//! Session formats of other BitTorrent clients, for switching to or away from rtorrent without rechecking the data
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Result};
+use reptool_core::bencode::Value;
+use reptool_core::report::{FileReport, RunSummary};
+use reptool_core::{Anchor, Encoding, ReplaceOptions, Rule};
+use tracing::warn;
+
+use crate::glob::FileMatcher;
+use crate::RepToolOption;
+
+mod qbittorrent;
+mod rtorrent;
+mod transmission;
+
+/// A client whose session convert can read or write
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Client {
+    /// BT_backup directory of qBittorrent: <hash>.torrent and <hash>.fastresume
+    Qbittorrent,
+    /// Config directory of Transmission holding resume/ and torrents/, read only
+    Transmission,
+}
+
+impl Client {
+    pub fn display_name(self) -> &'static str {
+        match self {
+            Client::Qbittorrent => "qBittorrent",
+            Client::Transmission => "Transmission",
+        }
+    }
+}
+
+/// A torrent of another client's session, with what rtorrent needs to take it over
+#[derive(Debug, Clone, Default)]
+pub struct ForeignTorrent {
+    /// The resume file it was read from
+    pub source: PathBuf,
+    /// The .torrent exactly as the client keeps it
+    pub torrent_data: Vec<u8>,
+    /// Directory holding the payload, the one named after info.name
+    pub save_path: String,
+    pub label: String,
+    pub is_complete: bool,
+    pub is_paused: bool,
+    pub uploaded: i64,
+    pub downloaded: i64,
+    pub added_time: i64,
+    pub completed_time: i64,
+}
+
+/// Write the session of `client` for every torrent of the rtorrent session directory to `output_dir`
+pub fn export(client: Client, matcher: &FileMatcher, option: &RepToolOption, output_dir: &Path) -> Result<RunSummary> {
+    match client {
+        Client::Qbittorrent => qbittorrent::export(matcher, option, output_dir),
+        Client::Transmission => bail!("Writing a Transmission session is not supported, only reading it with --from"),
+    }
+}
+
+/// Read the session of `client` in `input_dir` and write an rtorrent session to `output_dir`,
+/// the save paths rewritten by the first matching of `mappings` (OLD=NEW on a path prefix)
+pub fn import(client: Client, option: &RepToolOption, input_dir: &Path, output_dir: &Path, mappings: &[String]) -> Result<RunSummary> {
+    let rules = mappings.iter().map(|mapping| Rule::parse(mapping)).collect::<Result<Vec<_>>>()?;
+    let options = ReplaceOptions { rules: &rules, patterns: &[], encoding: Encoding::Utf8, only_from: &[], base_only: false, all_occurrences: false, anchor: Anchor::Prefix, ignore_case: false, path_style: None, normalize: None };
+    let torrents = match client {
+        Client::Qbittorrent => qbittorrent::read_session(input_dir, option.global.io_buffer)?,
+        Client::Transmission => transmission::read_session(input_dir, option.global.io_buffer)?,
+    };
+
+    let mut file_reports = Vec::new();
+    for torrent in torrents {
+        let (source, torrent) = match torrent {
+            Ok(torrent) => (torrent.source.display().to_string(), torrent),
+            Err((source, err)) => {
+                warn!("Skipped {}: {:#}", source.display(), err);
+                file_reports.push(FileReport { file: source.display().to_string(), error: Some(format!("{:#}", err)), ..Default::default() });
+                continue;
+            }
+        };
+        match rtorrent::write_session(&torrent, &options, option, output_dir) {
+            Ok(Some(modifications)) => file_reports.push(FileReport { file: source, matched: true, modifications, ..Default::default() }),
+            Ok(None) => file_reports.push(FileReport { file: source, ..Default::default() }),
+            Err(err) => {
+                warn!("Skipped {}: {:#}", source, err);
+                file_reports.push(FileReport { file: source, error: Some(format!("{:#}", err)), ..Default::default() });
+            }
+        }
+    }
+
+    Ok(RunSummary::new(file_reports))
+}
+
+/// A read session entry, or the file that could not be read and why
+type ReadResult = std::result::Result<ForeignTorrent, (PathBuf, anyhow::Error)>;
+
+fn integer(dict: &BTreeMap<Vec<u8>, Value>, key: &str) -> i64 {
+    match dict.get(key.as_bytes()) {
+        Some(Value::Integer(integer)) => *integer,
+        _ => 0,
+    }
+}
+
+fn text(dict: &BTreeMap<Vec<u8>, Value>, key: &str) -> String {
+    match dict.get(key.as_bytes()) {
+        Some(Value::Bytes(bytes)) => String::from_utf8_lossy(bytes).into_owned(),
+        _ => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use clap::Parser;
+    use reptool_core::{bencode, InfoHash};
+
+    use super::*;
+
+    /// A .torrent of two files `pay/a` (3 bytes) and `pay/b` (2 bytes) in pieces of 4 bytes
+    pub fn multi_file_torrent() -> Vec<u8> {
+        let file = |name: &str, length: i64| Value::Dict(BTreeMap::from([
+            (b"length".to_vec(), Value::Integer(length)),
+            (b"path".to_vec(), Value::List(vec![Value::Bytes(name.as_bytes().to_vec())])),
+        ]));
+        let info = BTreeMap::from([
+            (b"files".to_vec(), Value::List(vec![file("a", 3), file("b", 2)])),
+            (b"name".to_vec(), Value::Bytes(b"pay".to_vec())),
+            (b"piece length".to_vec(), Value::Integer(4)),
+            (b"pieces".to_vec(), Value::Bytes(vec![0; 40])),
+        ]);
+        bencode::encode(&Value::Dict(BTreeMap::from([(b"info".to_vec(), Value::Dict(info))])))
+    }
+
+    /// A new empty directory named after `name` in the temporary directory
+    pub fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("reptool-test-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn dict(entries: &[(&str, Value)]) -> Value {
+        Value::Dict(entries.iter().map(|(key, value)| (key.as_bytes().to_vec(), value.clone())).collect())
+    }
+
+    /// The .rtorrent and .libtorrent_resume written for the torrent of `multi_file_torrent`
+    fn read_rtorrent_session(output_dir: &Path) -> (Value, Value) {
+        let hash = InfoHash::of_torrent(&multi_file_torrent()).unwrap().v1.unwrap();
+        let read = |suffix: &str| bencode::decode(&fs::read(output_dir.join(format!("{}.torrent{}", hash, suffix))).unwrap()).unwrap();
+        assert_eq!(read(""), bencode::decode(&multi_file_torrent()).unwrap());
+        (read(".rtorrent"), read(".libtorrent_resume"))
+    }
+
+    #[test]
+    fn import_transmission_writes_an_rtorrent_session() {
+        let dir = temp_dir("transmission");
+        let (config_dir, output_dir, data_dir) = (dir.join("transmission"), dir.join("session"), dir.join("data"));
+        fs::create_dir_all(config_dir.join("resume")).unwrap();
+        fs::create_dir_all(config_dir.join("torrents")).unwrap();
+        fs::create_dir_all(data_dir.join("pay")).unwrap();
+        fs::write(data_dir.join("pay").join("a"), b"aaa").unwrap();
+        fs::write(data_dir.join("pay").join("b"), b"bb").unwrap();
+        fs::write(config_dir.join("torrents").join("x.torrent"), multi_file_torrent()).unwrap();
+        fs::write(config_dir.join("resume").join("x.resume"), bencode::encode(&dict(&[
+            ("destination", Value::Bytes(b"/downloads".to_vec())),
+            ("labels", Value::List(vec![Value::Bytes(b"tv shows".to_vec())])),
+            ("paused", Value::Integer(1)),
+            ("progress", dict(&[("have", Value::Bytes(b"all".to_vec()))])),
+            ("uploaded-ever", Value::Integer(5)),
+        ]))).unwrap();
+        let option = RepToolOption::parse_from(["rtorrent_status_file_modifier"]);
+
+        let summary = import(Client::Transmission, &option, &config_dir, &output_dir, &[format!("/downloads={}", data_dir.display())]).unwrap();
+        let (rtorrent, resume) = read_rtorrent_session(&output_dir);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(summary.files_failed, 0);
+        let rtorrent = rtorrent.as_dict().unwrap();
+        assert_eq!(text(rtorrent, "directory"), data_dir.join("pay").display().to_string());
+        assert_eq!(text(rtorrent, "custom1"), "tv%20shows");
+        assert_eq!((integer(rtorrent, "complete"), integer(rtorrent, "chunks_done")), (1, 2));
+        assert_eq!((integer(rtorrent, "state"), integer(rtorrent, "total_uploaded")), (0, 5));
+        // The data is in place, rtorrent takes it without hashing
+        assert_eq!(integer(resume.as_dict().unwrap(), "bitfield"), 2);
+    }
+
+    #[test]
+    fn import_qbittorrent_keeps_an_incomplete_torrent_for_hashing() {
+        let dir = temp_dir("qbittorrent-import");
+        let (backup_dir, output_dir) = (dir.join("BT_backup"), dir.join("session"));
+        fs::create_dir_all(&backup_dir).unwrap();
+        fs::write(backup_dir.join("x.torrent"), multi_file_torrent()).unwrap();
+        fs::write(backup_dir.join("x.fastresume"), bencode::encode(&dict(&[
+            ("pieces", Value::Bytes(vec![1, 0])),
+            ("qBt-category", Value::Bytes(b"movies".to_vec())),
+            ("qBt-savePath", Value::Bytes(b"/downloads/".to_vec())),
+            ("save_path", Value::Bytes(b"/ignored".to_vec())),
+        ]))).unwrap();
+        let option = RepToolOption::parse_from(["rtorrent_status_file_modifier"]);
+
+        let summary = import(Client::Qbittorrent, &option, &backup_dir, &output_dir, &[]).unwrap();
+        let (rtorrent, resume) = read_rtorrent_session(&output_dir);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(summary.files_failed, 0);
+        let rtorrent = rtorrent.as_dict().unwrap();
+        assert_eq!(text(rtorrent, "directory"), "/downloads/pay");
+        assert_eq!(text(rtorrent, "custom1"), "movies");
+        assert_eq!((integer(rtorrent, "complete"), integer(rtorrent, "state")), (0, 1));
+        let resume = resume.as_dict().unwrap();
+        assert!(!resume.contains_key(b"bitfield".as_slice()));
+        assert_eq!(resume.get(b"files".as_slice()).and_then(|files| match files {
+            Value::List(files) => Some(files.len()),
+            _ => None,
+        }), Some(2));
+    }
+}
@@ -0,0 +1,226 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use reptool_core::bencode::{self, Value};
+use reptool_core::file::{read_buffered, write_atomic};
+use reptool_core::report::{FileReport, RunSummary};
+use reptool_core::{InfoHash, SessionFile};
+use tracing::{info, warn};
+
+use super::{integer, text, ForeignTorrent, ReadResult};
+use crate::glob::FileMatcher;
+use crate::session::group_companions;
+use crate::{label, list_files, RepToolOption};
+
+/// A libtorrent fastresume as qBittorrent 4.x writes it to BT_backup
+pub fn export(matcher: &FileMatcher, option: &RepToolOption, output_dir: &Path) -> Result<RunSummary> {
+    if !option.global.dry_run {
+        fs::create_dir_all(output_dir).with_context(|| format!("Failed to create output directory: {:?}", output_dir))?;
+    }
+
+    let file_paths = list_files(matcher, option.input_dir()?, option.max_depth())?;
+    let mut file_reports = Vec::new();
+    for companions in group_companions(&file_paths).into_values() {
+        let (Some(torrent_path), Some(rtorrent_path)) = (companions.torrent, companions.rtorrent) else {
+            continue;
+        };
+
+        let file = rtorrent_path.display().to_string();
+        match export_torrent(&torrent_path, &rtorrent_path, option, output_dir) {
+            Ok(Some(fastresume_path)) => {
+                if option.global.dry_run && !option.global.json {
+                    println!("{}: would write {}", file, fastresume_path.display());
+                }
+                file_reports.push(FileReport { file, matched: true, ..Default::default() });
+            }
+            Ok(None) => file_reports.push(FileReport { file, ..Default::default() }),
+            Err(err) => {
+                warn!("Skipped {}: {:#}", file, err);
+                file_reports.push(FileReport { file, error: Some(format!("{:#}", err)), ..Default::default() });
+            }
+        }
+    }
+
+    Ok(RunSummary::new(file_reports))
+}
+
+/// Write `<hash>.torrent` and `<hash>.fastresume`, `None` when qBittorrent already has the torrent
+fn export_torrent(torrent_path: &Path, rtorrent_path: &Path, option: &RepToolOption, output_dir: &Path) -> Result<Option<PathBuf>> {
+    let torrent_data = read_buffered(torrent_path, option.global.io_buffer).with_context(|| format!("Failed to read file: {:?}", torrent_path))?;
+    let info_hash = InfoHash::of_torrent(&torrent_data).with_context(|| format!("Failed to hash file: {:?}", torrent_path))?;
+    // qBittorrent names v2 only torrents after their v2 hash cut to 40 digits
+    let hash = info_hash.v1.or(info_hash.v2.map(|v2| v2[..40].to_owned())).context("Missing info-hash")?.to_lowercase();
+    let torrent = bencode::decode(&torrent_data).with_context(|| format!("Failed to decode file: {:?}", torrent_path))?;
+    let session_file = SessionFile::open(rtorrent_path, option.global.io_buffer).with_context(|| format!("Failed to open file: {:?}", rtorrent_path))?;
+
+    let fastresume_path = output_dir.join(format!("{}.fastresume", hash));
+    if fastresume_path.exists() {
+        warn!("Skipped {}: {} already exists", rtorrent_path.display(), fastresume_path.display());
+        return Ok(None);
+    }
+
+    let fastresume = fastresume(&hash, &torrent, &session_file, option)?;
+    if !option.global.dry_run {
+        fs::copy(torrent_path, output_dir.join(format!("{}.torrent", hash))).with_context(|| format!("Failed to copy file {:?}", torrent_path))?;
+        write_atomic(&fastresume_path, &bencode::encode(&fastresume), option.global.io_buffer)?;
+        info!("Wrote {}", fastresume_path.display());
+    }
+
+    Ok(Some(fastresume_path))
+}
+
+fn fastresume(hash: &str, torrent: &Value, session_file: &SessionFile, option: &RepToolOption) -> Result<Value> {
+    let info = torrent.as_dict().and_then(|torrent| torrent.get(b"info".as_slice())).and_then(Value::as_dict).context("Missing info dictionary")?;
+    let text = |key: &str| match session_file.get(key) {
+        Some(Value::Bytes(bytes)) => option.global.encoding.decode(bytes).unwrap_or_else(|_| String::from_utf8_lossy(bytes).into_owned()),
+        _ => String::new(),
+    };
+    let integer = |key: &str| match session_file.get(key) {
+        Some(Value::Integer(integer)) => *integer,
+        _ => 0,
+    };
+
+    // rtorrent keeps the directory of a multi file torrent with its name, qBittorrent the directory holding it
+    let directory = text("directory");
+    let name = match info.get(b"name".as_slice()) {
+        Some(Value::Bytes(name)) => String::from_utf8_lossy(name).into_owned(),
+        _ => String::new(),
+    };
+    let directory_path = Path::new(&directory);
+    let save_path = match directory_path.parent() {
+        Some(parent) if info.contains_key(b"files".as_slice()) && directory_path.file_name().is_some_and(|file_name| *file_name == *name) => parent.display().to_string(),
+        _ => directory.clone(),
+    };
+
+    let is_complete = integer("complete") != 0;
+    let label = match session_file.get("custom1") {
+        Some(Value::Bytes(label)) => label::decode(label),
+        _ => String::new(),
+    };
+    let bytes = |text: &str| Value::Bytes(text.as_bytes().to_vec());
+    let hex_hash = (0..hash.len()).step_by(2).map(|index| u8::from_str_radix(&hash[index..index + 2], 16)).collect::<Result<Vec<u8>, _>>()?;
+
+    let mut fastresume: BTreeMap<Vec<u8>, Value> = BTreeMap::new();
+    let mut set = |key: &str, value: Value| {
+        fastresume.insert(key.as_bytes().to_vec(), value);
+    };
+    set("file-format", bytes("libtorrent resume file"));
+    set("file-version", Value::Integer(1));
+    set("info-hash", Value::Bytes(hex_hash));
+    set("save_path", bytes(&save_path));
+    set("qBt-savePath", bytes(&save_path));
+    set("qBt-category", bytes(&label));
+    set("qBt-tags", Value::List(Vec::new()));
+    set("qBt-name", bytes(""));
+    set("qBt-ratioLimit", Value::Integer(-2000));
+    set("qBt-seedingTimeLimit", Value::Integer(-2));
+    set("qBt-firstLastPiecePriority", Value::Integer(0));
+    set("qBt-queuePosition", Value::Integer(-1));
+    set("qBt-hasRootFolder", Value::Integer(i64::from(info.contains_key(b"files".as_slice()))));
+    // rtorrent writes state 1 for started torrents
+    set("paused", Value::Integer(i64::from(integer("state") == 0)));
+    set("auto_managed", Value::Integer(0));
+    set("total_uploaded", Value::Integer(integer("total_uploaded")));
+    set("total_downloaded", Value::Integer(integer("total_downloaded")));
+    set("added_time", Value::Integer(integer("timestamp.started")));
+    set("completed_time", Value::Integer(integer("timestamp.finished")));
+    // A complete torrent is taken as is and only verified piece by piece while seeding, instead of a full recheck
+    if let (true, Some(Value::Bytes(pieces))) = (is_complete, info.get(b"pieces".as_slice())) {
+        set("pieces", Value::Bytes(vec![1; pieces.len() / 20]));
+        set("seed_mode", Value::Integer(1));
+    }
+
+    Ok(Value::Dict(fastresume))
+}
+
+/// Every `<hash>.fastresume` of a BT_backup directory with its `<hash>.torrent`
+pub fn read_session(dir: &Path, io_buffer: usize) -> Result<Vec<ReadResult>> {
+    let mut fastresume_paths: Vec<PathBuf> = fs::read_dir(dir).with_context(|| format!("Failed to read input directory: {:?}", dir))?
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|path| path.extension().is_some_and(|extension| extension == "fastresume"))
+        .collect();
+    fastresume_paths.sort();
+
+    Ok(fastresume_paths.into_iter()
+        .map(|fastresume_path| read_torrent(&fastresume_path, io_buffer).map_err(|err| (fastresume_path, err)))
+        .collect())
+}
+
+fn read_torrent(fastresume_path: &Path, io_buffer: usize) -> Result<ForeignTorrent> {
+    let fastresume = bencode::decode(&read_buffered(fastresume_path, io_buffer)?).context("Failed to decode fastresume")?;
+    let fastresume = fastresume.as_dict().context("Fastresume is not a dictionary")?;
+    // Magnets without metadata yet have no .torrent
+    let torrent_path = fastresume_path.with_extension("torrent");
+    let torrent_data = read_buffered(&torrent_path, io_buffer).with_context(|| format!("Failed to read file: {:?}", torrent_path))?;
+
+    // The save path of a torrent in automatic mode is only in save_path
+    let save_path = match text(fastresume, "qBt-savePath") {
+        save_path if save_path.is_empty() => text(fastresume, "save_path"),
+        save_path => save_path,
+    };
+    let is_complete = integer(fastresume, "seed_mode") != 0 || match fastresume.get(b"pieces".as_slice()) {
+        Some(Value::Bytes(pieces)) => !pieces.is_empty() && pieces.iter().all(|piece| piece & 1 == 1),
+        _ => false,
+    };
+
+    Ok(ForeignTorrent {
+        source: fastresume_path.to_path_buf(),
+        torrent_data,
+        save_path,
+        label: text(fastresume, "qBt-category"),
+        is_complete,
+        is_paused: integer(fastresume, "paused") != 0,
+        uploaded: integer(fastresume, "total_uploaded"),
+        downloaded: integer(fastresume, "total_downloaded"),
+        added_time: integer(fastresume, "added_time"),
+        completed_time: integer(fastresume, "completed_time"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+    use crate::clients::tests::{multi_file_torrent, temp_dir};
+
+    #[test]
+    fn export_writes_a_fastresume_qbittorrent_reads_back() {
+        let dir = temp_dir("qbittorrent");
+        let (session_dir, output_dir) = (dir.join("session"), dir.join("BT_backup"));
+        fs::create_dir_all(&session_dir).unwrap();
+        fs::create_dir_all(&output_dir).unwrap();
+        let torrent_data = multi_file_torrent();
+        let hash = InfoHash::of_torrent(&torrent_data).unwrap().v1.unwrap();
+        let torrent_path = session_dir.join(format!("{}.torrent", hash));
+        let rtorrent_path = session_dir.join(format!("{}.torrent.rtorrent", hash));
+        fs::write(&torrent_path, &torrent_data).unwrap();
+        fs::write(&rtorrent_path, b"d8:completei1e7:custom16:movies9:directory9:/data/pay5:statei1e14:total_uploadedi42ee").unwrap();
+        let option = RepToolOption::parse_from(["rtorrent_status_file_modifier"]);
+
+        let fastresume_path = export_torrent(&torrent_path, &rtorrent_path, &option, &output_dir).unwrap().unwrap();
+        let fastresume = bencode::decode(&fs::read(&fastresume_path).unwrap()).unwrap();
+        let session = read_session(&output_dir, 0).unwrap();
+        let is_exported_twice = export_torrent(&torrent_path, &rtorrent_path, &option, &output_dir).unwrap().is_some();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(fastresume_path.file_name().unwrap().to_str().unwrap(), format!("{}.fastresume", hash.to_lowercase()));
+        let fastresume = fastresume.as_dict().unwrap();
+        for key in ["file-format", "info-hash", "save_path", "qBt-savePath", "qBt-category", "paused", "pieces", "seed_mode"] {
+            assert!(fastresume.contains_key(key.as_bytes()), "{}", key);
+        }
+        // The directory rtorrent named after the torrent is the save path of its parent for qBittorrent
+        assert_eq!(text(fastresume, "save_path"), "/data");
+        assert_eq!(fastresume.get(b"pieces".as_slice()), Some(&Value::Bytes(vec![1; 2])));
+
+        let [Ok(torrent)] = session.as_slice() else {
+            panic!("Expected one torrent, read {}", session.len());
+        };
+        assert_eq!((torrent.save_path.as_str(), torrent.label.as_str()), ("/data", "movies"));
+        assert!(torrent.is_complete && !torrent.is_paused);
+        assert_eq!((torrent.uploaded, torrent.torrent_data.as_slice()), (42, torrent_data.as_slice()));
+        assert!(!is_exported_twice);
+    }
+}
@@ -0,0 +1,131 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use reptool_core::bencode::{self, Value};
+use reptool_core::file::write_atomic;
+use reptool_core::{InfoHash, ModifyReport, ReplaceOptions};
+use tracing::{info, warn};
+
+use super::ForeignTorrent;
+use crate::{label, resume, RepToolOption};
+
+/// Write the `<HASH>.torrent` triplet of `torrent` to the rtorrent session directory `output_dir`.
+/// The directory change made by the mapping rules is returned, `None` when rtorrent already has the torrent.
+pub fn write_session(torrent: &ForeignTorrent, options: &ReplaceOptions, option: &RepToolOption, output_dir: &Path) -> Result<Option<Vec<ModifyReport>>> {
+    let hash = InfoHash::of_torrent(&torrent.torrent_data)?.v1.context("rtorrent does not support v2 only torrents")?;
+    let torrent_path = output_dir.join(format!("{}.torrent", hash));
+    if torrent_path.exists() {
+        warn!("Skipped {}: {} already exists", torrent.source.display(), torrent_path.display());
+        return Ok(None);
+    }
+
+    let metainfo = bencode::decode(&torrent.torrent_data).context("Failed to decode the .torrent")?;
+    let info = metainfo.as_dict().and_then(|metainfo| metainfo.get(b"info".as_slice())).and_then(Value::as_dict).context("Missing info dictionary")?;
+    let name = match info.get(b"name".as_slice()) {
+        Some(Value::Bytes(name)) => String::from_utf8_lossy(name).into_owned(),
+        _ => hash.clone(),
+    };
+
+    let mut modifications = Vec::new();
+    let save_path = match options.apply(&torrent.save_path) {
+        Some(save_path) => {
+            modifications.push(ModifyReport {
+                key: String::from("directory"),
+                old_length: torrent.save_path.len(),
+                new_length: save_path.len(),
+                old_value: torrent.save_path.clone(),
+                new_value: save_path.clone(),
+            });
+            save_path
+        }
+        None => torrent.save_path.clone(),
+    };
+    // rtorrent keeps the directory of a multi file torrent with its name
+    let directory = match info.get(b"files".as_slice()) {
+        Some(_) => Path::new(&save_path).join(&name).display().to_string(),
+        None => save_path,
+    };
+
+    let rtorrent = session(&hash, &directory, info, torrent, option)?;
+    // The data of a complete torrent is usually in place already, spare rtorrent the hash check when it is
+    let resume = if torrent.is_complete {
+        resume::data_path_of(info, Path::new(&directory))
+            .and_then(|data_path| resume::payload_files(info, &data_path))
+            .and_then(|files| resume::fast_resume(info, &files, Some(&empty_resume(info))))
+            .unwrap_or_else(|err| {
+                info!("{}: rtorrent will hash the data, {:#}", torrent.source.display(), err);
+                empty_resume(info)
+            })
+    } else {
+        empty_resume(info)
+    };
+    if option.global.dry_run {
+        if !option.global.json {
+            println!("{}: would write {} in {}", torrent.source.display(), hash, directory);
+        }
+        return Ok(Some(modifications));
+    }
+
+    fs::create_dir_all(output_dir).with_context(|| format!("Failed to create output directory: {:?}", output_dir))?;
+    write_atomic(&output_dir.join(format!("{}.torrent.rtorrent", hash)), &bencode::encode(&rtorrent), option.global.io_buffer)?;
+    write_atomic(&output_dir.join(format!("{}.torrent.libtorrent_resume", hash)), &bencode::encode(&resume), option.global.io_buffer)?;
+    // The .torrent last, it is what rtorrent loads the others by
+    write_atomic(&torrent_path, &torrent.torrent_data, option.global.io_buffer)?;
+    info!("Wrote {}", torrent_path.display());
+
+    Ok(Some(modifications))
+}
+
+/// The .torrent.rtorrent, with every key rtorrent writes itself
+fn session(hash: &str, directory: &str, info: &BTreeMap<Vec<u8>, Value>, torrent: &ForeignTorrent, option: &RepToolOption) -> Result<Value> {
+    let chunks = match info.get(b"pieces".as_slice()) {
+        Some(Value::Bytes(pieces)) => (pieces.len() / 20) as i64,
+        _ => 0,
+    };
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+    // The tracker key only has to be stable per torrent
+    let key = i64::from_str_radix(&hash[..8], 16)?;
+    let bytes = |text: &str| Value::Bytes(text.as_bytes().to_vec());
+
+    let mut session = BTreeMap::new();
+    let mut set = |key: &str, value: Value| {
+        session.insert(key.as_bytes().to_vec(), value);
+    };
+    set("chunks_done", Value::Integer(if torrent.is_complete { chunks } else { 0 }));
+    set("chunks_wanted", Value::Integer(0));
+    set("complete", Value::Integer(i64::from(torrent.is_complete)));
+    set("custom1", Value::Bytes(label::encode(&torrent.label)));
+    for custom in ["custom2", "custom3", "custom4", "custom5"] {
+        set(custom, bytes(""));
+    }
+    set("directory", Value::Bytes(option.global.encoding.encode(directory)?));
+    set("hashing", Value::Integer(0));
+    set("ignore_commands", Value::Integer(0));
+    set("key", Value::Integer(key));
+    set("priority", Value::Integer(2));
+    set("state", Value::Integer(i64::from(!torrent.is_paused)));
+    set("state_changed", Value::Integer(now));
+    set("state_counter", Value::Integer(1));
+    set("throttle_name", bytes(""));
+    set("tied_to_file", bytes(""));
+    set("timestamp.finished", Value::Integer(torrent.completed_time));
+    set("timestamp.started", Value::Integer(if torrent.added_time > 0 { torrent.added_time } else { now }));
+    set("total_downloaded", Value::Integer(torrent.downloaded));
+    set("total_uploaded", Value::Integer(torrent.uploaded));
+    set("views", Value::List(Vec::new()));
+
+    Ok(Value::Dict(session))
+}
+
+/// A .libtorrent_resume without piece state, rtorrent hashes the data once on first load
+fn empty_resume(info: &BTreeMap<Vec<u8>, Value>) -> Value {
+    let file_count = match info.get(b"files".as_slice()) {
+        Some(Value::List(files)) => files.len(),
+        _ => 1,
+    };
+    let file = Value::Dict(BTreeMap::from([(b"priority".to_vec(), Value::Integer(1))]));
+    Value::Dict(BTreeMap::from([(b"files".to_vec(), Value::List(vec![file; file_count]))]))
+}
@@ -0,0 +1,57 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use reptool_core::bencode::{self, Value};
+use reptool_core::file::read_buffered;
+
+use super::{integer, text, ForeignTorrent, ReadResult};
+
+/// Every `resume/<name>.resume` with its `torrents/<name>.torrent`, `dir` is the config directory or resume/ itself
+pub fn read_session(dir: &Path, io_buffer: usize) -> Result<Vec<ReadResult>> {
+    let resume_dir = if dir.join("resume").is_dir() { dir.join("resume") } else { dir.to_path_buf() };
+    let torrents_dir = resume_dir.parent().map(|config_dir| config_dir.join("torrents")).context("Missing torrents directory")?;
+
+    let mut resume_paths: Vec<PathBuf> = fs::read_dir(&resume_dir).with_context(|| format!("Failed to read input directory: {:?}", resume_dir))?
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|path| path.extension().is_some_and(|extension| extension == "resume"))
+        .collect();
+    resume_paths.sort();
+
+    Ok(resume_paths.into_iter()
+        .map(|resume_path| read_torrent(&resume_path, &torrents_dir, io_buffer).map_err(|err| (resume_path, err)))
+        .collect())
+}
+
+fn read_torrent(resume_path: &Path, torrents_dir: &Path, io_buffer: usize) -> Result<ForeignTorrent> {
+    let resume = bencode::decode(&read_buffered(resume_path, io_buffer)?).context("Failed to decode resume file")?;
+    let resume = resume.as_dict().context("Resume data is not a dictionary")?;
+    let torrent_path = torrents_dir.join(resume_path.with_extension("torrent").file_name().context("Missing file name")?);
+    let torrent_data = read_buffered(&torrent_path, io_buffer).with_context(|| format!("Failed to read file: {:?}", torrent_path))?;
+
+    // Labels came with Transmission 3, the first one becomes the rtorrent label
+    let label = match resume.get(b"labels".as_slice()) {
+        Some(Value::List(labels)) => labels.iter().find_map(|label| match label {
+            Value::Bytes(label) => Some(String::from_utf8_lossy(label).into_owned()),
+            _ => None,
+        }).unwrap_or_default(),
+        _ => String::new(),
+    };
+    let is_complete = match resume.get(b"progress".as_slice()).and_then(Value::as_dict) {
+        Some(progress) => matches!(progress.get(b"have".as_slice()), Some(Value::Bytes(have)) if have == b"all"),
+        None => false,
+    };
+
+    Ok(ForeignTorrent {
+        source: resume_path.to_path_buf(),
+        torrent_data,
+        save_path: text(resume, "destination"),
+        label,
+        is_complete,
+        is_paused: integer(resume, "paused") != 0,
+        uploaded: integer(resume, "uploaded-ever"),
+        downloaded: integer(resume, "downloaded-ever"),
+        added_time: integer(resume, "added-date"),
+        completed_time: integer(resume, "done-date"),
+    })
+}
@@ -0,0 +1,75 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use clap::parser::ValueSource;
+use clap::ArgMatches;
+use serde::Deserialize;
+
+use crate::RepToolOption;
+
+/// Defaults of the replace command read at startup, every one of them is overridden by its command line flag
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    /// Session directory used when no input path is given
+    pub session_path: Option<String>,
+    /// Replaces the --keyword list
+    pub keywords: Option<Vec<String>>,
+    /// Used when no --extensions is given
+    pub extensions: Option<Vec<String>>,
+    /// Used when no --output-path is given
+    pub output_path: Option<String>,
+    /// OLD=NEW pairs, only used when the command line gives no search string, -e, --map-file, container mappings or --normalize
+    pub mappings: Vec<String>,
+}
+
+/// `$XDG_CONFIG_HOME/reptool/config.toml`, falling back to `~/.config/reptool/config.toml`
+pub fn default_path() -> Option<PathBuf> {
+    let config_home = env::var_os("XDG_CONFIG_HOME").filter(|dir| !dir.is_empty()).map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+    Some(config_home.join("reptool").join("config.toml"))
+}
+
+/// Read `path`, or the default config when none is given, a missing default config is an empty one
+pub fn load(path: Option<&Path>) -> Result<Config> {
+    let path = match path {
+        Some(path) => path.to_owned(),
+        None => match default_path() {
+            Some(path) if path.is_file() => path,
+            _ => return Ok(Config::default()),
+        },
+    };
+
+    let content = fs::read_to_string(&path).with_context(|| format!("Failed to read config: {:?}", path))?;
+    toml::from_str(&content).with_context(|| format!("Failed to parse config: {:?}", path))
+}
+
+impl Config {
+    /// Fill in what the command line left out
+    pub fn apply(&self, option: &mut RepToolOption, matches: &ArgMatches) {
+        if let (None, Some(session_path)) = (&option.replace.input_path, &self.session_path) {
+            option.replace.input_path = Some(session_path.clone());
+        }
+        if let (Some(keywords), false) = (&self.keywords, is_from_command_line(matches, "keywords")) {
+            option.global.keywords = keywords.clone();
+        }
+        if let (true, Some(extensions)) = (option.global.extensions.is_empty(), &self.extensions) {
+            option.global.extensions = extensions.clone();
+        }
+        if let (true, Some(output_path)) = (option.replace.output_path.is_empty(), &self.output_path) {
+            option.replace.output_path = output_path.clone();
+        }
+        let has_rules = option.replace.search_string.is_some() || !option.replace.mapping.is_empty() || option.replace.map_file.is_some() || option.replace.normalize || option.replace.map_to.is_some() || option.replace.rules_file.is_some() || option.replace.script_file.is_some();
+        if !has_rules {
+            option.replace.mapping = self.mappings.clone();
+        }
+    }
+}
+
+/// Whether `id` was given on the command line, global flags may come before or after the subcommand
+fn is_from_command_line(matches: &ArgMatches, id: &str) -> bool {
+    let is_explicit = |matches: &ArgMatches| matches!(matches.value_source(id), Some(ValueSource::CommandLine));
+    is_explicit(matches) || matches.subcommand().is_some_and(|(_, matches)| is_explicit(matches))
+}
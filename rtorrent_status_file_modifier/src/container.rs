@@ -0,0 +1,202 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use reptool_core::Rule;
+use serde_yaml_ng::Value;
+
+/// Which view of the data the session values are rewritten to
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    Host,
+    Container,
+}
+
+/// One bind mount, the same data seen from the host and from inside the container
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mount {
+    pub host: String,
+    pub container: String,
+}
+
+impl Mount {
+    /// Parse `HOST:CONTAINER`, a trailing `:ro` or `:rw` like in docker run -v is ignored
+    pub fn parse(mapping: &str) -> Result<Self> {
+        let mut parts = mapping.split(':');
+        let (Some(host), Some(container)) = (parts.next(), parts.next()) else {
+            bail!("Missing : in container mapping {:?}, expected HOST:CONTAINER", mapping);
+        };
+        if host.is_empty() || container.is_empty() || parts.count() > 1 {
+            bail!("Invalid container mapping {:?}, expected HOST:CONTAINER", mapping);
+        }
+        Ok(Mount { host: host.trim_end_matches('/').to_owned(), container: container.trim_end_matches('/').to_owned() })
+    }
+}
+
+/// The bind mounts of the services of a docker compose file, only those of `service` when given.
+/// Named volumes are skipped, relative host paths are resolved against the directory of the compose file.
+pub fn read_compose(compose_path: &Path, service: Option<&str>) -> Result<Vec<Mount>> {
+    let content = fs::read_to_string(compose_path).with_context(|| format!("Failed to read compose file: {:?}", compose_path))?;
+    let compose: Value = serde_yaml_ng::from_str(&content).with_context(|| format!("Failed to parse compose file: {:?}", compose_path))?;
+    let services = compose.get("services").and_then(Value::as_mapping).with_context(|| format!("No services in {:?}", compose_path))?;
+    let base = compose_path.parent().unwrap_or(Path::new("."));
+
+    let mut mounts = Vec::new();
+    let mut is_service_found = false;
+    for (name, definition) in services {
+        if service.is_some_and(|service| name.as_str() != Some(service)) {
+            continue;
+        }
+        is_service_found = true;
+        let Some(volumes) = definition.get("volumes").and_then(Value::as_sequence) else {
+            continue;
+        };
+        for volume in volumes {
+            // Short syntax `./data:/data:ro`, or long syntax with type, source and target
+            let (source, target) = match volume {
+                Value::String(volume) => {
+                    let mount = Mount::parse(volume).with_context(|| format!("In {:?}", compose_path))?;
+                    (mount.host, mount.container)
+                }
+                Value::Mapping(_) if volume.get("type").and_then(Value::as_str).is_none_or(|kind| kind == "bind") => {
+                    let (Some(source), Some(target)) = (volume.get("source").and_then(Value::as_str), volume.get("target").and_then(Value::as_str)) else {
+                        continue;
+                    };
+                    (source.trim_end_matches('/').to_owned(), target.trim_end_matches('/').to_owned())
+                }
+                _ => continue,
+            };
+            let host = match source.as_str() {
+                source if source.starts_with('/') => source.to_owned(),
+                source if source.starts_with('.') => base.join(source.trim_start_matches("./")).display().to_string(),
+                // A named volume, its host path belongs to docker
+                _ => continue,
+            };
+            let mount = Mount { host, container: target };
+            if !mounts.contains(&mount) {
+                mounts.push(mount);
+            }
+        }
+    }
+    if let (Some(service), false) = (service, is_service_found) {
+        bail!("No service {:?} in {:?}", service, compose_path);
+    }
+
+    Ok(mounts)
+}
+
+/// The rules rewriting the values from the other side to `to`, the most specific mount first so a mount nested
+/// in another one wins. Fails when a path is mapped two ways or when a mounted path of the `check` side is missing.
+pub fn rules(mounts: &[Mount], to: Side, check: Side) -> Result<Vec<Rule>> {
+    for mount in mounts {
+        let (path, side) = match check {
+            Side::Host => (&mount.host, "host"),
+            Side::Container => (&mount.container, "container"),
+        };
+        if !Path::new(path).exists() {
+            bail!("The {} path {:?} of the container mapping {}:{} does not exist, see --check-side", side, path, mount.host, mount.container);
+        }
+    }
+
+    let mut rules: Vec<Rule> = mounts.iter().map(|mount| match to {
+        Side::Container => Rule { find: mount.host.clone(), replace: mount.container.clone(), ignore_case: false },
+        Side::Host => Rule { find: mount.container.clone(), replace: mount.host.clone(), ignore_case: false },
+    }).collect();
+    for (index, rule) in rules.iter().enumerate() {
+        if let Some(other) = rules[..index].iter().find(|other| other.find == rule.find && other.replace != rule.replace) {
+            bail!("{:?} is mapped to both {:?} and {:?}, pick one with --compose-service", rule.find, other.replace, rule.replace);
+        }
+    }
+    rules.sort_by_key(|rule| std::cmp::Reverse(rule.find.len()));
+    rules.dedup();
+
+    Ok(rules)
+}
+
+#[cfg(test)]
+mod tests {
+    use reptool_core::{Anchor, Encoding, ReplaceOptions};
+
+    use super::*;
+
+    fn mount(host: &str, container: &str) -> Mount {
+        Mount { host: host.to_owned(), container: container.to_owned() }
+    }
+
+    #[test]
+    fn parse_reads_docker_volume_syntax() {
+        assert_eq!(Mount::parse("/srv/data/:/data").unwrap(), mount("/srv/data", "/data"));
+        assert_eq!(Mount::parse("/srv/data:/data:ro").unwrap(), mount("/srv/data", "/data"));
+        assert!(Mount::parse("/srv/data").is_err());
+        assert!(Mount::parse(":/data").is_err());
+        assert!(Mount::parse("/a:/b:ro:x").is_err());
+    }
+
+    #[test]
+    fn rules_map_the_longest_prefix_first() {
+        let dir = std::env::temp_dir().join(format!("reptool-test-container-{}", std::process::id()));
+        fs::create_dir_all(dir.join("movies")).unwrap();
+        let host = dir.display().to_string();
+        let mounts = [mount(&host, "/data"), mount(&format!("{}/movies", host), "/movies")];
+        let to_container = rules(&mounts, Side::Container, Side::Host);
+        let to_host = rules(&mounts, Side::Host, Side::Host);
+        let missing = rules(&[mount("/reptool-missing", "/data")], Side::Container, Side::Host);
+        fs::remove_dir_all(&dir).unwrap();
+
+        let to_container = to_container.unwrap();
+        let options = ReplaceOptions {
+            rules: &to_container,
+            patterns: &[],
+            encoding: Encoding::Utf8,
+            only_from: &[],
+            base_only: false,
+            all_occurrences: false,
+            anchor: Anchor::Prefix,
+            ignore_case: false,
+            path_style: None,
+            normalize: None,
+        };
+        assert_eq!(options.apply(&format!("{}/movies/x", host)).as_deref(), Some("/movies/x"));
+        assert_eq!(options.apply(&format!("{}/tv/x", host)).as_deref(), Some("/data/tv/x"));
+
+        let to_host = to_host.unwrap();
+        assert_eq!(to_host[0], Rule { find: "/movies".to_owned(), replace: format!("{}/movies", host), ignore_case: false });
+        assert!(missing.is_err());
+    }
+
+    #[test]
+    fn rules_reject_a_path_mapped_two_ways() {
+        let container = std::env::temp_dir().display().to_string();
+        let mounts = [mount("/a", &container), mount("/b", &container)];
+        assert!(rules(&mounts, Side::Host, Side::Container).is_err());
+        assert_eq!(rules(&mounts, Side::Container, Side::Container).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn read_compose_keeps_the_bind_mounts() {
+        let dir = std::env::temp_dir().join(format!("reptool-test-compose-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let compose_path = dir.join("compose.yaml");
+        fs::write(&compose_path, r#"
+services:
+  rtorrent:
+    volumes:
+      - ./downloads:/downloads:rw
+      - config:/config
+      - type: bind
+        source: /srv/media/
+        target: /media
+  other:
+    volumes:
+      - /srv/other:/other
+"#).unwrap();
+        let service = read_compose(&compose_path, Some("rtorrent"));
+        let all = read_compose(&compose_path, None);
+        let unknown = read_compose(&compose_path, Some("missing"));
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(service.unwrap(), [mount(&dir.join("downloads").display().to_string(), "/downloads"), mount("/srv/media", "/media")]);
+        assert_eq!(all.unwrap().len(), 3);
+        assert!(unknown.is_err());
+    }
+}
@@ -0,0 +1,79 @@
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use clap::{ArgGroup, Args};
+use reptool_core::bencode::Value;
+use reptool_core::report::{FileReport, RunSummary};
+use reptool_core::{Encoding, SessionFile};
+use tracing::warn;
+
+use crate::backup::backup_before_write;
+use crate::logging::log_changes;
+use crate::clients::Client;
+use crate::glob::FileMatcher;
+use crate::{list_files, print_change, process_each, RepToolOption};
+
+#[derive(Args, Clone)]
+#[command(group(ArgGroup::new("target").required(true).args(["to", "client", "from"])))]
+pub struct ConvertArgs {
+    /// Session directory
+    pub dir : String,
+
+    /// Encoding to rewrite the keyword fields in, they are read in --encoding
+    #[arg(long, value_enum)]
+    pub to : Option<Encoding>,
+
+    /// Write the session of another client to --output instead, directory, completion and label included
+    #[arg(long, value_enum, requires = "output", conflicts_with = "to")]
+    pub client : Option<Client>,
+
+    /// Read the session of another client from the session directory and write an rtorrent session to --output
+    #[arg(long, value_enum, requires = "output", conflicts_with_all = ["to", "client"])]
+    pub from : Option<Client>,
+
+    /// Directory receiving the converted session, e.g. qBittorrent's BT_backup
+    #[arg(short, long, value_name = "DIR")]
+    pub output : Option<PathBuf>,
+
+    /// Rewrite the save paths read with --from starting with OLD to start with NEW, repeatable, the first match wins
+    #[arg(long = "map", value_name = "OLD=NEW", requires = "from")]
+    pub mappings : Vec<String>,
+}
+
+/// Re-encode the keyword fields of every .torrent.rtorrent in place, e.g. legacy latin1 paths to UTF-8
+pub fn convert(matcher: &FileMatcher, option: &RepToolOption, to: Encoding) -> Result<RunSummary> {
+    let file_paths = list_files(matcher, option.input_dir()?, option.max_depth())?.into_iter()
+        .filter(|file_path| file_path.to_str().expect("Invalid file name").ends_with(".torrent.rtorrent"));
+    let file_reports = process_each(file_paths, option, |file_path| {
+        let file = file_path.display().to_string();
+        let mut session_file = SessionFile::open(file_path, option.global.io_buffer).with_context(|| format!("Failed to open file: {:?}", file_path))?;
+        let mut modifications = Vec::new();
+        for key in session_file.keys_matching_any(&option.global.keywords)? {
+            let Some(Value::Bytes(bytes)) = session_file.value().as_dict().and_then(|dict| dict.get(key.as_bytes())) else {
+                continue;
+            };
+            let Ok(value) = option.global.encoding.decode(bytes) else {
+                warn!("Skipped {}: {} is not valid {:?}", file, key, option.global.encoding);
+                continue;
+            };
+            if let Some(modification) = session_file.set(&key, &value, to)? {
+                modifications.push(modification);
+            }
+        }
+
+        if option.global.dry_run && !option.global.json {
+            for modification in &modifications {
+                print_change(&file, modification);
+            }
+        }
+        if !modifications.is_empty() && !option.global.dry_run {
+            backup_before_write(option, file_path)?;
+            session_file.save(option.global.io_buffer)?;
+            log_changes(&file, &modifications);
+        }
+
+        Ok(Some(FileReport { matched: !modifications.is_empty(), file, modifications, ..Default::default() }))
+    });
+
+    Ok(RunSummary::new(file_reports))
+}
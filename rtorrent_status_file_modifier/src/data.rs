@@ -0,0 +1,251 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use reptool_core::bencode::Value;
+use reptool_core::{ModifyReport, SessionFile};
+use tracing::{info, warn};
+
+use crate::fsops::{self, LinkData};
+use crate::session::{torrent_name, torrent_path_of};
+
+/// What --check-data does with a torrent whose new directory is missing on disk
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckData {
+    /// Rewrite the session file anyway and warn
+    Warn,
+    /// Leave the session file untouched and warn
+    Skip,
+}
+
+/// How --move-data brings the payload to the new directory
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MoveData {
+    /// Rename, or copy then delete across filesystems
+    Move,
+    /// Copy and keep the original
+    Copy,
+}
+
+/// The data paths the new values point to that do not exist, the directory itself and with `check_files`
+/// every file listed in the companion .torrent
+pub fn missing_data(session_file: &SessionFile, modifications: &[ModifyReport], check_files: bool, io_buffer: usize) -> Vec<PathBuf> {
+    let directories: Vec<&Path> = modifications.iter()
+        .filter(|modification| modification.key.starts_with("directory"))
+        .map(|modification| Path::new(&modification.new_value))
+        .collect();
+    let files = if check_files { torrent_files(session_file.path(), io_buffer) } else { Vec::new() };
+
+    let mut missing = Vec::new();
+    for directory in directories {
+        if !directory.exists() {
+            missing.push(directory.to_path_buf());
+            continue;
+        }
+        // rtorrent keeps either the base directory or the one named after the torrent, accept both layouts
+        missing.extend(files.iter()
+            .filter(|(name, file)| !directory.join(file).exists() && !directory.join(name).join(file).exists())
+            .map(|(_, file)| directory.join(file)));
+    }
+
+    missing
+}
+
+/// (`info.name`, path) of the files of the .torrent next to `file_path`, the path is empty for a single file torrent
+fn torrent_files(file_path: &Path, io_buffer: usize) -> Vec<(PathBuf, PathBuf)> {
+    let Ok(torrent) = SessionFile::open(torrent_path_of(file_path), io_buffer) else {
+        return Vec::new();
+    };
+    let Some(info) = torrent.value().as_dict().and_then(|dict| dict.get(b"info".as_slice())).and_then(Value::as_dict) else {
+        return Vec::new();
+    };
+    let Some(Value::Bytes(name)) = info.get(b"name".as_slice()) else {
+        return Vec::new();
+    };
+    let name = PathBuf::from(String::from_utf8_lossy(name).into_owned());
+
+    let Some(Value::List(files)) = info.get(b"files".as_slice()) else {
+        return vec![(PathBuf::new(), name)];
+    };
+    files.iter().filter_map(|file| {
+        let Some(Value::List(components)) = file.as_dict()?.get(b"path".as_slice()) else {
+            return None;
+        };
+        let path = components.iter().filter_map(|component| match component {
+            Value::Bytes(component) => Some(String::from_utf8_lossy(component).into_owned()),
+            _ => None,
+        }).collect();
+        Some((name.clone(), path))
+    }).collect()
+}
+
+/// (old path, new path) of the payload of the torrent for every changed directory field.
+/// The payload is the file or directory named after `info.name`, either inside the old directory or the old
+/// directory itself. A payload already at its new path needs no move.
+pub fn payload_moves(session_file: &SessionFile, modifications: &[ModifyReport], io_buffer: usize) -> Result<Vec<(PathBuf, PathBuf)>> {
+    let torrent_path = torrent_path_of(session_file.path());
+    let torrent = SessionFile::open(&torrent_path, io_buffer).with_context(|| format!("Failed to read the torrent to find its data: {:?}", torrent_path))?;
+    let name = torrent_name(torrent.value()).with_context(|| format!("Missing info.name in {:?}", torrent_path))?;
+
+    let mut moves: Vec<(PathBuf, PathBuf)> = Vec::new();
+    for modification in modifications.iter().filter(|modification| modification.key.starts_with("directory")) {
+        let (old_directory, new_directory) = (Path::new(&modification.old_value), Path::new(&modification.new_value));
+        let (from, to) = if old_directory.join(&name).exists() {
+            (old_directory.join(&name), new_directory.join(&name))
+        } else if old_directory.file_name().is_some_and(|file_name| *file_name == *name) && old_directory.exists() {
+            (old_directory.to_path_buf(), new_directory.to_path_buf())
+        } else if !new_directory.join(&name).exists() && !new_directory.exists() {
+            bail!("Data of {:?} not found in {:?}", name, old_directory);
+        } else {
+            continue;
+        };
+
+        // `directory` and `directory_base` usually name the same payload, it is moved once
+        match moves.iter().find(|(moved_from, _)| *moved_from == from) {
+            Some((_, moved_to)) if *moved_to == to => {}
+            Some((_, moved_to)) => bail!("Data {:?} would be moved to both {:?} and {:?}", from, moved_to, to),
+            None => moves.push((from, to)),
+        }
+    }
+
+    Ok(moves)
+}
+
+/// A payload brought to its new path, kept to undo it when the session file pointing there is not written
+#[derive(Debug, Clone)]
+pub struct Relocated {
+    pub from: PathBuf,
+    pub to: PathBuf,
+    /// The original was moved away, otherwise `to` is a copy of it or links to it
+    pub moved: bool,
+}
+
+impl Relocated {
+    /// Put the payload back, a move is moved back and a copy or a link removed
+    pub fn undo(&self) {
+        let undone = if self.moved {
+            relocate(&self.to, &self.from, MoveData::Move)
+        } else {
+            let is_tree = fs::symlink_metadata(&self.to).is_ok_and(|metadata| metadata.is_dir());
+            let removed = if is_tree { fs::remove_dir_all(&self.to) } else { fs::remove_file(&self.to) };
+            removed.with_context(|| format!("Failed to remove {:?}", self.to))
+        };
+        match undone {
+            Ok(()) => info!("Put data back {} -> {}", self.to.display(), self.from.display()),
+            Err(err) => warn!("Failed to put data back to {}, it is left at {}: {:#}", self.from.display(), self.to.display(), err),
+        }
+    }
+}
+
+/// Undo `relocated`, the last one first
+pub fn undo_all(relocated: &[Relocated]) {
+    for relocation in relocated.iter().rev() {
+        relocation.undo();
+    }
+}
+
+/// Move or copy `from` to `to`, never over an existing path
+pub fn relocate(from: &Path, to: &Path, mode: MoveData) -> Result<()> {
+    prepare_target(to)?;
+
+    if mode == MoveData::Move {
+        match fs::rename(from, to) {
+            Ok(()) => return Ok(()),
+            Err(err) if err.kind() == io::ErrorKind::CrossesDevices => info!("{:?} is on another filesystem, copying", to),
+            Err(err) => return Err(err).with_context(|| format!("Failed to move {:?} to {:?}", from, to)),
+        }
+    }
+
+    fsops::copy_tree(from, to)?;
+    if mode == MoveData::Move {
+        let removed = if from.is_dir() { fs::remove_dir_all(from) } else { fs::remove_file(from) };
+        removed.with_context(|| format!("Failed to remove {:?} after copying it", from))?;
+    }
+
+    Ok(())
+}
+
+/// Total size of the files under `path`, symlinks not followed, what cannot be read counted as empty
+pub fn tree_size(path: &Path) -> u64 {
+    let Ok(metadata) = fs::symlink_metadata(path) else {
+        return 0;
+    };
+    if !metadata.is_dir() {
+        return metadata.len();
+    }
+    fs::read_dir(path).map_or(0, |entries| entries.flatten().map(|entry| tree_size(&entry.path())).sum())
+}
+
+/// Link `from` to `to` keeping the original, never over an existing path
+pub fn link(from: &Path, to: &Path, mode: LinkData) -> Result<()> {
+    prepare_target(to)?;
+    let linked = fsops::link_tree(from, to, mode);
+    if linked.is_err() && to.is_dir() && !to.is_symlink() {
+        // Drop what was linked before the failure so a retry starts clean
+        let _ = fs::remove_dir_all(to);
+    }
+    linked
+}
+
+fn prepare_target(to: &Path) -> Result<()> {
+    if to.exists() {
+        bail!("Refusing to overwrite existing data: {:?}", to);
+    }
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent).with_context(|| format!("Failed to create directory: {:?}", parent))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::Transaction;
+
+    fn modification(key: &str, old_value: &str, new_value: &str) -> ModifyReport {
+        ModifyReport { key: key.to_owned(), old_value: old_value.to_owned(), new_value: new_value.to_owned(), old_length: old_value.len(), new_length: new_value.len() }
+    }
+
+    #[test]
+    fn payload_moves_and_rollback_put_the_data_back() {
+        let dir = std::env::temp_dir().join(format!("reptool-test-data-{}", std::process::id()));
+        let (old_directory, new_directory) = (dir.join("old"), dir.join("new"));
+        fs::create_dir_all(old_directory.join("payload")).unwrap();
+        fs::write(old_directory.join("payload").join("file"), b"data").unwrap();
+        fs::write(dir.join("HASH.torrent"), b"d4:infod6:lengthi4e4:name7:payloadee").unwrap();
+        let session_file = SessionFile::decode(dir.join("HASH.torrent.rtorrent"), b"de").unwrap();
+
+        // directory and directory_base both point to the payload, it is moved once
+        let (old_value, new_value) = (old_directory.to_str().unwrap(), new_directory.to_str().unwrap());
+        let modifications = [modification("directory", old_value, new_value), modification("directory_base", old_value, new_value)];
+        let moves = payload_moves(&session_file, &modifications, 0).unwrap();
+        assert_eq!(moves, [(old_directory.join("payload"), new_directory.join("payload"))]);
+
+        let (from, to) = moves[0].clone();
+        relocate(&from, &to, MoveData::Move).unwrap();
+        let transaction = Transaction::default();
+        transaction.keep_relocated(vec![Relocated { from, to, moved: true }]);
+        transaction.rollback();
+        let is_back = old_directory.join("payload").join("file").exists() && !new_directory.join("payload").exists();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(is_back);
+    }
+
+    #[test]
+    fn undo_removes_a_copy_and_keeps_the_original() {
+        let dir = std::env::temp_dir().join(format!("reptool-test-copy-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (from, to) = (dir.join("payload"), dir.join("copy").join("payload"));
+        fs::write(&from, b"data").unwrap();
+
+        relocate(&from, &to, MoveData::Copy).unwrap();
+        Relocated { from: from.clone(), to: to.clone(), moved: false }.undo();
+        let (is_kept, is_removed) = (from.exists(), !to.exists());
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(is_kept && is_removed);
+    }
+}
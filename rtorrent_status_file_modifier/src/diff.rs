@@ -0,0 +1,46 @@
+use reptool_core::report::ModifyReport;
+
+use crate::ui::{self, CYAN, GREEN, RED};
+
+/// How --diff shows the changed fields of a file
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiffStyle {
+    /// Unified diff of the bencoded fields, length prefix included
+    Unified,
+    /// One line per field with the changed part marked, [-old-]{+new+} without colors
+    Inline,
+}
+
+/// The diff of one file as a single string, so the output of parallel jobs does not interleave
+pub fn render(file_path: &str, modifications: &[ModifyReport], style: DiffStyle, color: bool) -> String {
+    let paint = |code: &str, text: &str| ui::paint(code, text, color);
+
+    let mut diff = String::new();
+    match style {
+        DiffStyle::Unified => {
+            diff.push_str(&format!("--- {}\n+++ {}\n", file_path, file_path));
+            for modification in modifications {
+                let key = format!("{}:{}", modification.key.len(), modification.key);
+                diff.push_str(&paint(CYAN, &format!("@@ {} @@", modification.key)));
+                diff.push('\n');
+                diff.push_str(&paint(RED, &format!("-{}{}:{}", key, modification.old_length, modification.old_value)));
+                diff.push('\n');
+                diff.push_str(&paint(GREEN, &format!("+{}{}:{}", key, modification.new_length, modification.new_value)));
+                diff.push('\n');
+            }
+        }
+        DiffStyle::Inline => {
+            for modification in modifications {
+                let (prefix, old, new, suffix) = ui::split_change(&modification.old_value, &modification.new_value);
+                let (old, new) = if color {
+                    (paint(RED, old), paint(GREEN, new))
+                } else {
+                    (format!("[-{}-]", old), format!("{{+{}+}}", new))
+                };
+                diff.push_str(&format!("{}: {} {}{}{}{}\n", file_path, modification.key, prefix, old, new, suffix));
+            }
+        }
+    }
+
+    diff
+}
@@ -0,0 +1,58 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use clap::Args;
+use reptool_core::bencode;
+use reptool_core::file::{read_buffered, write_atomic};
+use reptool_core::json::{self, Binary};
+
+use crate::RepToolOption;
+
+#[derive(Args, Clone)]
+pub struct DumpArgs {
+    /// Bencoded file, e.g. a .torrent, .torrent.rtorrent or .torrent.libtorrent_resume
+    pub file : PathBuf,
+
+    /// How strings that are not valid UTF-8 are written
+    #[arg(long, value_enum, default_value_t = Binary::Hex)]
+    pub binary : Binary,
+}
+
+#[derive(Args, Clone)]
+pub struct EncodeArgs {
+    /// JSON file written by dump
+    pub file : PathBuf,
+
+    /// Bencoded file to write, the JSON file name without .json by default
+    #[arg(short, long, value_name = "FILE")]
+    pub output : Option<PathBuf>,
+}
+
+/// Print a bencoded file as pretty JSON
+pub fn dump(option: &RepToolOption, args: &DumpArgs) -> Result<()> {
+    let content = read_buffered(&args.file, option.global.io_buffer).with_context(|| format!("Failed to read file: {:?}", args.file))?;
+    let value = bencode::decode(&content).with_context(|| format!("Failed to decode file: {:?}", args.file))?;
+    println!("{}", serde_json::to_string_pretty(&json::to_json(&value, args.binary))?);
+    Ok(())
+}
+
+/// Write the JSON of dump back as bencode, returns the written file
+pub fn encode(option: &RepToolOption, args: &EncodeArgs) -> Result<PathBuf> {
+    let output = match &args.output {
+        Some(output) => output.clone(),
+        None => {
+            let file_name = args.file.to_str().and_then(|file_name| file_name.strip_suffix(".json"));
+            PathBuf::from(file_name.with_context(|| format!("Missing --output, {:?} does not end with .json", args.file))?)
+        }
+    };
+
+    let content = fs::read_to_string(&args.file).with_context(|| format!("Failed to read file: {:?}", args.file))?;
+    let json: serde_json::Value = serde_json::from_str(&content).with_context(|| format!("Failed to parse JSON: {:?}", args.file))?;
+    let value = json::from_json(&json).with_context(|| format!("Failed to convert JSON: {:?}", args.file))?;
+    if !option.global.dry_run {
+        write_atomic(Path::new(&output), &bencode::encode(&value), option.global.io_buffer)?;
+    }
+
+    Ok(output)
+}
@@ -0,0 +1,77 @@
+use anyhow::{bail, Context, Result};
+use tracing::warn;
+
+/// `text` with its escapes decoded, or as it is with --no-escape
+pub fn decode(text: &str, no_escape: bool) -> Result<String> {
+    if no_escape { Ok(text.to_owned()) } else { unescape(text) }
+}
+
+/// Decode C-style escapes (`\n`, `\t`, `\r`, `\0`, `\\`, `\xNN`) in a search or replace string. `\xNN` is limited to
+/// ASCII, other characters are written as they are. An escape decoding to a control character is warned about, it
+/// is more often a Windows path such as `C:\temp` than a wanted tab.
+pub fn unescape(text: &str) -> Result<String> {
+    let mut decoded = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            decoded.push(c);
+            continue;
+        }
+
+        let escaped = match chars.next() {
+            Some('n') => '\n',
+            Some('t') => '\t',
+            Some('r') => '\r',
+            Some('0') => '\0',
+            Some('\\') => '\\',
+            Some('x') => {
+                let hex: String = chars.by_ref().take(2).collect();
+                let byte = u8::from_str_radix(&hex, 16).ok().filter(|_| hex.len() == 2)
+                    .with_context(|| format!("Invalid \\x escape {:?} in {:?}", hex, text))?;
+                if !byte.is_ascii() {
+                    bail!("\\x{} in {:?} is not ASCII, write the character itself", hex, text);
+                }
+                char::from(byte)
+            }
+            Some(other) => bail!("Unknown escape \\{} in {:?}, use --no-escape for literal backslashes", other, text),
+            None => bail!("Trailing backslash in {:?}, use --no-escape for literal backslashes", text),
+        };
+        if escaped.is_control() {
+            warn!("{:?} has an escape decoding to the control character {:?}, use --no-escape if the backslash is part of a path", text, escaped);
+        }
+        decoded.push(escaped);
+    }
+
+    Ok(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_the_escapes() {
+        assert_eq!(unescape(r"a\nb").unwrap(), "a\nb");
+        assert_eq!(unescape(r"a\tb").unwrap(), "a\tb");
+        assert_eq!(unescape(r"a\rb").unwrap(), "a\rb");
+        assert_eq!(unescape(r"a\0b").unwrap(), "a\0b");
+        assert_eq!(unescape(r"C:\\data").unwrap(), r"C:\data");
+        assert_eq!(unescape(r"\x41\x7e").unwrap(), "A~");
+        assert_eq!(unescape("/mnt/données").unwrap(), "/mnt/données");
+    }
+
+    #[test]
+    fn rejects_invalid_escapes() {
+        assert!(unescape(r"\q").unwrap_err().to_string().contains("Unknown escape"));
+        assert!(unescape(r"trailing\").unwrap_err().to_string().contains("Trailing backslash"));
+        assert!(unescape(r"\x4").is_err());
+        assert!(unescape(r"\xzz").is_err());
+        assert!(unescape(r"\xE9").unwrap_err().to_string().contains("not ASCII"));
+    }
+
+    #[test]
+    fn no_escape_keeps_the_backslashes() {
+        assert_eq!(decode(r"/c\tmp\x41\q", true).unwrap(), r"/c\tmp\x41\q");
+        assert_eq!(decode(r"/c\tmp", false).unwrap(), "/c\tmp");
+    }
+}
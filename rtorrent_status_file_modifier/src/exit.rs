@@ -0,0 +1,71 @@
+use std::fmt;
+use std::io;
+use std::process::ExitCode;
+
+/// How a run ended, the exit code of the process
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    /// The changes were applied, or would be with --dry-run
+    Success = 0,
+    /// Invalid arguments, config or input, the run could not start
+    Usage = 1,
+    /// No value matched the rules, nothing to change
+    NoMatch = 2,
+    /// Some files or torrents failed, timed out or are incomplete, the others were processed
+    PartialFailure = 3,
+    /// Reading or writing a file failed
+    Io = 4,
+    /// Stopped by SIGINT or SIGTERM, the files processed so far are in the report
+    Interrupted = 130,
+}
+
+impl Status {
+    /// The status of a run that failed with `err`, set by a `StatusError` in its chain or else by its cause
+    pub fn of_error(err: &anyhow::Error) -> Status {
+        if let Some(status_error) = err.chain().find_map(|cause| cause.downcast_ref::<StatusError>()) {
+            return status_error.status;
+        }
+        if err.chain().any(|cause| cause.is::<io::Error>()) {
+            Status::Io
+        } else {
+            Status::Usage
+        }
+    }
+}
+
+impl From<Status> for ExitCode {
+    fn from(status: Status) -> Self {
+        ExitCode::from(status as u8)
+    }
+}
+
+/// An error ending the run with `status` whatever its cause
+#[derive(Debug)]
+pub struct StatusError {
+    pub status: Status,
+    pub message: String,
+}
+
+impl fmt::Display for StatusError {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for StatusError {}
+
+/// The error of a run that processed some files and failed on others
+pub fn partial_failure(message: String) -> anyhow::Error {
+    StatusError { status: Status::PartialFailure, message }.into()
+}
+
+/// The error of a run stopped by a signal
+pub fn interrupted(message: String) -> anyhow::Error {
+    StatusError { status: Status::Interrupted, message }.into()
+}
+
+/// Print a command line error and exit, with 0 for --help and --version
+pub fn clap_error(err: clap::Error) -> ! {
+    let _ = err.print();
+    std::process::exit(if err.use_stderr() { Status::Usage as i32 } else { Status::Success as i32 })
+}
@@ -0,0 +1,59 @@
+use std::fs;
+use std::io::{self, Read};
+use std::path::{self, Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+
+/// The paths listed in `source`, `-` for stdin, one per line or separated by NUL bytes with `null`.
+/// Blank entries are skipped, relative paths are resolved against the current directory.
+pub fn read(source: &str, null: bool) -> Result<Vec<PathBuf>> {
+    let mut content = Vec::new();
+    if source == "-" {
+        io::stdin().read_to_end(&mut content).context("Failed to read the file list from stdin")?;
+    } else {
+        content = fs::read(source).with_context(|| format!("Failed to read file list: {:?}", source))?;
+    }
+
+    let separator = if null { b'\0' } else { b'\n' };
+    let mut file_paths = Vec::new();
+    for entry in content.split(|&byte| byte == separator) {
+        let entry = if null { entry } else { entry.strip_suffix(b"\r").unwrap_or(entry) };
+        if entry.is_empty() {
+            continue;
+        }
+        let file_path = path_of(entry)?;
+        file_paths.push(path::absolute(&file_path).with_context(|| format!("Invalid path: {:?}", file_path))?);
+    }
+    if file_paths.is_empty() {
+        bail!("No file paths in {}", if source == "-" { "stdin" } else { source });
+    }
+
+    Ok(file_paths)
+}
+
+#[cfg(unix)]
+fn path_of(entry: &[u8]) -> Result<PathBuf> {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    Ok(PathBuf::from(OsStr::from_bytes(entry)))
+}
+
+#[cfg(not(unix))]
+fn path_of(entry: &[u8]) -> Result<PathBuf> {
+    Ok(PathBuf::from(std::str::from_utf8(entry).context("File path is not valid UTF-8")?))
+}
+
+/// The deepest directory holding every one of `file_paths`, stands for the input directory of the run
+pub fn common_dir(file_paths: &[PathBuf]) -> PathBuf {
+    let mut common = file_paths[0].parent().unwrap_or(Path::new("/")).to_path_buf();
+    for file_path in &file_paths[1..] {
+        while !file_path.starts_with(&common) {
+            if !common.pop() {
+                break;
+            }
+        }
+    }
+
+    common
+}
@@ -0,0 +1,95 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use regex::Regex;
+use reptool_core::bencode;
+use reptool_core::file::read_buffered;
+use reptool_core::InfoHash;
+
+use crate::session::{announce_urls, completion, torrent_name, torrent_path_of};
+use crate::{indexed_hashes, info_hash_of, RepToolOption};
+
+/// Which torrents a run may touch, the files of a torrent all share its verdict
+pub struct TorrentFilter {
+    name: Option<Regex>,
+    tracker: Option<Regex>,
+    hashes: Vec<String>,
+    /// Only the complete torrents when true, the incomplete ones when false
+    complete: Option<bool>,
+    indexed: Option<HashSet<String>>,
+    io_buffer: usize,
+}
+
+impl TorrentFilter {
+    pub fn new(option: &RepToolOption) -> Result<Self> {
+        let name = option.replace.name_filter.as_deref()
+            .map(|pattern| Regex::new(pattern).with_context(|| format!("Invalid --name-filter: {:?}", pattern)))
+            .transpose()?;
+        let tracker = option.replace.tracker_filter.as_deref()
+            .map(|pattern| Regex::new(pattern).with_context(|| format!("Invalid --tracker-filter: {:?}", pattern)))
+            .transpose()?;
+        let hashes = option.replace.hash.iter().map(|hash| hash.to_uppercase()).collect();
+        let complete = if option.replace.only_complete {
+            Some(true)
+        } else if option.replace.only_incomplete {
+            Some(false)
+        } else {
+            None
+        };
+        let indexed = indexed_hashes(option)?;
+        Ok(TorrentFilter { name, tracker, hashes, complete, indexed, io_buffer: option.global.io_buffer })
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.name.is_some() || self.tracker.is_some() || !self.hashes.is_empty() || self.complete.is_some() || self.indexed.is_some()
+    }
+
+    /// Keep the files of the selected torrents, the companion .torrent is read once per torrent
+    pub fn retain(&self, file_paths: Vec<PathBuf>) -> Vec<PathBuf> {
+        if !self.is_active() {
+            return file_paths;
+        }
+
+        let mut verdicts: HashMap<PathBuf, bool> = HashMap::new();
+        file_paths.into_iter().filter(|file_path| {
+            let torrent_path = torrent_path_of(file_path);
+            *verdicts.entry(torrent_path.clone()).or_insert_with(|| self.matches(file_path, &torrent_path))
+        }).collect()
+    }
+
+    fn matches(&self, file_path: &Path, torrent_path: &Path) -> bool {
+        if self.indexed.as_ref().is_some_and(|indexed| !indexed.contains(&info_hash_of(file_path))) {
+            return false;
+        }
+        // A torrent whose state is unknown is neither complete nor incomplete
+        if self.complete.is_some_and(|complete| completion(torrent_path, self.io_buffer) != Some(complete)) {
+            return false;
+        }
+        let is_named_match = self.hashes.is_empty() || self.hashes.contains(&info_hash_of(file_path));
+        if is_named_match && self.name.is_none() && self.tracker.is_none() {
+            return true;
+        }
+
+        // A torrent without its .torrent has no computed hash, name or tracker to match
+        let Ok(data) = read_buffered(torrent_path, self.io_buffer) else {
+            return false;
+        };
+        // Session files are named after the v1 hash, the v2 one of the .torrent is accepted too
+        let is_hash_match = is_named_match
+            || InfoHash::of_torrent(&data).is_ok_and(|info_hash| self.hashes.iter().any(|hash| info_hash.matches(hash)));
+        if !is_hash_match {
+            return false;
+        }
+        if self.name.is_none() && self.tracker.is_none() {
+            return true;
+        }
+
+        let Ok(torrent) = bencode::decode(&data) else {
+            return false;
+        };
+        let is_name_match = self.name.as_ref().is_none_or(|name| torrent_name(&torrent).is_some_and(|torrent_name| name.is_match(&torrent_name)));
+        let is_tracker_match = self.tracker.as_ref().is_none_or(|tracker| announce_urls(&torrent).iter().any(|url| tracker.is_match(url)));
+        is_name_match && is_tracker_match
+    }
+}
@@ -0,0 +1,115 @@
+use std::fs::{self, File, FileTimes};
+use std::io;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+
+/// How --link-data makes the data appear at the new directory while keeping the original
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkData {
+    /// Hard link every file, same filesystem only
+    Hardlink,
+    /// Copy on write clone of every file, needs a filesystem supporting it (btrfs, xfs)
+    Reflink,
+    /// One symbolic link to the original file or directory
+    Symlink,
+}
+
+/// Copy a file or a whole directory tree, `to` must not exist yet
+pub fn copy_tree(from: &Path, to: &Path) -> Result<()> {
+    walk_tree(from, to, &|from, to| fs::copy(from, to).map(|_| ()))
+        .with_context(|| format!("Failed to copy {:?} to {:?}", from, to))
+}
+
+/// Link a file or a whole directory tree to `to`, directories are recreated and the files linked
+pub fn link_tree(from: &Path, to: &Path, mode: LinkData) -> Result<()> {
+    let linked = match mode {
+        LinkData::Hardlink => walk_tree(from, to, &|from, to| fs::hard_link(from, to)),
+        LinkData::Reflink => walk_tree(from, to, &reflink),
+        LinkData::Symlink => symlink(&from.canonicalize()?, to),
+    };
+    linked.with_context(|| format!("Failed to {:?} {:?} to {:?}", mode, from, to))
+}
+
+/// Give `to` the access and modification times, permissions and owner of `from`.
+/// Changing the owner needs privileges, without them it is kept.
+pub fn preserve_metadata(from: &Path, to: &Path) -> Result<()> {
+    let metadata = fs::metadata(from).with_context(|| format!("Failed to read metadata: {:?}", from))?;
+    let preserved = (|| {
+        // Permissions last, a read only mode would stop the times from being set and chown may clear setuid bits
+        let times = FileTimes::new().set_accessed(metadata.accessed()?).set_modified(metadata.modified()?);
+        File::options().write(true).open(to)?.set_times(times)?;
+        set_owner(&metadata, to)?;
+        fs::set_permissions(to, metadata.permissions())
+    })();
+    preserved.with_context(|| format!("Failed to preserve metadata of {:?} on {:?}", from, to))
+}
+
+#[cfg(unix)]
+fn set_owner(metadata: &fs::Metadata, to: &Path) -> io::Result<()> {
+    use std::os::unix::fs::MetadataExt;
+
+    match std::os::unix::fs::chown(to, Some(metadata.uid()), Some(metadata.gid())) {
+        Err(err) if err.kind() == io::ErrorKind::PermissionDenied => Ok(()),
+        chowned => chowned,
+    }
+}
+
+#[cfg(not(unix))]
+fn set_owner(_metadata: &fs::Metadata, _to: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+fn walk_tree(from: &Path, to: &Path, file_op: &dyn Fn(&Path, &Path) -> io::Result<()>) -> io::Result<()> {
+    if !from.is_dir() {
+        return file_op(from, to);
+    }
+
+    fs::create_dir(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        walk_tree(&entry.path(), &to.join(entry.file_name()), file_op)?;
+    }
+
+    Ok(())
+}
+
+/// Clone `from` into a new file `to` sharing its blocks
+#[cfg(target_os = "linux")]
+fn reflink(from: &Path, to: &Path) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    // _IOW(0x94, 9, int) from linux/fs.h
+    const FICLONE: libc::c_ulong = 0x4004_9409;
+
+    let source = File::open(from)?;
+    let target = File::create_new(to)?;
+    // SAFETY: both descriptors are open for the duration of the call, FICLONE takes the source descriptor by value
+    let result = unsafe { libc::ioctl(target.as_raw_fd(), FICLONE as _, source.as_raw_fd()) };
+    if result == -1 {
+        let err = io::Error::last_os_error();
+        drop(target);
+        let _ = fs::remove_file(to);
+        return Err(err);
+    }
+    fs::set_permissions(to, source.metadata()?.permissions())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn reflink(_from: &Path, _to: &Path) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "reflinks are only supported on Linux"))
+}
+
+#[cfg(unix)]
+fn symlink(from: &Path, to: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(from, to)
+}
+
+#[cfg(windows)]
+fn symlink(from: &Path, to: &Path) -> io::Result<()> {
+    if from.is_dir() {
+        std::os::windows::fs::symlink_dir(from, to)
+    } else {
+        std::os::windows::fs::symlink_file(from, to)
+    }
+}
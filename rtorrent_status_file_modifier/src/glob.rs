@@ -0,0 +1,66 @@
+/// Match `text` against a shell style pattern where `*` matches any run of characters and `?` a single one
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    // Greedy matching with backtracking to the last star
+    let (mut p, mut t) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = star {
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+pub fn is_glob(pattern: &str) -> bool {
+    pattern.contains(['*', '?'])
+}
+
+/// Which files of the input path are session files to process, matched on the file name
+#[derive(Debug, Clone)]
+pub struct FileMatcher {
+    extensions: Vec<String>,
+    include: Vec<String>,
+    exclude: Vec<String>,
+    follow_symlinks: bool,
+}
+
+impl FileMatcher {
+    /// Files ending with one of `extensions`, matching one of `include` if any, and none of `exclude`
+    pub fn new(extensions: Vec<String>, include: Vec<String>, exclude: Vec<String>) -> Self {
+        FileMatcher { extensions, include, exclude, follow_symlinks: false }
+    }
+
+    /// Also take the files and directories symlinked into the input path
+    pub fn follow_symlinks(mut self, follow_symlinks: bool) -> Self {
+        self.follow_symlinks = follow_symlinks;
+        self
+    }
+
+    pub fn is_following_symlinks(&self) -> bool {
+        self.follow_symlinks
+    }
+
+    pub fn extensions(&self) -> impl Iterator<Item = &str> {
+        self.extensions.iter().map(String::as_str)
+    }
+
+    pub fn is_match(&self, file_name: &str) -> bool {
+        self.extensions.iter().any(|extension| file_name.ends_with(extension.as_str()))
+            && (self.include.is_empty() || self.include.iter().any(|pattern| glob_match(pattern, file_name)))
+            && !self.exclude.iter().any(|pattern| glob_match(pattern, file_name))
+    }
+}
@@ -0,0 +1,69 @@
+use std::io;
+use std::process::{Command, Stdio};
+
+use anyhow::{bail, Context, Result};
+use reptool_core::ModifyReport;
+use tracing::info;
+
+/// Run `command` through the shell with its `{name}` placeholders replaced by the shell quoted `vars`, which are
+/// also passed as `REPTOOL_<NAME>` environment variables. Its output goes to stderr to keep stdout for the reports.
+/// A dry run only says what it would run.
+pub fn run(command: &str, vars: &[(&str, String)], dry_run: bool) -> Result<()> {
+    let mut expanded = command.to_owned();
+    for (name, value) in vars {
+        expanded = expanded.replace(&format!("{{{}}}", name), &quote(value));
+    }
+    if dry_run {
+        info!("Would run: {}", expanded);
+        return Ok(());
+    }
+
+    info!("Running: {}", expanded);
+    let mut shell = shell(&expanded);
+    for (name, value) in vars {
+        shell.env(format!("REPTOOL_{}", name.to_uppercase()), value);
+    }
+    let status = shell.stdin(Stdio::null()).stdout(io::stderr()).status()
+        .with_context(|| format!("Failed to run command: {:?}", expanded))?;
+    if !status.success() {
+        bail!("Command {:?} failed with {}", expanded, status);
+    }
+
+    Ok(())
+}
+
+/// `{file}`, `{old_dir}` and `{new_dir}` of a rewritten file, the directories being the old and new value of the
+/// first keyword when it changed, or else of the first changed field
+pub fn file_vars(file_path: &str, modifications: &[ModifyReport], keyword: &str) -> Vec<(&'static str, String)> {
+    let modification = modifications.iter().find(|modification| modification.key == keyword).or(modifications.first());
+    vec![
+        ("file", file_path.to_owned()),
+        ("old_dir", modification.map(|modification| modification.old_value.clone()).unwrap_or_default()),
+        ("new_dir", modification.map(|modification| modification.new_value.clone()).unwrap_or_default()),
+    ]
+}
+
+#[cfg(unix)]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("sh");
+    shell.arg("-c").arg(command);
+    shell
+}
+
+#[cfg(not(unix))]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("cmd");
+    shell.arg("/C").arg(command);
+    shell
+}
+
+/// A single shell word
+#[cfg(unix)]
+fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+#[cfg(not(unix))]
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('"', "\"\""))
+}
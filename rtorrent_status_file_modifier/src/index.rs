@@ -0,0 +1,154 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use anyhow::{bail, Context, Result};
+use reptool_core::Encoding;
+use rusqlite::types::ValueRef;
+use rusqlite::{params, Connection, OpenFlags};
+
+use crate::session::{group_companions, load_sessions};
+
+/// Name of the index in the session directory when --db or --index-db is not given
+pub const DEFAULT_INDEX_DB: &str = ".reptool-index.sqlite";
+
+const SCHEMA: &str = "CREATE TABLE IF NOT EXISTS torrents (
+    hash TEXT PRIMARY KEY,
+    name TEXT NOT NULL,
+    directory TEXT NOT NULL,
+    label TEXT,
+    tracker TEXT,
+    size INTEGER,
+    complete INTEGER,
+    file TEXT NOT NULL,
+    modified_ns INTEGER NOT NULL
+)";
+
+/// How many torrents the index holds after a refresh and how many were parsed again
+pub struct Refresh {
+    pub indexed: usize,
+    pub updated: usize,
+}
+
+/// Bring the index at `db_path` up to date with `file_paths`. Only the torrents whose .torrent.rtorrent or .torrent
+/// changed since the last refresh are parsed again, those whose files are gone are dropped.
+pub fn refresh(db_path: &Path, file_paths: &[PathBuf], key: &str, encoding: Encoding) -> Result<Refresh> {
+    let mut connection = Connection::open(db_path).with_context(|| format!("Failed to open index: {:?}", db_path))?;
+    connection.execute(SCHEMA, []).with_context(|| format!("Failed to create index: {:?}", db_path))?;
+
+    let mut indexed: HashMap<String, i64> = HashMap::new();
+    {
+        let mut statement = connection.prepare("SELECT file, modified_ns FROM torrents")?;
+        let rows = statement.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        for row in rows {
+            let (file, modified_ns) = row?;
+            indexed.insert(file, modified_ns);
+        }
+    }
+
+    let transaction = connection.transaction()?;
+    let mut seen = HashSet::new();
+    let mut updated = 0;
+    for companions in group_companions(file_paths).into_values() {
+        let Some(rtorrent_path) = &companions.rtorrent else {
+            continue;
+        };
+        let file = rtorrent_path.display().to_string();
+        let modified_ns = [Some(rtorrent_path), companions.torrent.as_ref()].into_iter().flatten()
+            .filter_map(|file_path| modified_ns(file_path).ok())
+            .max()
+            .unwrap_or_default();
+        seen.insert(file.clone());
+        if indexed.get(&file) == Some(&modified_ns) {
+            continue;
+        }
+
+        let group: Vec<PathBuf> = [companions.rtorrent.clone(), companions.torrent.clone()].into_iter().flatten().collect();
+        transaction.execute("DELETE FROM torrents WHERE file = ?1", params![file])?;
+        for session in load_sessions(&group, key, encoding) {
+            transaction.execute(
+                "INSERT OR REPLACE INTO torrents (hash, name, directory, label, tracker, size, complete, file, modified_ns)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                params![session.hash, session.name, session.directory, session.label, session.tracker_host,
+                    session.size.map(|size| size as i64), session.complete, file, modified_ns],
+            )?;
+            updated += 1;
+        }
+    }
+    for file in indexed.keys().filter(|file| !seen.contains(*file)) {
+        transaction.execute("DELETE FROM torrents WHERE file = ?1", params![file])?;
+    }
+    transaction.commit().with_context(|| format!("Failed to write index: {:?}", db_path))?;
+
+    let indexed = connection.query_row("SELECT COUNT(*) FROM torrents", [], |row| row.get::<_, i64>(0))?;
+    Ok(Refresh { indexed: indexed as usize, updated })
+}
+
+/// Run a query against the index, printing the rows tab separated under a header, or as a JSON array of objects
+pub fn print_query(db_path: &Path, query: &str, json: bool) -> Result<()> {
+    let connection = open_read_only(db_path)?;
+    let mut statement = connection.prepare(query).with_context(|| format!("Invalid query: {:?}", query))?;
+    let columns: Vec<String> = statement.column_names().into_iter().map(str::to_owned).collect();
+
+    let mut rows = statement.query([])?;
+    let mut objects = Vec::new();
+    if !json {
+        println!("{}", columns.join("\t"));
+    }
+    while let Some(row) = rows.next()? {
+        let mut values = Vec::with_capacity(columns.len());
+        for index in 0..columns.len() {
+            values.push(json_value(row.get_ref(index)?));
+        }
+        if json {
+            objects.push(serde_json::Value::Object(columns.iter().cloned().zip(values).collect()));
+        } else {
+            let fields: Vec<String> = values.iter().map(|value| match value {
+                serde_json::Value::Null => String::new(),
+                serde_json::Value::String(text) => text.clone(),
+                other => other.to_string(),
+            }).collect();
+            println!("{}", fields.join("\t"));
+        }
+    }
+    if json {
+        println!("{}", serde_json::to_string_pretty(&objects)?);
+    }
+
+    Ok(())
+}
+
+/// The hashes of the torrents matching `condition`, an SQL expression over the columns of the index
+pub fn matching_hashes(db_path: &Path, condition: &str) -> Result<HashSet<String>> {
+    let connection = open_read_only(db_path)?;
+    let query = format!("SELECT hash FROM torrents WHERE {}", condition);
+    let mut statement = connection.prepare(&query).with_context(|| format!("Invalid --where: {:?}", condition))?;
+    let hashes = statement.query_map([], |row| row.get::<_, String>(0))?
+        .collect::<rusqlite::Result<_>>()
+        .with_context(|| format!("Failed to query index: {:?}", db_path))?;
+    Ok(hashes)
+}
+
+fn open_read_only(db_path: &Path) -> Result<Connection> {
+    if !db_path.exists() {
+        bail!("No index at {:?}, run the index command first", db_path);
+    }
+    Connection::open_with_flags(db_path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .with_context(|| format!("Failed to open index: {:?}", db_path))
+}
+
+fn json_value(value: ValueRef) -> serde_json::Value {
+    match value {
+        ValueRef::Null => serde_json::Value::Null,
+        ValueRef::Integer(integer) => integer.into(),
+        ValueRef::Real(real) => real.into(),
+        ValueRef::Text(text) | ValueRef::Blob(text) => String::from_utf8_lossy(text).into(),
+    }
+}
+
+fn modified_ns(file_path: &Path) -> Result<i64> {
+    let modified = fs::metadata(file_path).and_then(|metadata| metadata.modified())
+        .with_context(|| format!("Failed to read modification time: {:?}", file_path))?;
+    Ok(modified.duration_since(UNIX_EPOCH).map_or(0, |duration| duration.as_nanos() as i64))
+}
@@ -0,0 +1,566 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use clap::Args;
+use reptool_core::bencode::{self, Value};
+use reptool_core::file::read_buffered;
+use reptool_core::{InfoHash, SessionFile};
+use regex::RegexBuilder;
+use serde_json::json;
+use tracing::warn;
+
+use crate::exit::Status;
+use crate::glob::FileMatcher;
+use crate::session::{group_companions, load_sessions, Session};
+use crate::{info_hash_of, list_files, RepToolOption};
+
+#[derive(Args, Clone)]
+pub struct ListArgs {
+    /// Session directory
+    pub dir : String,
+
+    /// Dump every string field of the session files with its key path instead, to find the keyword to target
+    #[arg(long)]
+    pub strings : bool,
+
+    /// Count the files per extension and how many hold the keyword instead
+    #[arg(long, conflicts_with = "strings")]
+    pub by_extension : bool,
+
+    /// With --strings, stop after N fields per file
+    #[arg(long, value_name = "N", default_value_t = 500)]
+    pub max_entries : usize,
+}
+
+#[derive(Args, Clone)]
+pub struct VerifyArgs {
+    /// Session directory
+    pub dir : String,
+
+    /// Also report the torrents outside the default directory of this rtorrent config
+    #[arg(long, value_name = "RTORRENT_RC")]
+    pub config : Option<String>,
+
+    /// Also report the torrents outside this directory, overrides the one read from --config
+    #[arg(long, value_name = "DIR")]
+    pub default_directory : Option<String>,
+}
+
+#[derive(Args, Clone)]
+pub struct GrepArgs {
+    /// Session directory
+    pub dir : String,
+
+    /// Regular expression searched in the decoded string values
+    pub pattern : String,
+
+    /// Only search the values whose key path matches this regular expression, e.g. ^directory$ or ^files\[
+    #[arg(long, value_name = "REGEX")]
+    pub key : Option<String>,
+
+    /// Match letters regardless of case
+    #[arg(short, long)]
+    pub ignore_case : bool,
+}
+
+// Values longer than this are cut in text output to keep the tree readable
+const MAX_VALUE_CHARS: usize = 200;
+
+struct StringField<'a> {
+    path: Vec<String>,
+    value: &'a [u8],
+}
+
+fn collect_strings<'a>(value: &'a Value, path: &mut Vec<String>, fields: &mut Vec<StringField<'a>>) {
+    match value {
+        Value::Bytes(bytes) => fields.push(StringField { path: path.clone(), value: bytes }),
+        Value::List(list) => {
+            for (index, item) in list.iter().enumerate() {
+                path.push(format!("[{}]", index));
+                collect_strings(item, path, fields);
+                path.pop();
+            }
+        }
+        Value::Dict(dict) => {
+            for (key, item) in dict {
+                path.push(String::from_utf8_lossy(key).into_owned());
+                collect_strings(item, path, fields);
+                path.pop();
+            }
+        }
+        Value::Integer(_) => {}
+    }
+}
+
+/// `announce-list[0][0]` or `files[2].path[0]`, as printed by grep
+fn key_path(path: &[String]) -> String {
+    let mut joined = String::new();
+    for key in path {
+        if !joined.is_empty() && !key.starts_with('[') {
+            joined.push('.');
+        }
+        joined.push_str(key);
+    }
+    joined
+}
+
+fn display_value(value: &[u8]) -> String {
+    match std::str::from_utf8(value) {
+        Ok(text) if !text.chars().any(|c| c.is_control()) => {
+            if text.chars().count() > MAX_VALUE_CHARS {
+                let cut: String = text.chars().take(MAX_VALUE_CHARS).collect();
+                format!("{:?}... ({} bytes)", cut, value.len())
+            } else {
+                format!("{:?}", text)
+            }
+        }
+        _ => format!("<binary, {} bytes>", value.len()),
+    }
+}
+
+fn print_tree(file_path: &Path, fields: &[StringField], total: usize) {
+    println!("{}", file_path.display());
+
+    // Only print the parent keys that differ from the previous field
+    let mut previous: &[String] = &[];
+    for field in fields {
+        let Some((leaf, parents)) = field.path.split_last() else {
+            // The whole file is a single string
+            println!("  {}", display_value(field.value));
+            continue;
+        };
+        let common = previous.iter().zip(parents).take_while(|(a, b)| a == b).count();
+        for (depth, parent) in parents.iter().enumerate().skip(common) {
+            println!("{}{}", "  ".repeat(depth + 1), parent);
+        }
+        println!("{}{}: {}", "  ".repeat(parents.len() + 1), leaf, display_value(field.value));
+        previous = parents;
+    }
+
+    if total > fields.len() {
+        println!("  ... {} more fields not shown", total - fields.len());
+    }
+}
+
+/// A table of the torrents with their name, info-hash, keyword value, size and completion
+pub fn list_sessions(matcher: &FileMatcher, option: &RepToolOption) -> Result<()> {
+    let file_paths = list_files(matcher, option.input_dir()?, option.max_depth())?;
+    let sessions = load_sessions(&file_paths, option.keyword(), option.global.encoding);
+
+    if option.global.json {
+        println!("{}", serde_json::to_string_pretty(&sessions_json(&sessions))?);
+        return Ok(());
+    }
+
+    let name_width = sessions.iter().map(|session| session.name.chars().count()).max().unwrap_or(0).max(4);
+    let hash_width = sessions.iter().map(|session| session.hash.len() + 1).max().unwrap_or(0).max(4);
+    println!("{:<name_width$}  {:<hash_width$}  {:>10}  {:<10}  {}", "NAME", "HASH", "SIZE", "STATE", option.keyword().to_uppercase());
+    for session in &sessions {
+        let size = session.size.map(format_size).unwrap_or_else(|| "?".to_owned());
+        let state = match session.complete {
+            Some(true) => "complete",
+            Some(false) => "incomplete",
+            None => "?",
+        };
+        // A ! marks a session file named after another hash than the one of its .torrent
+        let hash = match &session.info_hash {
+            Some(info_hash) if !info_hash.matches(&session.hash) => format!("{}!", session.hash),
+            _ => session.hash.clone(),
+        };
+        println!("{:<name_width$}  {:<hash_width$}  {:>10}  {:<10}  {}", session.name, hash, size, state, session.directory);
+    }
+    println!("{} torrent(s)", sessions.len());
+    let mismatched = sessions.iter().filter(|session| session.info_hash.as_ref().is_some_and(|info_hash| !info_hash.matches(&session.hash))).count();
+    if mismatched > 0 {
+        println!("{} torrent(s) marked ! are named after another hash than their .torrent, see verify", mismatched);
+    }
+
+    Ok(())
+}
+
+/// The torrents as list --json shows them
+pub fn sessions_json(sessions: &[Session]) -> serde_json::Value {
+    sessions.iter().map(|session| json!({
+        "name": session.name,
+        "hash": session.hash,
+        "info_hash": session.info_hash,
+        "directory": session.directory,
+        "size": session.size,
+        "complete": session.complete,
+        "label": session.label,
+        "tracker": session.tracker_host,
+        "file": session.rtorrent_path.display().to_string(),
+    })).collect()
+}
+
+/// Human readable size in binary units, e.g. 1.5 GiB
+pub fn format_size(size: u64) -> String {
+    const UNITS: [&str; 5] = ["KiB", "MiB", "GiB", "TiB", "PiB"];
+    if size < 1024 {
+        return format!("{} B", size);
+    }
+    let mut value = size as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit + 1 < UNITS.len() {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+/// Dump every string field of the session files so users can find the keyword to target
+pub fn dump_all_strings(matcher: &FileMatcher, option: &RepToolOption, max_entries: usize) -> Result<()> {
+    let mut reports = Vec::new();
+
+    for file_path in list_files(matcher, option.input_dir()?, option.max_depth())? {
+        let content = fs::read(&file_path).with_context(|| format!("Failed to read file: {:?}", file_path))?;
+        let value = bencode::decode(&content).with_context(|| format!("Failed to decode file: {:?}", file_path))?;
+
+        let mut fields = Vec::new();
+        collect_strings(&value, &mut Vec::new(), &mut fields);
+        let total = fields.len();
+        fields.truncate(max_entries);
+
+        if option.global.json {
+            let entries: Vec<_> = fields.iter().map(|field| json!({
+                "path": field.path,
+                "value": std::str::from_utf8(field.value).ok(),
+                "length": field.value.len(),
+            })).collect();
+            reports.push(json!({
+                "file": file_path.display().to_string(),
+                "fields": entries,
+                "truncated": total > fields.len(),
+            }));
+        } else {
+            print_tree(&file_path, &fields, total);
+        }
+    }
+
+    if option.global.json {
+        println!("{}", serde_json::to_string_pretty(&reports)?);
+    }
+
+    Ok(())
+}
+
+/// Print the string values of the session files matching the pattern with their file and key path, decoded with
+/// --encoding. Binary values such as the piece hashes are left out.
+pub fn grep(matcher: &FileMatcher, option: &RepToolOption, args: &GrepArgs) -> Result<Status> {
+    let pattern = RegexBuilder::new(&args.pattern).case_insensitive(args.ignore_case).build()
+        .with_context(|| format!("Invalid pattern: {:?}", args.pattern))?;
+    let key = args.key.as_deref()
+        .map(|key| RegexBuilder::new(key).case_insensitive(args.ignore_case).build().with_context(|| format!("Invalid --key: {:?}", key)))
+        .transpose()?;
+
+    let mut matches = Vec::new();
+    let mut found = 0;
+    for file_path in list_files(matcher, option.input_dir()?, option.max_depth())? {
+        let value = match read_buffered(&file_path, option.global.io_buffer).map_err(anyhow::Error::from)
+            .and_then(|content| bencode::decode(&content)) {
+            Ok(value) => value,
+            Err(err) => {
+                warn!("Skipped {}: {:#}", file_path.display(), err);
+                continue;
+            }
+        };
+
+        let mut fields = Vec::new();
+        collect_strings(&value, &mut Vec::new(), &mut fields);
+        for field in fields {
+            let Ok(text) = option.global.encoding.decode(field.value) else {
+                continue;
+            };
+            let path = key_path(&field.path);
+            if text.chars().any(|c| c.is_control()) || key.as_ref().is_some_and(|key| !key.is_match(&path)) || !pattern.is_match(&text) {
+                continue;
+            }
+            found += 1;
+            if option.global.json {
+                matches.push(json!({ "file": file_path.display().to_string(), "key": path, "value": text }));
+            } else {
+                println!("{}: {}: {}", file_path.display(), path, display_value(text.as_bytes()));
+            }
+        }
+    }
+
+    if option.global.json {
+        println!("{}", serde_json::to_string_pretty(&matches)?);
+    }
+
+    // Like grep, finding nothing is not a success
+    Ok(if found == 0 { Status::NoMatch } else { Status::Success })
+}
+
+/// Count the scanned files per extension and how many of them hold an editable keyword field
+pub fn count_by_extension(matcher: &FileMatcher, option: &RepToolOption) -> Result<()> {
+    // (extension, files, files with the keyword)
+    let mut counts: Vec<(&str, usize, usize)> = matcher.extensions().map(|extension| (extension, 0, 0)).collect();
+
+    for file_path in list_files(matcher, option.input_dir()?, option.max_depth())? {
+        let file_name = file_path.to_str().expect("Invalid file name");
+        // The first extension wins, so .torrent.rtorrent counts as rtorrent and not torrent
+        let Some(count) = counts.iter_mut().find(|(extension, _, _)| file_name.ends_with(extension)) else {
+            continue;
+        };
+        count.1 += 1;
+
+        let has_keyword = SessionFile::open(&file_path, option.global.io_buffer).ok()
+            .and_then(|session_file| session_file.keys_matching_any(&option.global.keywords).ok())
+            .is_some_and(|keys| !keys.is_empty());
+        if has_keyword {
+            count.2 += 1;
+        }
+    }
+
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+
+    if option.global.json {
+        let entries: Vec<_> = counts.iter().map(|(extension, files, editable)| json!({
+            "extension": extension,
+            "files": files,
+            "with_keyword": editable,
+        })).collect();
+        println!("{}", serde_json::to_string_pretty(&entries)?);
+    } else {
+        println!("{:<20} {:>8} {:>12}", "EXTENSION", "FILES", "WITH KEYWORD");
+        for (extension, files, editable) in &counts {
+            println!("{:<20} {:>8} {:>12}", extension, files, editable);
+        }
+    }
+
+    Ok(())
+}
+
+/// The download base of an rtorrent config, the last `directory.default.set` (or legacy `directory`) line wins
+fn default_directory_from_config(config_path: &Path) -> Result<String> {
+    let content = fs::read_to_string(config_path).with_context(|| format!("Failed to read config: {:?}", config_path))?;
+
+    let mut default_directory = None;
+    for line in content.lines() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        let Some((name, value)) = line.split_once('=') else {
+            continue;
+        };
+        if matches!(name.trim(), "directory.default.set" | "directory") {
+            default_directory = Some(value.trim().trim_matches('"').to_owned());
+        }
+    }
+
+    let Some(default_directory) = default_directory else {
+        bail!("No directory.default.set line in config: {:?}", config_path);
+    };
+    // rtorrent expands a leading ~ itself
+    match (default_directory.strip_prefix('~'), env::var("HOME")) {
+        (Some(rest), Ok(home)) => Ok(format!("{}{}", home, rest)),
+        _ => Ok(default_directory),
+    }
+}
+
+/// Run the consistency checks of the verify command and report them together
+pub fn verify(matcher: &FileMatcher, option: &RepToolOption, args: &VerifyArgs) -> Result<()> {
+    let file_paths = list_files(matcher, option.input_dir()?, option.max_depth())?;
+    let (without_torrent, without_session) = find_orphans(&file_paths);
+    let incomplete = find_incomplete(&file_paths);
+    let unparseable = find_unparseable(&file_paths);
+    let missing_directories = find_missing_directories(&file_paths, option);
+    let hash_mismatches = find_hash_mismatches(&file_paths, option);
+    let base = match (&args.default_directory, &args.config) {
+        (Some(default_directory), _) => Some(default_directory.clone()),
+        (None, Some(config_path)) => Some(default_directory_from_config(Path::new(config_path))?),
+        (None, None) => None,
+    };
+    let nonconforming = match &base {
+        Some(base) => find_nonconforming(&file_paths, option, base.trim_end_matches('/'))?,
+        None => Vec::new(),
+    };
+
+    if option.global.json {
+        let mut report = json!({
+            "session_without_torrent": without_torrent,
+            "torrent_without_session": without_session,
+            "missing_companions": incomplete.iter().map(|(file_path, missing)| json!({
+                "file": file_path,
+                "missing": missing,
+            })).collect::<Vec<_>>(),
+            "unparseable": unparseable.iter().map(|(file_path, error)| json!({
+                "file": file_path,
+                "error": error,
+            })).collect::<Vec<_>>(),
+            "hash_mismatch": hash_mismatches.iter().map(|(file_path, info_hash)| json!({
+                "file": file_path,
+                "info_hash": info_hash,
+            })).collect::<Vec<_>>(),
+            "missing_directory": missing_directories.iter().map(|(file_path, directory)| json!({
+                "file": file_path.display().to_string(),
+                "directory": directory,
+            })).collect::<Vec<_>>(),
+        });
+        if let Some(base) = &base {
+            let entries: Vec<_> = nonconforming.iter().map(|(file_path, directory)| json!({
+                "file": file_path.display().to_string(),
+                "directory": directory,
+            })).collect();
+            report["base"] = json!(base);
+            report["nonconforming"] = json!(entries);
+        }
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
+    }
+
+    println!("Session files without a .torrent ({}):", without_torrent.len());
+    for file_path in &without_torrent {
+        println!("  {}", file_path);
+    }
+    println!(".torrent files without session data ({}):", without_session.len());
+    for file_path in &without_session {
+        println!("  {}", file_path);
+    }
+    println!("Torrents with a missing companion ({}):", incomplete.len());
+    for (file_path, missing) in &incomplete {
+        println!("  {}: no {}", file_path, missing.join(", "));
+    }
+    println!("Unparseable files ({}):", unparseable.len());
+    for (file_path, error) in &unparseable {
+        println!("  {}: {}", file_path, error);
+    }
+    println!("Files named after another hash than their .torrent ({}):", hash_mismatches.len());
+    for (file_path, info_hash) in &hash_mismatches {
+        let hashes: Vec<&str> = info_hash.v1.iter().chain(info_hash.v2.iter()).map(String::as_str).collect();
+        println!("  {}: info-hash {}", file_path, hashes.join(" / "));
+    }
+    println!("Directories missing on disk ({}):", missing_directories.len());
+    for (file_path, directory) in &missing_directories {
+        println!("  {}: {}", file_path.display(), directory);
+    }
+    if let Some(base) = &base {
+        println!("Torrents outside {} ({}):", base, nonconforming.len());
+        for (file_path, directory) in &nonconforming {
+            println!("  {}: {}", file_path.display(), directory);
+        }
+    }
+
+    Ok(())
+}
+
+/// The torrents whose directory is outside the default directory configured for rtorrent
+fn find_nonconforming(file_paths: &[PathBuf], option: &RepToolOption, base: &str) -> Result<Vec<(PathBuf, String)>> {
+    let mut nonconforming = Vec::new();
+    for file_path in file_paths {
+        if !file_path.to_str().expect("Invalid file name").ends_with(".torrent.rtorrent") {
+            continue;
+        }
+
+        let content = fs::read(file_path).with_context(|| format!("Failed to read file: {:?}", file_path))?;
+        // Unparseable files are reported on their own
+        let Ok(value) = bencode::decode(&content) else {
+            continue;
+        };
+        let Some(Value::Bytes(directory)) = value.as_dict().and_then(|dict| dict.get(option.keyword().as_bytes())) else {
+            continue;
+        };
+
+        // Compare whole path components so /data does not cover /database
+        let directory = String::from_utf8_lossy(directory).into_owned();
+        let is_conforming = directory.strip_prefix(base).is_some_and(|rest| rest.is_empty() || rest.starts_with('/'));
+        if !is_conforming {
+            nonconforming.push((file_path.clone(), directory));
+        }
+    }
+
+    Ok(nonconforming)
+}
+
+/// Session data without a .torrent and .torrent files without session data, never deletes anything
+fn find_orphans(file_paths: &[PathBuf]) -> (Vec<String>, Vec<String>) {
+    let mut without_torrent = Vec::new();
+    let mut without_session = Vec::new();
+    for companions in group_companions(file_paths).values() {
+        match &companions.torrent {
+            None => without_torrent.extend(companions.session_files().map(|file_path| file_path.display().to_string())),
+            Some(torrent) if companions.rtorrent.is_none() && companions.resume.is_none() => {
+                without_session.push(torrent.display().to_string());
+            }
+            Some(_) => {}
+        }
+    }
+
+    (without_torrent, without_session)
+}
+
+/// Torrents with a .torrent and some but not all of its session files, with the missing suffixes
+fn find_incomplete(file_paths: &[PathBuf]) -> Vec<(String, Vec<&'static str>)> {
+    let mut incomplete = Vec::new();
+    for companions in group_companions(file_paths).values() {
+        let (Some(torrent), true) = (&companions.torrent, companions.rtorrent.is_some() || companions.resume.is_some()) else {
+            continue;
+        };
+
+        let mut missing = Vec::new();
+        if companions.rtorrent.is_none() {
+            missing.push(".torrent.rtorrent");
+        }
+        if companions.resume.is_none() {
+            missing.push(".torrent.libtorrent_resume");
+        }
+        if !missing.is_empty() {
+            incomplete.push((torrent.display().to_string(), missing));
+        }
+    }
+
+    incomplete
+}
+
+/// Files that cannot be read or are not valid bencode, with the reason
+fn find_unparseable(file_paths: &[PathBuf]) -> Vec<(String, String)> {
+    file_paths.iter()
+        .filter_map(|file_path| {
+            let decoded = fs::read(file_path).map_err(anyhow::Error::from).and_then(|content| bencode::decode(&content));
+            decoded.err().map(|err| (file_path.display().to_string(), format!("{:#}", err)))
+        })
+        .collect()
+}
+
+/// The torrents whose directory does not exist on this machine
+fn find_missing_directories(file_paths: &[PathBuf], option: &RepToolOption) -> Vec<(PathBuf, String)> {
+    let mut missing = Vec::new();
+    for file_path in file_paths {
+        if !file_path.to_str().expect("Invalid file name").ends_with(".torrent.rtorrent") {
+            continue;
+        }
+        let Ok(session_file) = SessionFile::open(file_path, option.global.io_buffer) else {
+            continue;
+        };
+        let Some(Value::Bytes(directory)) = session_file.value().as_dict().and_then(|dict| dict.get(option.keyword().as_bytes())) else {
+            continue;
+        };
+
+        let directory = option.global.encoding.decode(directory).unwrap_or_else(|_| String::from_utf8_lossy(directory).into_owned());
+        if !directory.is_empty() && !Path::new(&directory).exists() {
+            missing.push((file_path.clone(), directory));
+        }
+    }
+
+    missing
+}
+
+/// The .torrent files whose info-hash is not the one they and their session files are named after
+fn find_hash_mismatches(file_paths: &[PathBuf], option: &RepToolOption) -> Vec<(String, InfoHash)> {
+    let mut mismatches = Vec::new();
+    for companions in group_companions(file_paths).values() {
+        let Some(torrent) = &companions.torrent else {
+            continue;
+        };
+        // Unparseable files are reported on their own
+        let Ok(info_hash) = read_buffered(torrent, option.global.io_buffer).map_err(anyhow::Error::from).and_then(|data| InfoHash::of_torrent(&data)) else {
+            continue;
+        };
+        if !info_hash.matches(&info_hash_of(torrent)) {
+            mismatches.push((torrent.display().to_string(), info_hash));
+        }
+    }
+
+    mismatches
+}
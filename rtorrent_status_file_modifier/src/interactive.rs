@@ -0,0 +1,62 @@
+use std::io::{self, BufRead, Write};
+use std::sync::Mutex;
+
+use anyhow::Result;
+use reptool_core::report::ModifyReport;
+
+/// What to do with the files not asked about yet
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum Remaining {
+    #[default]
+    Ask,
+    ApplyAll,
+    SkipAll,
+}
+
+/// The answers of an -i run, shared by its jobs so only one question is shown at a time
+#[derive(Debug, Default)]
+pub struct Confirmations {
+    remaining: Mutex<Remaining>,
+}
+
+impl Confirmations {
+    /// Show the changes of `file_path` and ask whether to write them, like `git add -p`
+    pub fn confirm(&self, file_path: &str, modifications: &[ModifyReport]) -> Result<bool> {
+        let mut remaining = self.remaining.lock().expect("Confirmations poisoned");
+        match *remaining {
+            Remaining::ApplyAll => return Ok(true),
+            Remaining::SkipAll => return Ok(false),
+            Remaining::Ask => {}
+        }
+
+        let mut stderr = io::stderr().lock();
+        writeln!(stderr, "{}", file_path)?;
+        for modification in modifications {
+            writeln!(stderr, "  {}: {} -> {}", modification.key, modification.old_value, modification.new_value)?;
+        }
+        loop {
+            write!(stderr, "Apply this change [y,n,a,q,?]? ")?;
+            stderr.flush()?;
+            let mut answer = String::new();
+            // The end of the input stops the run like q
+            if io::stdin().lock().read_line(&mut answer)? == 0 {
+                writeln!(stderr)?;
+                *remaining = Remaining::SkipAll;
+                return Ok(false);
+            }
+            match answer.trim() {
+                "y" => return Ok(true),
+                "n" => return Ok(false),
+                "a" => {
+                    *remaining = Remaining::ApplyAll;
+                    return Ok(true);
+                }
+                "q" => {
+                    *remaining = Remaining::SkipAll;
+                    return Ok(false);
+                }
+                _ => writeln!(stderr, "y - apply this file\nn - skip this file\na - apply this file and all the next ones\nq - skip this file and all the next ones")?,
+            }
+        }
+    }
+}
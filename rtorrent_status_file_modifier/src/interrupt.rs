@@ -0,0 +1,25 @@
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use anyhow::{Context, Result};
+
+use crate::exit::Status;
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Turn SIGINT and SIGTERM into a request to stop: the files being written are finished, no other file is started
+/// and the run ends with its report. A second signal exits at once, the atomic writes leave no file half written.
+pub fn install() -> Result<()> {
+    ctrlc::set_handler(|| {
+        if INTERRUPTED.swap(true, Ordering::Relaxed) {
+            eprintln!("Interrupted again, exiting now");
+            process::exit(Status::Interrupted as i32);
+        }
+        eprintln!("Interrupted, finishing the files being written (interrupt again to exit now)");
+    }).context("Failed to install the signal handler")
+}
+
+/// Whether the run was asked to stop
+pub fn is_interrupted() -> bool {
+    INTERRUPTED.load(Ordering::Relaxed)
+}
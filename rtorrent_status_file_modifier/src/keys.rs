@@ -0,0 +1,121 @@
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use clap::Args;
+use reptool_core::bencode::Value;
+use reptool_core::report::{FileReport, RunSummary};
+use reptool_core::SessionFile;
+use serde_json::json;
+use tracing::warn;
+
+use crate::backup::backup_before_write;
+use crate::logging::log_changes;
+use crate::glob::FileMatcher;
+use crate::{info_hash_of, list_files, print_change, process_each, RepToolOption};
+
+#[derive(Args, Clone)]
+pub struct GetArgs {
+    /// Session directory
+    pub dir : String,
+
+    /// Top level key of the .torrent.rtorrent files, e.g. custom1, throttle_name or priority
+    pub key : String,
+
+    /// Only the torrent with this info-hash, repeatable
+    #[arg(long, value_name = "HASH")]
+    pub hash : Vec<String>,
+}
+
+#[derive(Args, Clone)]
+pub struct SetArgs {
+    /// Session directory
+    pub dir : String,
+
+    /// Top level key of the .torrent.rtorrent files, added where missing
+    pub key : String,
+
+    /// New value, stored as an integer when the key already holds one or with --integer
+    pub value : String,
+
+    /// Store the value as an integer
+    #[arg(long)]
+    pub integer : bool,
+
+    /// Only the torrent with this info-hash, repeatable
+    #[arg(long, value_name = "HASH")]
+    pub hash : Vec<String>,
+}
+
+/// The .torrent.rtorrent files of the session directory, only those of `hashes` when given
+pub fn session_files(matcher: &FileMatcher, option: &RepToolOption, hashes: &[String]) -> Result<Vec<PathBuf>> {
+    Ok(list_files(matcher, option.input_dir()?, option.max_depth())?.into_iter()
+        .filter(|file_path| file_path.to_str().expect("Invalid file name").ends_with(".torrent.rtorrent"))
+        .filter(|file_path| hashes.is_empty() || hashes.iter().any(|hash| hash.eq_ignore_ascii_case(&info_hash_of(file_path))))
+        .collect())
+}
+
+/// Print the value of a top level key of every .torrent.rtorrent, lists and dictionaries are only named
+pub fn get(matcher: &FileMatcher, option: &RepToolOption, args: &GetArgs) -> Result<()> {
+    let mut entries = Vec::new();
+    for file_path in session_files(matcher, option, &args.hash)? {
+        let session_file = SessionFile::open(&file_path, option.global.io_buffer).with_context(|| format!("Failed to open file: {:?}", file_path))?;
+        let value = match session_file.get(&args.key) {
+            None => json!(null),
+            Some(Value::Integer(integer)) => json!(integer),
+            Some(Value::Bytes(bytes)) => json!(option.global.encoding.decode(bytes).unwrap_or_else(|_| String::from_utf8_lossy(bytes).into_owned())),
+            Some(Value::List(list)) => json!(format!("<list of {}>", list.len())),
+            Some(Value::Dict(dict)) => json!(format!("<dictionary of {}>", dict.len())),
+        };
+        entries.push((file_path.display().to_string(), value));
+    }
+
+    if option.global.json {
+        let entries: Vec<_> = entries.iter().map(|(file, value)| json!({ "file": file, "value": value })).collect();
+        println!("{}", serde_json::to_string_pretty(&entries)?);
+        return Ok(());
+    }
+    for (file, value) in &entries {
+        match value {
+            serde_json::Value::Null => println!("{}: (missing)", file),
+            serde_json::Value::String(text) => println!("{}: {:?}", file, text),
+            value => println!("{}: {}", file, value),
+        }
+    }
+
+    Ok(())
+}
+
+/// Set a top level key of every .torrent.rtorrent, strings are written in --encoding
+pub fn set(matcher: &FileMatcher, option: &RepToolOption, args: &SetArgs) -> Result<RunSummary> {
+    let file_reports = process_each(session_files(matcher, option, &args.hash)?, option, |file_path| {
+        let file = file_path.display().to_string();
+        let mut session_file = SessionFile::open(file_path, option.global.io_buffer).with_context(|| format!("Failed to open file: {:?}", file_path))?;
+
+        let is_integer = args.integer || matches!(session_file.get(&args.key), Some(Value::Integer(_)));
+        let value = if is_integer {
+            Value::Integer(args.value.parse().with_context(|| format!("{} needs an integer, not {:?}", args.key, args.value))?)
+        } else {
+            Value::Bytes(option.global.encoding.encode(&args.value)?)
+        };
+        let modification = match session_file.insert(&args.key, value, option.global.encoding) {
+            Ok(modification) => modification,
+            Err(err) => {
+                warn!("Skipped {}: {:#}", file, err);
+                return Ok(None);
+            }
+        };
+
+        if let (Some(modification), true) = (&modification, option.global.dry_run && !option.global.json) {
+            print_change(&file, modification);
+        }
+        if modification.is_some() && !option.global.dry_run {
+            backup_before_write(option, file_path)?;
+            session_file.save(option.global.io_buffer)?;
+            log_changes(&file, modification.as_slice());
+        }
+
+        Ok(Some(FileReport { matched: modification.is_some(), file, modifications: modification.into_iter().collect(), ..Default::default() }))
+    });
+
+    Ok(RunSummary::new(file_reports))
+}
@@ -0,0 +1,128 @@
+use anyhow::{Context, Result};
+use clap::{ArgGroup, Args};
+use reptool_core::bencode::Value;
+use reptool_core::report::{FileReport, RunSummary};
+use reptool_core::{Encoding, SessionFile};
+use tracing::warn;
+
+use crate::backup::backup_before_write;
+use crate::logging::log_changes;
+use crate::filter::TorrentFilter;
+use crate::glob::FileMatcher;
+use crate::{list_files, print_change, process_each, RepToolOption};
+
+// ruTorrent keeps the label in custom1 URL encoded
+pub const LABEL_KEY: &str = "custom1";
+
+#[derive(Args, Clone)]
+#[command(group(ArgGroup::new("change").required(true).args(["from_label", "set_label"])))]
+pub struct RelabelArgs {
+    /// Session directory
+    pub dir : String,
+
+    /// Only relabel the torrents with this label, an empty one for the unlabeled torrents
+    #[arg(long, value_name = "LABEL", requires = "to_label")]
+    pub from_label : Option<String>,
+
+    /// New label of the torrents labeled --from-label
+    #[arg(long, value_name = "LABEL", requires = "from_label")]
+    pub to_label : Option<String>,
+
+    /// Label every selected torrent, an empty label removes it
+    #[arg(long, value_name = "LABEL")]
+    pub set_label : Option<String>,
+
+    /// Only relabel the torrents whose name matches this regular expression
+    #[arg(long, value_name = "REGEX")]
+    pub name_filter : Option<String>,
+
+    /// Only relabel the torrents with an announce URL matching this regular expression
+    #[arg(long, value_name = "REGEX")]
+    pub tracker_filter : Option<String>,
+}
+
+/// Change the ruTorrent label of the selected torrents, in place
+pub fn relabel(matcher: &FileMatcher, option: &RepToolOption, args: &RelabelArgs) -> Result<RunSummary> {
+    let mut filter_option = option.clone();
+    filter_option.replace.name_filter = args.name_filter.clone();
+    filter_option.replace.tracker_filter = args.tracker_filter.clone();
+    let file_paths: Vec<_> = list_files(matcher, option.input_dir()?, option.max_depth())?.into_iter()
+        .filter(|file_path| file_path.to_str().expect("Invalid file name").ends_with(".torrent.rtorrent"))
+        .collect();
+
+    let file_reports = process_each(TorrentFilter::new(&filter_option)?.retain(file_paths), option, |file_path| {
+        let file = file_path.display().to_string();
+        let mut session_file = SessionFile::open(file_path, option.global.io_buffer).with_context(|| format!("Failed to open file: {:?}", file_path))?;
+        let old_label = match session_file.get(LABEL_KEY) {
+            Some(Value::Bytes(label)) => decode(label),
+            _ => String::new(),
+        };
+
+        let new_label = match (&args.set_label, &args.from_label, &args.to_label) {
+            (Some(label), _, _) => label,
+            (None, Some(from_label), Some(to_label)) if *from_label == old_label => to_label,
+            _ => &old_label,
+        };
+        let modification = match session_file.insert(LABEL_KEY, Value::Bytes(encode(new_label)), Encoding::Utf8) {
+            Ok(modification) => modification,
+            Err(err) => {
+                warn!("Skipped {}: {:#}", file, err);
+                return Ok(None);
+            }
+        };
+        // Report the labels as ruTorrent shows them
+        let modification = modification.filter(|_| *new_label != old_label).map(|mut modification| {
+            modification.old_value = old_label.clone();
+            modification.new_value = new_label.clone();
+            modification
+        });
+
+        if let (Some(modification), true) = (&modification, option.global.dry_run && !option.global.json) {
+            print_change(&file, modification);
+        }
+        if modification.is_some() && !option.global.dry_run {
+            backup_before_write(option, file_path)?;
+            session_file.save(option.global.io_buffer)?;
+            log_changes(&file, modification.as_slice());
+        }
+
+        Ok(Some(FileReport { matched: modification.is_some(), file, modifications: modification.into_iter().collect(), ..Default::default() }))
+    });
+
+    Ok(RunSummary::new(file_reports))
+}
+
+/// The label as ruTorrent writes it to custom1, everything but the unreserved characters is escaped
+pub fn encode(label: &str) -> Vec<u8> {
+    let mut encoded = Vec::with_capacity(label.len());
+    for &byte in label.as_bytes() {
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'_' | b'.' | b'~') {
+            encoded.push(byte);
+        } else {
+            encoded.extend_from_slice(format!("%{:02X}", byte).as_bytes());
+        }
+    }
+
+    encoded
+}
+
+/// The label stored in custom1
+pub fn decode(bytes: &[u8]) -> String {
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        let hex = bytes.get(index + 1..index + 3).and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match (bytes[index], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                index += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                index += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
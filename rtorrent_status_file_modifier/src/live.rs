@@ -0,0 +1,63 @@
+use anyhow::{bail, Context, Result};
+use reptool_core::report::{FileReport, ModifyReport, RunSummary};
+use tracing::{info, warn};
+
+use crate::rpc::RpcClient;
+use crate::template;
+use crate::{print_change, RepToolOption};
+
+/// Rewrite the directory of the torrents loaded in a running rtorrent over XML-RPC, no session file is touched.
+/// Each torrent shows up in the report under its info-hash, one that cannot be moved as a failure.
+pub fn replace_live(option: &RepToolOption) -> Result<RunSummary> {
+    if template::has_placeholders(&option.rules) {
+        bail!("Placeholders in the replace string need the session files, they cannot be used with --live");
+    }
+    let url = option.replace.xmlrpc.as_deref().context("Missing XML-RPC url")?;
+    let client = RpcClient::new(url)?;
+    let replace_options = option.replace_options();
+
+    let mut file_reports = Vec::new();
+    for torrent in client.torrents().context("Failed to list torrents")? {
+        let mut file_report = FileReport { file: torrent.hash.clone(), ..Default::default() };
+        let Some(new_directory) = replace_options.apply(&torrent.directory) else {
+            file_reports.push(file_report);
+            continue;
+        };
+        if !replace_options.is_allowed(&torrent.directory) {
+            warn!("Skipped {}: {:?} is not under any --only-from root", torrent.hash, torrent.directory);
+            file_report.outside_roots.push(torrent.directory.clone());
+            file_reports.push(file_report);
+            continue;
+        }
+
+        let modification = ModifyReport {
+            key: String::from("directory"),
+            old_value: torrent.directory.clone(),
+            new_length: new_directory.len(),
+            old_length: torrent.directory.len(),
+            new_value: new_directory,
+        };
+        if option.global.dry_run {
+            if !option.global.json {
+                print_change(&torrent.hash, &modification);
+            }
+        } else if let Err(err) = client.set_directory(&torrent, &modification.new_value) {
+            // A torrent that cannot be moved is reported, the others are still moved unless --fail-fast
+            warn!("Failed to move torrent {}: {:#}", torrent.hash, err);
+            file_report.error = Some(format!("{:#}", err));
+            file_reports.push(file_report);
+            if option.global.fail_fast {
+                break;
+            }
+            continue;
+        } else {
+            info!("Moved {} to {}", torrent.hash, modification.new_value);
+        }
+
+        file_report.matched = true;
+        file_report.modifications.push(modification);
+        file_reports.push(file_report);
+    }
+
+    Ok(RunSummary::new(file_reports))
+}
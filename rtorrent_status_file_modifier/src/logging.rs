@@ -0,0 +1,64 @@
+use std::fs::OpenOptions;
+use std::path::Path;
+use std::sync::Mutex;
+
+use anyhow::{Context, Result};
+use reptool_core::report::ModifyReport;
+use tracing::info;
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::fmt::{self, MakeWriter};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{Layer, Registry};
+
+use crate::ui;
+
+/// How log lines are written
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    #[default]
+    Text,
+    /// One JSON object per line
+    Json,
+}
+
+type BoxedLayer = Box<dyn Layer<Registry> + Send + Sync>;
+
+/// Log to stderr at the level of -q and -v, and append to `log_file` when given. The log file always gets the
+/// changes, whatever the verbosity, so an unattended run leaves a trail of every value it rewrote.
+pub fn init(quiet: bool, verbose: u8, log_file: Option<&Path>, format: LogFormat) -> Result<()> {
+    let level_filter = match (quiet, verbose) {
+        (true, _) => LevelFilter::ERROR,
+        (false, 0) => LevelFilter::WARN,
+        (false, 1) => LevelFilter::INFO,
+        (false, 2) => LevelFilter::DEBUG,
+        (false, _) => LevelFilter::TRACE,
+    };
+
+    // Logs go to stderr to keep stdout clean for reports
+    let mut layers = vec![layer(std::io::stderr, format, ui::stderr_colors()).with_filter(level_filter).boxed()];
+    if let Some(log_file) = log_file {
+        let file = OpenOptions::new().create(true).append(true).open(log_file)
+            .with_context(|| format!("Failed to open log file: {:?}", log_file))?;
+        layers.push(layer(Mutex::new(file), format, false).with_filter(level_filter.max(LevelFilter::INFO)).boxed());
+    }
+    tracing_subscriber::registry().with(layers).try_init().context("Failed to set the subscriber")
+}
+
+fn layer<W>(writer: W, format: LogFormat, ansi: bool) -> BoxedLayer
+where
+    W: for<'writer> MakeWriter<'writer> + Send + Sync + 'static,
+{
+    let layer = fmt::layer().with_writer(writer).with_ansi(ansi);
+    match format {
+        LogFormat::Text => layer.boxed(),
+        LogFormat::Json => layer.json().boxed(),
+    }
+}
+
+/// One event per rewritten field, once the file is saved
+pub fn log_changes(file_path: &str, modifications: &[ModifyReport]) {
+    for modification in modifications {
+        info!(file = file_path, key = %modification.key, old = %modification.old_value, new = %modification.new_value, "Changed field");
+    }
+}
@@ -1,172 +1,1313 @@
+use std::collections::HashSet;
 use std::fs;
-use std::io::{self, Seek, Read, Write};
-use std::path::{Path};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 
-use regex::Regex;
-use clap::Parser;
-use anyhow::{Context, Result};
-use tracing::{info, span, warn, Level};
-use tracing_subscriber::{filter::LevelFilter, fmt};
+use clap::error::ErrorKind;
+use clap::{CommandFactory, FromArgMatches};
+use anyhow::{bail, Context, Result};
+use regex::bytes::RegexBuilder;
+use tracing::{debug, info, span, warn, Level};
 
-#[derive(Parser)]
-#[command(name = "rtorrent_status_file_modifier")]
-#[command(author = "sontran")]
-#[command(version = "1.0")]
-#[command(about = "Replace string for .torrent.rtorrent", long_about = "This program modifies rtorrent's status file to change the download path for an already loaded torrent.")]
-struct RepToolOption {
-    /// Input path contains .torrent.rtorrent
-    input_path : String,
+mod archive;
+mod backup;
+mod checkpoint;
+mod check;
+mod clients;
+mod cli;
+mod config;
+mod container;
+mod convert;
+mod data;
+mod diff;
+mod dump;
+mod escape;
+mod exit;
+mod files_from;
+mod filter;
+mod fsops;
+mod glob;
+mod hooks;
+#[cfg(feature = "index")]
+mod index;
+mod inspect;
+mod interactive;
+mod interrupt;
+mod keys;
+mod label;
+mod live;
+mod logging;
+mod manifest;
+mod migrate;
+mod notify;
+mod progress;
+mod resume;
+mod retrack;
+mod rpc;
+mod rules;
+mod running;
+#[cfg(feature = "script")]
+mod script;
+mod serve;
+mod service;
+mod session;
+mod state;
+mod template;
+mod timeout;
+mod transaction;
+mod ui;
+mod watch;
+#[cfg(feature = "tui")]
+mod tui;
 
-    /// Search string
-    search_string : String,
+use checkpoint::Checkpoint;
+use cli::{Command, RepToolOption};
+use data::{CheckData, MoveData};
+use exit::Status;
+use filter::TorrentFilter;
+use glob::FileMatcher;
+use logging::log_changes;
+use template::TemplateVars;
+use timeout::WriteGate;
+use reptool_core::file::read_buffered;
+use reptool_core::report::{self, FileReport, ModifyReport, RunSummary};
+use reptool_core::{ReplaceOptions, ReplaceReport, Rule, SessionFile};
+use rpc::RpcClient;
+use running::RunLock;
+use service::ManagedService;
+use state::StateFile;
+use ui::print_change;
 
-    /// Replace string
-    replace_string : String,
+/// Session files under `input_dir`, descending at most `max_depth` levels of subdirectories.
+/// The input path itself may be a symlink, the symlinks inside it are skipped unless the matcher follows them.
+fn list_files(matcher: &FileMatcher, input_dir: &Path, max_depth: usize) -> Result<Vec<PathBuf>> {
+    let real_input_dir = input_dir.canonicalize().with_context(|| format!("Failed to read input directory: {:?}", input_dir))?;
+    let mut walk = Walk { matcher, input_dir, real_input_dir: &real_input_dir, visited: HashSet::from([real_input_dir.clone()]), file_paths: Vec::new() };
+    walk.collect(input_dir, max_depth)?;
+    walk.file_paths.sort();
 
-    /// Show all infos
-    #[arg(short, long)]
-    verbose_mode : bool,
+    Ok(walk.file_paths)
+}
+
+/// A traversal of the input path, `visited` holds the real paths seen so far when following symlinks
+struct Walk<'a> {
+    matcher: &'a FileMatcher,
+    input_dir: &'a Path,
+    real_input_dir: &'a Path,
+    visited: HashSet<PathBuf>,
+    file_paths: Vec<PathBuf>,
+}
+
+impl Walk<'_> {
+    fn collect(&mut self, dir: &Path, max_depth: usize) -> Result<()> {
+        let files = fs::read_dir(dir).with_context(|| format!("Failed to read input directory: {:?}", dir))?;
+        for file in files {
+            let file = file?;
+            let mut file_path = file.path();
+            let mut file_type = file.file_type()?;
+            let is_match = self.matcher.is_match(&file.file_name().to_string_lossy());
+            if file_type.is_symlink() {
+                if !self.matcher.is_following_symlinks() {
+                    if is_match || max_depth > 0 {
+                        warn!("Skipped symlink {}, see --follow-symlinks", file_path.display());
+                    }
+                    continue;
+                }
+                let Ok(real_path) = file_path.canonicalize() else {
+                    warn!("Skipped broken symlink {}", file_path.display());
+                    continue;
+                };
+                file_type = fs::metadata(&real_path)?.file_type();
+                // Edit the target, saving through the link would replace it with a copy
+                file_path = match real_path.strip_prefix(self.real_input_dir) {
+                    Ok(relative_path) => self.input_dir.join(relative_path),
+                    Err(_) => real_path,
+                };
+            }
 
-    /// Define output path to copy and modify, untouch input path files
-    #[arg(short, long, default_value_t = String::from(""))]
-    output_path : String,
+            let is_wanted = (file_type.is_file() && is_match) || (file_type.is_dir() && max_depth > 0);
+            // A directory linked into one of its own subdirectories would loop, a file reached twice is done once
+            if !is_wanted || (self.matcher.is_following_symlinks() && !self.visited.insert(file_path.canonicalize()?)) {
+                continue;
+            }
+            if file_type.is_file() {
+                self.file_paths.push(file_path);
+            } else {
+                self.collect(&file_path, max_depth - 1)?;
+            }
+        }
 
-    /// Define keyword to search and replace
-    #[arg(short, long, default_value_t = String::from("directory"))]
-    keyword : String,
+        Ok(())
+    }
 }
 
-fn replace_files(extensions: &[&str], option: &RepToolOption) -> Result<()> {
-    let input_dir = Path::new(&option.input_path);
-    let output_dir = Path::new(&option.output_path);
+fn replace_files(matcher: &FileMatcher, option: &RepToolOption) -> Result<RunSummary> {
+    let input_dir = option.input_dir()?;
+    if option.rules.is_empty() && !option.replace.normalize && option.rule_set.is_none() && option.replace.script_file.is_none() {
+        bail!("Missing search string");
+    }
+    let output_dir = Path::new(&option.replace.output_path);
 
-    if option.output_path != "" {
+    if !option.replace.output_path.is_empty() && !option.global.dry_run {
         // Create the output directory if it doesn't exist
         if !output_dir.exists() {
-           fs::create_dir_all(output_dir).with_context(|| format!("Failed to create output directory: {:?}", &option.output_path))?;
-        }
-    }
-
-    // Iterate over the files in the input directory
-    let mut is_found = false;
-    let files = fs::read_dir(input_dir).with_context(|| format!("Failed to read input directory: {:?}", &option.input_path))?;
-    for file in files {
-        let file = file?;
-        let file_path = file.path();
-
-        if file_path.is_file() {
-            // Check if the file has one of the desired extensions
-            if extensions.iter().any(|&end| file_path.to_str().expect("Invalid file name").ends_with(end)) {
-                // Copy and process in output path for all related extension
-                if option.output_path != "" {
-                    let file_name = file_path.file_name().expect("Missing file name");
-                    let output_file_path = output_dir.join(file_name);
-                    let output_path_str = &output_file_path.to_str().expect("Invalid file name");
-
-                    // Copy the file to the output directory
-                    fs::copy(&file_path, &output_file_path).with_context(|| format!("Failed to copy file {:?}", file_path))?;
-                    if option.verbose_mode {
-                        info!("Copied file: {}", output_file_path.to_str().expect("Invalid file name"));
-                    }
+           fs::create_dir_all(output_dir).with_context(|| format!("Failed to create output directory: {:?}", &option.replace.output_path))?;
+        }
+    }
 
-                    // Replace the file .torrent.rtorrent
-                    if output_path_str.ends_with(".torrent.rtorrent") {
-                        let result: bool = replace_string_in_file(output_path_str, &option.keyword, &option.search_string, &option.replace_string, option.verbose_mode)?;
-                        if result {
-                            is_found = result;
-                        }
-                    }
-                } else {
-                    // Process file in input path by default
-                    let input_path_str = file_path.to_str().expect("Missing file name");
-
-                    // Replace the file .torrent.rtorrent
-                    if input_path_str.ends_with(".torrent.rtorrent") {
-                        let result: bool = replace_string_in_file(input_path_str, &option.keyword, &option.search_string, &option.replace_string, option.verbose_mode)?;
-                        if result {
-                            is_found = result;
-                        }
-                    }
+    // Ask the running rtorrent which torrents must not be touched
+    let mut protected_hashes = HashSet::new();
+    if option.replace.protect_running_torrents {
+        let url = option.replace.xmlrpc.as_deref().context("Missing XML-RPC url")?;
+        match RpcClient::new(url).and_then(|client| client.active_hashes()) {
+            Ok(hashes) => protected_hashes = hashes,
+            Err(err) if option.replace.strict => return Err(err.context("Failed to query running torrents")),
+            Err(err) => warn!("Failed to query running torrents, nothing is protected: {:#}", err),
+        }
+    }
+
+    // Skip the files a previous interrupted run already finished. In place runs always record their progress, it
+    // is only read back with --resume or an explicit --checkpoint.
+    let fingerprint = rules_fingerprint(option);
+    let is_in_place = option.replace.output_path.is_empty() && !option.global.dry_run && !option.replace.transactional;
+    let checkpoint = match &option.replace.checkpoint {
+        Some(path) => Some(Checkpoint::new(Path::new(path), &fingerprint)),
+        None if is_in_place || option.replace.resume => Some(Checkpoint::new(&input_dir.join(checkpoint::DEFAULT_CHECKPOINT), &fingerprint)),
+        None => None,
+    };
+    let finished_files = match &checkpoint {
+        Some(checkpoint) if option.replace.resume || option.replace.checkpoint.is_some() => checkpoint.load()?,
+        Some(checkpoint) if checkpoint.exists() && !option.global.dry_run => {
+            warn!("A previous run on {} was interrupted, starting over, pass --resume to skip the files it finished", input_dir.display());
+            checkpoint.finish()?;
+            HashSet::new()
+        }
+        _ => HashSet::new(),
+    };
+    if !finished_files.is_empty() {
+        info!("Resuming from checkpoint, {} file(s) already done", finished_files.len());
+    } else if option.replace.resume {
+        info!("Nothing to resume in {}, processing every file", input_dir.display());
+    }
+    let checkpoint = checkpoint.filter(|_| !option.global.dry_run);
+    let file_paths = match &option.listed_files {
+        Some(listed_files) => listed_files.iter()
+            .filter(|file_path| file_path.file_name().is_some_and(|name| matcher.is_match(&name.to_string_lossy())))
+            .cloned()
+            .collect(),
+        None => list_files(matcher, input_dir, option.max_depth())?,
+    };
+    // An output path inside the input directory holds the mirrored copies of an earlier run, they are no input
+    let nested_output_dir = output_dir.canonicalize().ok()
+        .filter(|_| !option.replace.output_path.is_empty() && option.max_depth() > 0)
+        .filter(|output_dir| input_dir.canonicalize().is_ok_and(|input_dir| output_dir.starts_with(input_dir)));
+    let file_paths: Vec<PathBuf> = match nested_output_dir {
+        Some(output_dir) => file_paths.into_iter()
+            .filter(|file_path| !file_path.canonicalize().is_ok_and(|file_path| file_path.starts_with(&output_dir)))
+            .collect(),
+        None => file_paths,
+    };
+    let file_paths: Vec<PathBuf> = file_paths.into_iter()
+        .filter(|file_path| !finished_files.contains(file_path))
+        .collect();
+    let mut file_paths = TorrentFilter::new(option)?.retain(file_paths);
+
+    // Leave the files as the previous run with the same rules left them
+    let mut state_file = None;
+    if option.replace.since_last_run {
+        let state_path = option.replace.state_file.clone().unwrap_or_else(|| input_dir.join(state::DEFAULT_STATE_FILE));
+        let loaded = StateFile::load(&state_path, &fingerprint)?;
+        let count = file_paths.len();
+        file_paths.retain(|file_path| !output_path_of(file_path, option).is_ok_and(|output_path| loaded.is_unchanged(file_path, output_path.as_deref())));
+        info!("{} file(s) unchanged since the last run", count - file_paths.len());
+        state_file = Some(loaded);
+    }
+
+    // Iterate over the files in the input directory, one bounded batch at a time
+    let batch_size = option.replace.batch_size.unwrap_or(file_paths.len()).max(1);
+    let batch_count = file_paths.len().div_ceil(batch_size);
+    let progress = progress::file_progress(file_paths.len(), option);
+    let files_modified = AtomicUsize::new(0);
+    // Only the totals and the noteworthy reports outlive their batch
+    let mut summary = RunSummary::default();
+    for (batch_index, batch) in file_paths.chunks(batch_size).enumerate() {
+        let batch_reports = run_parallel(batch, usize::from(option.replace.jobs), option.global.fail_fast, |file_path| {
+            let file_report = run_file(file_path, option, &protected_hashes);
+            if file_report.matched {
+                progress.set_message((files_modified.fetch_add(1, Ordering::Relaxed) + 1).to_string());
+            }
+            progress.inc(1);
+            file_report
+        });
+
+        let mut batch_matched = 0;
+        let mut batch_finished = Vec::new();
+        for (file_path, file_report) in batch.iter().zip(batch_reports) {
+            if file_report.matched {
+                batch_matched += 1;
+            }
+            // Failed and timed out files stay out of the checkpoint so a restart retries them
+            if file_report.error.is_none() && !file_report.timed_out {
+                batch_finished.push(file_path.clone());
+            }
+            summary.add(file_report);
+        }
+        let mut is_stopped = (option.global.fail_fast && batch_finished.len() < batch.len()) || interrupt::is_interrupted();
+
+        // With --batch-size a --transactional run commits batch by batch, a batch with a failure is rolled back
+        if let (Some(transaction), Some(_)) = (&option.transaction, option.replace.batch_size) {
+            if batch_finished.len() < batch.len() || interrupt::is_interrupted() {
+                let rolled_back = transaction.rollback();
+                warn!("Rolled back batch {}/{}, none of its {} staged file(s) was written", batch_index + 1, batch_count, rolled_back);
+                is_stopped = true;
+            } else {
+                transaction.commit()?;
+            }
+        }
+
+        // The files staged by --transactional are not written yet, the transaction may still roll them back
+        if let Some(checkpoint) = checkpoint.as_ref().filter(|_| option.transaction.is_none()) {
+            for file_path in &batch_finished {
+                if let Err(err) = checkpoint.record(file_path) {
+                    warn!("{:#}", err);
                 }
             }
+            checkpoint.sync()?;
+        }
+        if let Some(state_file) = state_file.as_mut().filter(|_| !option.global.dry_run) {
+            let outputs = batch_finished.iter().map(|file_path| output_path_of(file_path, option)).collect::<Result<Vec<_>>>()?;
+            state_file.record(batch_finished.iter().zip(outputs))?;
+        }
+        if option.replace.batch_size.is_some() {
+            info!("Batch {}/{} done: {} file(s), {} matched", batch_index + 1, batch_count, batch.len(), batch_matched);
         }
+        if is_stopped {
+            break;
+        }
+    }
+
+    progress.finish_and_clear();
+
+    if let Some(state_file) = state_file.as_mut().filter(|_| !option.global.dry_run) {
+        state_file.save()?;
+    }
+    if let Some(checkpoint) = checkpoint.as_ref().filter(|_| summary.files_failed == 0 && summary.files_timed_out == 0 && !interrupt::is_interrupted()) {
+        checkpoint.finish()?;
     }
-    if !is_found {
+
+    Ok(summary)
+}
+
+/// Identifies what a replace run does to the files, for --since-last-run and --resume
+fn rules_fingerprint(option: &RepToolOption) -> String {
+    state::fingerprint(&format!("{:?} {:?} {:?} {:?} {:?}", option.global.keywords, option.replace_options(), option.rule_set, option.replace.script_file, option.replace.output_path))
+}
+
+/// The warnings and the dry run line closing a replace run, once for all the input directories
+fn print_summary(summary: &RunSummary, option: &RepToolOption) {
+    if summary.files_missing_data > 0 {
+        warn!("{} file(s) point to data missing on disk", summary.files_missing_data);
+    }
+    if summary.files_missing_keyword > 0 {
+        warn!("{} file(s) have no field matching {}", summary.files_missing_keyword, option.global.keywords.join(", "));
+    }
+    if summary.files_already_migrated > 0 {
+        info!("{} file(s) already migrated, their fields hold the new values", summary.files_already_migrated);
+    }
+    if summary.files_skipped_no_match > 0 {
+        info!("{} file(s) skipped by the pre-scan, none of the search strings is in them", summary.files_skipped_no_match);
+    }
+    if summary.files_matched == 0 && summary.files_already_migrated == 0 && !option.replace.allow_empty {
         warn!("No matching found.");
     }
+    if let Some(max_replacements) = option.replace.max_replacements {
+        let left = option.replacements.load(Ordering::SeqCst).saturating_sub(max_replacements);
+        if left > 0 {
+            info!("{} more file(s) would change, left for a later run by --max-replacements {}", left, max_replacements);
+        }
+    }
+    if option.replace.count_only {
+        println!("{} of {} file(s) would change, {} field(s) in all", summary.files_matched, summary.files_scanned, summary.fields_rewritten);
+        return;
+    }
+    if option.global.dry_run && !option.global.json {
+        println!("Dry run: {} of {} file(s) would change, nothing was written", summary.files_matched, summary.files_scanned);
+    }
+    if !option.global.json && !option.global.quiet {
+        println!("{}", statistics(summary));
+    }
+}
 
-    Ok(())
+/// The totals of a run on one line, e.g. `Scanned 120 file(s) in 0.4s (300 files/s): 12 matched, ...`
+fn statistics(summary: &RunSummary) -> String {
+    format!("Scanned {} file(s) in {:.1}s ({:.0} files/s): {} matched, {} field(s) rewritten, {} written, {} of data moved, {} error(s)",
+        summary.files_scanned, summary.elapsed_ms as f64 / 1000.0, summary.files_per_second, summary.files_matched,
+        summary.fields_rewritten, inspect::format_size(summary.bytes_written), inspect::format_size(summary.data_moved), summary.files_failed)
+}
+
+/// Run `run` over `file_paths` on `jobs` threads, the reports keep the order of `file_paths`
+fn run_parallel(file_paths: &[PathBuf], jobs: usize, fail_fast: bool, run: impl Fn(&Path) -> FileReport + Sync) -> Vec<FileReport> {
+    // With fail_fast no file is started once one failed, the ones already running finish
+    let is_stopped = AtomicBool::new(false);
+    let next_index = AtomicUsize::new(0);
+    let reports = Mutex::new(Vec::with_capacity(file_paths.len()));
+    let work = || loop {
+        let index = next_index.fetch_add(1, Ordering::Relaxed);
+        let Some(file_path) = file_paths.get(index).filter(|_| !is_stopped.load(Ordering::Relaxed) && !interrupt::is_interrupted()) else {
+            break;
+        };
+        let file_report = run(file_path);
+        if fail_fast && file_report.error.is_some() {
+            is_stopped.store(true, Ordering::Relaxed);
+        }
+        reports.lock().expect("Report list poisoned").push((index, file_report));
+    };
+    if jobs <= 1 {
+        work();
+    } else {
+        thread::scope(|scope| {
+            for _ in 0..jobs.min(file_paths.len()) {
+                scope.spawn(work);
+            }
+        });
+    }
+
+    let mut reports = reports.into_inner().expect("Report list poisoned");
+    reports.sort_by_key(|(index, _)| *index);
+    reports.into_iter().map(|(_, file_report)| file_report).collect()
+}
+
+/// Process one file of the run, a broken file is reported instead of stopping the run
+fn run_file(file_path: &Path, option: &RepToolOption, protected_hashes: &HashSet<String>) -> FileReport {
+    if protected_hashes.contains(&info_hash_of(file_path)) {
+        ui::print_skipped(&file_path.display().to_string(), "active torrent");
+        return FileReport { file: file_path.display().to_string(), protected: true, ..Default::default() };
+    }
+
+    let result = match option.replace.file_timeout {
+        Some(seconds) => process_file_with_timeout(file_path, option, Duration::from_secs(seconds)),
+        None => process_file(file_path, option).map(Some),
+    };
+    match result {
+        Ok(Some(file_report)) => file_report,
+        Ok(None) => {
+            warn!("Timed out processing file: {}", file_path.display());
+            FileReport { file: file_path.display().to_string(), timed_out: true, ..Default::default() }
+        }
+        Err(err) => failed_report(file_path, &err),
+    }
 }
 
-fn replace_string_in_file(file_path: &str, key: &str, find: &str, replace: &str, verbose: bool) -> Result<bool> {
-    if verbose {
-       info!("Processing file: {}", file_path);
+/// The report of a file that could not be processed
+fn failed_report(file_path: &Path, err: &anyhow::Error) -> FileReport {
+    warn!("Failed to process file {}: {:#}", file_path.display(), err);
+    FileReport { file: file_path.display().to_string(), error: Some(format!("{:#}", err)), ..Default::default() }
+}
+
+/// Run `process` on every file in turn, a file that fails is reported and the next one processed unless --fail-fast.
+/// `process` gives `None` for the files it skips.
+fn process_each(file_paths: impl IntoIterator<Item = PathBuf>, option: &RepToolOption, mut process: impl FnMut(&Path) -> Result<Option<FileReport>>) -> Vec<FileReport> {
+    let mut file_reports = Vec::new();
+    for file_path in file_paths {
+        if interrupt::is_interrupted() {
+            break;
+        }
+        match process(&file_path) {
+            Ok(file_report) => file_reports.extend(file_report),
+            Err(err) => {
+                file_reports.push(failed_report(&file_path, &err));
+                if option.global.fail_fast {
+                    break;
+                }
+            }
+        }
+    }
+    file_reports
+}
+
+/// Run `process_file` on a worker thread and give up waiting after `timeout`, returns `None` when timed out.
+/// The worker is detached rather than killed. A worker that has not started writing its file is kept from writing
+/// it, one that has is waited for before the run lock is released, and its file reported as possibly written.
+fn process_file_with_timeout(file_path: &Path, option: &RepToolOption, timeout: Duration) -> Result<Option<FileReport>> {
+    let (sender, receiver) = mpsc::channel();
+    let gate = Arc::new(WriteGate::default());
+    let mut option = option.clone();
+    option.write_gate = Some(Arc::clone(&gate));
+    let worker = thread::spawn({
+        let file_path = file_path.to_path_buf();
+        move || {
+            // The receiver is gone when the file already timed out
+            let _ = sender.send(process_file(&file_path, &option));
+        }
+    });
+
+    match receiver.recv_timeout(timeout) {
+        Ok(result) => result.map(Some),
+        Err(mpsc::RecvTimeoutError::Timeout) if gate.cancel() => Ok(None),
+        Err(mpsc::RecvTimeoutError::Timeout) => {
+            timeout::keep_late_writer(worker);
+            warn!("Timed out writing file, it may still be written: {}", file_path.display());
+            let error = String::from("Timed out while being written, the file may still be written");
+            Ok(Some(FileReport { file: file_path.display().to_string(), timed_out: true, error: Some(error), ..Default::default() }))
+        }
+        Err(mpsc::RecvTimeoutError::Disconnected) => bail!("Worker thread panicked"),
+    }
+}
+
+/// Copy (in output mode) and modify one file
+fn process_file(file_path: &Path, option: &RepToolOption) -> Result<FileReport> {
+    // Copy and process in output path for all related extension, process file in input path by default
+    let target_path = if let Some(output_file_path) = output_path_of(file_path, option)? {
+        if let Some(parent) = output_file_path.parent() {
+            fs::create_dir_all(parent).with_context(|| format!("Failed to create output directory: {:?}", parent))?;
+        }
+
+        // Copy the file to the output directory
+        fs::copy(file_path, &output_file_path).with_context(|| format!("Failed to copy file {:?}", file_path))?;
+        debug!("Copied file: {}", output_file_path.to_str().expect("Invalid file name"));
+        output_file_path
+    } else {
+        file_path.to_path_buf()
+    };
+    let target_path_str = target_path.to_str().expect("Invalid file name");
+    let is_rtorrent = target_path_str.ends_with(".torrent.rtorrent");
+    let is_resume = option.replace.reset_resume && target_path_str.ends_with(".libtorrent_resume");
+
+    // Keep the original before it gets edited in place
+    let mut archived_to = None;
+    if let Some(archive_dir) = option.replace.archive_originals_to.as_deref().filter(|_| (is_rtorrent || is_resume) && !option.global.dry_run) {
+        archived_to = archive::archive_original(file_path, option.input_dir()?, Path::new(archive_dir))?;
+    }
+
+    // Replace the file .torrent.rtorrent
+    let mut rewrite = Rewrite::default();
+    let mut skipped_no_match = false;
+    if is_rtorrent {
+        match replace_string_in_file(target_path_str, &option.global.keywords, &option.replace_options(), option)? {
+            Some(rewritten) => rewrite = rewritten,
+            None => skipped_no_match = true,
+        }
+    }
+    let report = rewrite.report;
+
+    // Drop the resume state of .libtorrent_resume so rtorrent rechecks the data
+    let mut is_reset = false;
+    if is_resume && option.global.dry_run {
+        if !option.replace.count_only {
+            println!("{}: resume data would be reset", target_path_str);
+        }
+    } else if is_resume {
+        let _writing = option.write_gate.as_ref().map(|gate| gate.enter()).transpose()?;
+        is_reset = resume::reset_resume_file(target_path_str, option)?;
+    }
+
+    if option.replace.preserve && target_path != file_path {
+        fsops::preserve_metadata(file_path, &target_path)?;
+    }
+
+    // An archive of a file that was left untouched is useless
+    if let Some(archive_path) = &archived_to {
+        if report.modifications.is_empty() && !is_reset {
+            fs::remove_file(archive_path).with_context(|| format!("Failed to remove archive: {:?}", archive_path))?;
+            archived_to = None;
+        } else {
+            info!("Archived original: {}", archive_path.display());
+        }
+    }
+
+    Ok(FileReport {
+        file: target_path_str.to_owned(),
+        matched: !report.modifications.is_empty(),
+        archived_to: archived_to.map(|path| path.display().to_string()),
+        missing_keyword: is_rtorrent && !skipped_no_match && report.keys.is_empty(),
+        skipped_no_match,
+        already_migrated: report.modifications.is_empty() && !report.already_migrated.is_empty(),
+        modifications: report.modifications,
+        outside_roots: report.outside_roots,
+        missing_data: rewrite.missing_data,
+        bytes_written: rewrite.bytes_written,
+        data_moved: rewrite.data_moved,
+        ..Default::default()
+    })
+}
+
+/// Where `file_path` is copied to and rewritten with an output path, `None` when it is rewritten in place
+fn output_path_of(file_path: &Path, option: &RepToolOption) -> Result<Option<PathBuf>> {
+    if option.replace.output_path.is_empty() || option.global.dry_run {
+        return Ok(None);
+    }
+    // Keep the subdirectory of a recursive scan
+    let relative_path = file_path.strip_prefix(option.input_dir()?).ok()
+        .or_else(|| file_path.file_name().map(Path::new))
+        .context("Missing file name")?;
+    Ok(Some(Path::new(&option.replace.output_path).join(relative_path)))
+}
+
+/// Session files are named after the info-hash of their torrent, e.g. `<HASH>.torrent.rtorrent`
+fn info_hash_of(file_path: &Path) -> String {
+    let file_name = file_path.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+    file_name.split('.').next().unwrap_or_default().to_uppercase()
+}
+
+/// What `replace_string_in_file` did to a file
+#[derive(Default)]
+struct Rewrite {
+    report: ReplaceReport,
+    missing_data: Vec<String>,
+    bytes_written: u64,
+    /// Size of the data moved or copied by --move-data
+    data_moved: u64,
+}
+
+/// `None` when the pre-scan finds none of the search strings in the file
+fn replace_string_in_file(file_path: &str, keys: &[String], options: &ReplaceOptions, option: &RepToolOption) -> Result<Option<Rewrite>> {
+    debug!("Processing file: {}", file_path);
+
+    // Most files of a large session hold none of the search strings, they are not worth decoding
+    let content = read_buffered(Path::new(file_path), option.global.io_buffer).with_context(|| format!("Failed to open file: {:?}", file_path))?;
+    if option.rule_set.is_none() && option.replace.script_file.is_none() && !options.may_match(&content) {
+        debug!("Skipped {}: no search string in the file", file_path);
+        return Ok(None);
+    }
+    let mut session_file = SessionFile::decode(file_path, &content).with_context(|| format!("Failed to open file: {:?}", file_path))?;
+
+    // Placeholders like {name} resolve differently for every torrent
+    let expanded_rules;
+    let expanded_options;
+    let options = if template::has_placeholders(options.rules) {
+        expanded_rules = TemplateVars::new(&session_file, option.global.io_buffer).expand_rules(options.rules, Path::new(file_path))?;
+        expanded_options = ReplaceOptions { rules: &expanded_rules, ..*options };
+        &expanded_options
+    } else {
+        options
+    };
+    let mut report = match &option.rule_set {
+        Some(rule_set) => rule_set.apply(&mut session_file, keys, options, option.global.io_buffer),
+        None => session_file.replace(keys, options),
+    }.with_context(|| format!("Failed to read the keys of file: {:?}", file_path))?;
+
+    if option.replace.script_file.is_some() && file_path.ends_with(".torrent.rtorrent") {
+        for modification in run_script(&mut session_file, option)? {
+            rules::merge(&mut report.modifications, modification);
+        }
+    }
+
+    if report.keys.is_empty() {
+        info!("Skipped {}: no field matching {}", file_path, keys.join(", "));
+    }
+    if report.modifications.is_empty() && !report.already_migrated.is_empty() {
+        info!("Skipped {}: already migrated", file_path);
+    }
+    // A trailing wildcard expands to every concrete key sharing the prefix, logged to keep stdout for the reports
+    if keys.iter().any(|key| key.ends_with('*')) && !report.keys.is_empty() {
+        info!("{}: keyword {} matched [{}]", file_path, keys.join(", "), report.keys.join(", "));
+    }
+    for value in &report.outside_roots {
+        ui::print_skipped(file_path, &format!("{:?} is not under any --only-from root", value));
+    }
+
+    // Catch a typo in the replace string before rtorrent marks the data as missing
+    let mut missing_data = Vec::new();
+    if let Some(check_data) = option.replace.check_data {
+        missing_data = data::missing_data(&session_file, &report.modifications, option.replace.check_files, option.global.io_buffer);
+        for data_path in &missing_data {
+            warn!("{}: new data path does not exist: {}", file_path, data_path.display());
+        }
+        if check_data == CheckData::Skip && !missing_data.is_empty() {
+            ui::print_skipped(file_path, "data missing at the new location");
+            report.modifications.clear();
+        }
+    }
+
+    // Files past --max-replacements are left for a later run
+    if let Some(max_replacements) = option.replace.max_replacements.filter(|_| !report.modifications.is_empty()) {
+        if option.replacements.fetch_add(1, Ordering::SeqCst) >= max_replacements {
+            info!("Skipped {}: --max-replacements {} reached", file_path, max_replacements);
+            report.modifications.clear();
+        }
+    }
+
+    match option.replace.diff {
+        _ if option.global.json || option.replace.count_only || report.modifications.is_empty() => {}
+        Some(style) => print!("{}", diff::render(file_path, &report.modifications, style, ui::stdout_colors())),
+        None if option.global.dry_run => {
+            for modification in &report.modifications {
+                print_change(file_path, modification);
+            }
+        }
+        None => {}
+    }
+
+    if option.replace.interactive && !report.modifications.is_empty() && !option.confirmations.confirm(file_path, &report.modifications)? {
+        info!("Skipped {}: declined", file_path);
+        report.modifications.clear();
+    }
+
+    // With --file-timeout a file that timed out is not touched any more
+    let _writing = match &option.write_gate {
+        Some(gate) if !report.modifications.is_empty() && !option.global.dry_run => Some(gate.enter()?),
+        _ => None,
+    };
+
+    // The session file is only rewritten once its data is in place, the data is put back when it cannot be written
+    let is_relocating = option.replace.move_data.is_some() || option.replace.link_data.is_some();
+    let mut data_moved = 0;
+    let mut relocated = Vec::new();
+    if is_relocating && !report.modifications.is_empty() {
+        for (from, to) in data::payload_moves(&session_file, &report.modifications, option.global.io_buffer)? {
+            if option.replace.count_only {
+                continue;
+            }
+            if option.global.dry_run {
+                let action = match (option.replace.move_data, option.replace.link_data) {
+                    (Some(MoveData::Copy), _) => String::from("copy"),
+                    (None, Some(link_data)) => format!("{:?}", link_data).to_lowercase(),
+                    _ => String::from("move"),
+                };
+                println!("{}: data would {} {} -> {}", file_path, action, from.display(), to.display());
+                continue;
+            }
+            let moved = match (option.replace.move_data, option.replace.link_data) {
+                (Some(move_data), _) => {
+                    let size = data::tree_size(&from);
+                    let relocated_data = data::relocate(&from, &to, move_data);
+                    if relocated_data.is_ok() {
+                        data_moved += size;
+                    }
+                    relocated_data.map(|()| move_data == MoveData::Move)
+                }
+                (None, Some(link_data)) => data::link(&from, &to, link_data).map(|()| false),
+                (None, None) => continue,
+            };
+            match moved {
+                Ok(moved) => {
+                    info!("Relocated data {} -> {}", from.display(), to.display());
+                    relocated.push(data::Relocated { from, to, moved });
+                }
+                Err(err) => {
+                    data::undo_all(&relocated);
+                    return Err(err);
+                }
+            }
+        }
+    }
+
+    // Update new content to file
+    let mut bytes_written = 0;
+    if !report.modifications.is_empty() && !option.global.dry_run {
+        let hook_vars = hooks::file_vars(file_path, &report.modifications, option.keyword());
+        let written = (|| {
+            if let Some(pre_file_cmd) = &option.replace.pre_file_cmd {
+                hooks::run(pre_file_cmd, &hook_vars, false)?;
+            }
+            backup::backup_before_write(option, Path::new(file_path))?;
+            match &option.transaction {
+                Some(transaction) => transaction.stage(&session_file, &report.modifications, options.encoding, option.global.io_buffer),
+                None => session_file.save_checked(&report.modifications, options.encoding, option.global.io_buffer),
+            }
+        })();
+        bytes_written = match written {
+            Ok(bytes_written) => bytes_written,
+            Err(err) => {
+                data::undo_all(&relocated);
+                return Err(err);
+            }
+        };
+        // A staged file is only written on commit, its data goes back with a rollback
+        if let Some(transaction) = &option.transaction {
+            transaction.keep_relocated(relocated);
+        }
+        log_changes(file_path, &report.modifications);
+        if let Some(post_file_cmd) = &option.replace.post_file_cmd {
+            if let Err(err) = hooks::run(post_file_cmd, &hook_vars, false) {
+                warn!("{}: {:#}", file_path, err);
+            }
+        }
     }
 
-    let mut is_found = false;
-    let mut file = fs::OpenOptions::new().read(true).write(true).open(file_path).with_context(|| format!("Failed to open file: {:?}", file_path))?;
-    let mut content = String::new();
+    let missing_data = missing_data.iter().map(|data_path| data_path.display().to_string()).collect();
+    Ok(Some(Rewrite { report, missing_data, bytes_written, data_moved }))
+}
 
-    file.read_to_string(&mut content)?;
+/// The positional pair, the -e pairs, the --map-file pairs and the container mappings, in that order
+fn collect_rules(option: &RepToolOption) -> Result<Vec<Rule>> {
+    let mut rules = Vec::new();
+    if let (Some(find), Some(replace)) = (&option.replace.search_string, &option.replace.replace_string) {
+        rules.push(Rule { find: find.clone(), replace: replace.clone(), ignore_case: false });
+    }
+    for mapping in &option.replace.mapping {
+        rules.push(Rule::parse(mapping)?);
+    }
+    if let Some(map_file) = &option.replace.map_file {
+        let content = fs::read_to_string(map_file).with_context(|| format!("Failed to read map file: {:?}", map_file))?;
+        for line in content.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#')) {
+            rules.push(Rule::parse(line).with_context(|| format!("Invalid line in map file: {:?}", map_file))?);
+        }
+    }
 
-    // Only get directory:path to replace
-    let re = Regex::new(format!(r#":({})(\d+):([^:]+)"#, key).as_str()).expect("Failed to construct regex pattern");
-    let mat = re.find(&content).expect("Failed to match pattern");
+    // Decode the escapes once, the length prefix is computed from the decoded bytes. A regular expression has escapes
+    // of its own.
+    for rule in &mut rules {
+        if !option.replace.regex {
+            rule.find = escape::decode(&rule.find, option.replace.no_escape)?;
+        }
+        rule.replace = escape::decode(&rule.replace, option.replace.no_escape)?;
+    }
 
-    let find_content = &content[mat.start()..mat.end()];
+    if let Some(map_to) = option.replace.map_to {
+        let mut mounts = option.replace.container_map.iter().map(|mapping| container::Mount::parse(mapping)).collect::<Result<Vec<_>>>()?;
+        if let Some(compose_file) = &option.replace.compose_file {
+            mounts.extend(container::read_compose(compose_file, option.replace.compose_service.as_deref())?);
+        }
+        rules.extend(container::rules(&mounts, map_to, option.replace.check_side)?);
+    }
 
-    for cap in re.captures_iter(&content) {
+    Ok(rules)
+}
 
-        // Check whether pattern exist or not
+/// Save the run report to --report-file, for scripts that cannot parse stdout, and send it to --notify-url
+fn write_report(summary: &RunSummary, option: &RepToolOption) -> Result<()> {
+    if let Some(notify_url) = &option.global.notify_url {
+        notify::send_summary(notify_url, summary, option.global.notify_errors);
+    }
+    let Some(report_file) = &option.global.report_file else {
+        return Ok(());
+    };
+    fs::write(report_file, serde_json::to_string_pretty(summary)?).with_context(|| format!("Failed to write report: {:?}", report_file))
+}
 
-        if cap[3].contains(&find) {
-            is_found = true;
-            let offset_size: i32 = replace.len() as i32 - find.len() as i32;
-            let num: i32 = cap[2].parse().expect("Failed to convert string len");
-            let new_size = num + offset_size;
-            let mut update_string: String = ":".to_owned();
-            update_string.push_str(&cap[1]);
-            update_string.push_str(&new_size.to_string());
-            update_string.push_str(":");
-            let new_path = cap[3].replacen(find, replace, 1);
-            update_string.push_str(&new_path) ;
-            let modified_content = content.replace(&find_content, &update_string);
+/// One line per changed field, as previewed by --dry-run
+#[cfg(feature = "tui")]
+fn browse(matcher: &FileMatcher, dir: &Path, option: &RepToolOption) -> Result<()> {
+    tui::browse(&list_files(matcher, dir, option.max_depth())?, option.keyword(), option.global.encoding)
+}
 
-            // Update new content to file
-            file.seek(io::SeekFrom::Start(0))?;
-            file.write_all(modified_content.as_bytes())?;
-            file.set_len(modified_content.len() as u64)?;
+#[cfg(not(feature = "tui"))]
+fn browse(_matcher: &FileMatcher, _dir: &Path, _option: &RepToolOption) -> Result<()> {
+    bail!("The browse command is not available, rebuild with --features tui")
+}
+
+#[cfg(feature = "index")]
+fn update_index(matcher: &FileMatcher, dir: &Path, db: Option<&Path>, query: Option<&str>, option: &RepToolOption) -> Result<()> {
+    let db_path = db.map_or_else(|| dir.join(index::DEFAULT_INDEX_DB), Path::to_path_buf);
+    let file_paths = list_files(matcher, dir, option.max_depth())?;
+    let refresh = index::refresh(&db_path, &file_paths, option.keyword(), option.global.encoding)?;
+    match query {
+        Some(query) => index::print_query(&db_path, query, option.global.json),
+        None => {
+            println!("Indexed {} torrent(s), {} updated", refresh.indexed, refresh.updated);
+            Ok(())
         }
     }
+}
+
+#[cfg(not(feature = "index"))]
+fn update_index(_matcher: &FileMatcher, _dir: &Path, _db: Option<&Path>, _query: Option<&str>, _option: &RepToolOption) -> Result<()> {
+    bail!("The index command is not available, rebuild with --features index")
+}
+
+#[cfg(feature = "script")]
+fn load_script(option: &mut RepToolOption) -> Result<()> {
+    option.script = option.replace.script_file.as_deref().map(script::Script::load).transpose()?.map(Arc::new);
+    Ok(())
+}
+
+#[cfg(not(feature = "script"))]
+fn load_script(option: &mut RepToolOption) -> Result<()> {
+    if option.replace.script_file.is_some() {
+        bail!("--script is not available, rebuild with --features script");
+    }
+    Ok(())
+}
+
+/// The fields set by --script on a .torrent.rtorrent
+#[cfg(feature = "script")]
+fn run_script(session_file: &mut SessionFile, option: &RepToolOption) -> Result<Vec<ModifyReport>> {
+    match &option.script {
+        Some(script) => script.apply(session_file, option.global.encoding, option.global.io_buffer),
+        None => Ok(Vec::new()),
+    }
+}
+
+#[cfg(not(feature = "script"))]
+fn run_script(_session_file: &mut SessionFile, _option: &RepToolOption) -> Result<Vec<ModifyReport>> {
+    Ok(Vec::new())
+}
+
+/// Keep everything else out of `session_dir` for as long as the returned lock lives: refuse a running rtorrent and
+/// take the run lock, waiting for another run with --wait. A dry run locks nothing.
+fn lock_session(session_dir: &Path, option: &RepToolOption) -> Result<Option<RunLock>> {
+    if option.global.dry_run {
+        return Ok(None);
+    }
+    running::ensure_not_running(session_dir, option.global.force)?;
+    RunLock::acquire(session_dir, option.global.wait).map(Some)
+}
+
+/// `lock_session` on the input directory of `option`, and a new backup run for the files the run rewrites unless
+/// a dry run
+fn lock_for_run(option: &mut RepToolOption) -> Result<Option<RunLock>> {
+    let lock = lock_session(option.input_dir()?, option)?;
+    if !option.global.dry_run {
+        option.backup_run = Some(backup::new_run(option.input_dir()?)?);
+    }
+    Ok(lock)
+}
+
+/// Run a batch command editing the session directory of `option` under `lock_for_run`, save its report, print it
+/// with --json and else the line `message` makes of it, and fail when some files failed
+fn run_batch(mut option: RepToolOption, run: impl FnOnce(&RepToolOption) -> Result<RunSummary>, message: impl FnOnce(&RunSummary) -> String) -> Result<Status> {
+    let _lock = lock_for_run(&mut option)?;
+    let summary = run(&option)?;
+    write_report(&summary, &option)?;
+    if option.global.json {
+        println!("{}", serde_json::to_string_pretty(&summary)?);
+    } else {
+        println!("{}", message(&summary));
+    }
+    failure_status(&summary, &option)
+}
 
-    Ok(is_found)
+/// The hashes of the torrents selected by --where, all torrents without it
+#[cfg(feature = "index")]
+fn indexed_hashes(option: &RepToolOption) -> Result<Option<HashSet<String>>> {
+    let Some(condition) = &option.replace.where_condition else {
+        return Ok(None);
+    };
+    let db_path = match &option.replace.index_db {
+        Some(index_db) => index_db.clone(),
+        None => option.input_dir()?.join(index::DEFAULT_INDEX_DB),
+    };
+    index::matching_hashes(&db_path, condition).map(Some)
 }
 
-fn main() -> Result<()> {
+#[cfg(not(feature = "index"))]
+fn indexed_hashes(option: &RepToolOption) -> Result<Option<HashSet<String>>> {
+    if option.replace.where_condition.is_some() {
+        bail!("--where is not available, rebuild with --features index");
+    }
+    Ok(None)
+}
+
+/// With --files-from or --input the input path may be left out, the positional arguments are then shifted by one.
+/// The first --input takes the place of a missing input path.
+fn shift_positionals(option: &mut RepToolOption) {
+    let replace = &mut option.replace;
+    if replace.files_from.is_none() && replace.inputs.is_empty() {
+        return;
+    }
+    if replace.input_path.as_deref() != Some("-") && replace.search_string.is_some() && replace.replace_string.is_none() {
+        replace.replace_string = replace.search_string.take();
+        replace.search_string = replace.input_path.take();
+    }
+    if replace.input_path.is_none() && !replace.inputs.is_empty() {
+        replace.input_path = Some(replace.inputs.remove(0));
+    }
+}
+
+/// Read the list of --files-from, or of stdin for an input path of -. Their common directory becomes the input
+/// path, for the rtorrent.lock check, the backups and the relative paths under --output-path.
+fn read_listed_files(option: &mut RepToolOption) -> Result<()> {
+    if option.replace.input_path.as_deref() == Some("-") {
+        option.replace.files_from = Some(String::from("-"));
+        option.replace.input_path = None;
+    }
+    let Some(files_from) = option.replace.files_from.clone() else {
+        return Ok(());
+    };
+    if option.replace.input_path.is_some() {
+        bail!("--files-from replaces the input path, give only SEARCH_STRING REPLACE_STRING");
+    }
+
+    let listed_files = files_from::read(&files_from, option.replace.null)?;
+    option.replace.input_path = Some(files_from::common_dir(&listed_files).display().to_string());
+    option.listed_files = Some(listed_files);
+    Ok(())
+}
+
+/// What clap cannot require by itself since the config file may provide it, exits like a usage error
+fn check_required(option: &RepToolOption) {
+    let is_replace = matches!(option.command, None | Some(Command::Replace(_)));
+    if !is_replace || option.replace.live || option.replace.json_schema {
+        return;
+    }
+
+    let missing = if option.replace.input_path.is_none() {
+        Some("<INPUT_PATH> (or session-path in the config file)")
+    } else if option.replace.search_string.is_some() && option.replace.replace_string.is_none() && option.replace.per_tracker_map.is_empty() {
+        Some("<REPLACE_STRING>")
+    } else if option.replace.search_string.is_none() && option.replace.mapping.is_empty() && option.replace.map_file.is_none() && option.replace.apply_manifest.is_none() && !option.replace.normalize && option.replace.map_to.is_none() && option.replace.rules_file.is_none() && option.replace.script_file.is_none() {
+        Some("<SEARCH_STRING> <REPLACE_STRING> (or -e, --map-file, --rules, --script, mappings in the config file)")
+    } else {
+        None
+    };
+    if let Some(missing) = missing {
+        exit::clap_error(RepToolOption::command().error(ErrorKind::MissingRequiredArgument, format!("the following required arguments were not provided: {}", missing)));
+    }
+}
+
+fn main() -> std::process::ExitCode {
+    match run() {
+        Ok(status) => status.into(),
+        Err(err) => {
+            eprintln!("Error: {:?}", err);
+            Status::of_error(&err).into()
+        }
+    }
+}
+
+fn run() -> Result<Status> {
 
     let span = span!(Level::TRACE, "rtorrent_status_file_modifier span");
     let _enter = span.enter();
 
-    let option: RepToolOption = RepToolOption::parse();
+    let matches = RepToolOption::command().try_get_matches().unwrap_or_else(|err| exit::clap_error(err));
+    let mut option = RepToolOption::from_arg_matches(&matches).unwrap_or_else(|err| exit::clap_error(err));
+    if let Some(Command::Replace(args)) = &option.command {
+        option.replace = (**args).clone();
+    }
+    if let Some(Command::Watch(args)) = &option.command {
+        option.replace.mapping = args.mapping.clone();
+        option.replace.map_file = args.map_file.clone();
+    }
+    shift_positionals(&mut option);
+    read_listed_files(&mut option)?;
+    let config = if option.global.no_config { config::Config::default() } else { config::load(option.global.config_file.as_deref())? };
+    config.apply(&mut option, &matches);
+    check_required(&option);
+
+    ui::init(option.global.color, option.global.quiet);
+    logging::init(option.global.quiet, option.global.verbose, option.global.log_file.as_deref(), option.global.log_format)?;
 
-    // Create the tracing subscriber with the specified level filter
-    let mut level_filter = LevelFilter::WARN;
-    if option.verbose_mode {
-        level_filter = LevelFilter::TRACE;
+    option.rules = collect_rules(&option)?;
+    if option.replace.regex {
+        option.patterns = option.rules.iter()
+            .map(|rule| RegexBuilder::new(&rule.find).case_insensitive(option.replace.ignore_case || rule.ignore_case).build()
+                .with_context(|| format!("Invalid regular expression: {:?}", rule.find)))
+            .collect::<Result<_>>()?;
+    }
+    // Counting is a dry run that keeps quiet about every file
+    if option.replace.count_only {
+        option.global.dry_run = true;
     }
 
-    let subscriber = fmt::Subscriber::builder()
-        .with_max_level(level_filter)
-        .finish();
+    // The batch commands stop between files on Ctrl-C, the others, prompts included, keep the default handling
+    let is_batch = matches!(option.command, None | Some(Command::Replace(_) | Command::Set(_) | Command::FastResume(_) | Command::Relabel(_) | Command::Retrack(_) | Command::Convert(_)));
+    if is_batch && !option.replace.interactive && !option.replace.live {
+        interrupt::install()?;
+    }
+    option.rule_set = option.replace.rules_file.as_deref().map(rules::RuleSet::load).transpose()?;
+    if let (Some(search_string), false) = (&option.replace.search_string, option.replace.per_tracker_map.is_empty()) {
+        let unescape = |text: &str| escape::decode(text, option.replace.no_escape);
+        let targets = option.replace.per_tracker_map.iter().map(|mapping| unescape(mapping)).collect::<Result<Vec<_>>>()?;
+        let fallback = option.replace.replace_string.as_deref().map(unescape).transpose()?;
+        option.rule_set = Some(rules::RuleSet::per_tracker(&targets, &unescape(search_string)?, fallback.as_deref())?);
+    }
+    load_script(&mut option)?;
 
-    // Initialize the tracing subscriber with your custom subscriber
-    tracing::subscriber::set_global_default(subscriber).expect("Failed to set the subscriber");
+    // Copying onto the input files themselves is an in-place edit through a confusing path
+    if !option.replace.output_path.is_empty() {
+        let input_dir = option.input_dir().ok().and_then(|input_dir| input_dir.canonicalize().ok());
+        let output_dir = Path::new(&option.replace.output_path).canonicalize().ok();
+        if input_dir.is_some() && input_dir == output_dir {
+            warn!("Output path is the input path, modifying the input files in place");
+            option.replace.output_path.clear();
+        }
+    }
+
+    if option.replace.json_schema {
+        println!("{}", serde_json::to_string_pretty(&report::json_schema())?);
+        return Ok(Status::Success);
+    }
+
+    let extensions = if option.global.extensions.is_empty() {
+        ["rtorrent", "torrent", "libtorrent_resume"].map(String::from).to_vec()
+    } else {
+        option.global.extensions.clone()
+    };
+    let matcher = FileMatcher::new(extensions, option.global.include.clone(), option.global.exclude.clone())
+        .follow_symlinks(option.global.follow_symlinks);
+    match &option.command {
+        None | Some(Command::Replace(_)) => {}
+        Some(Command::Completions { shell }) => {
+            let mut command = RepToolOption::command();
+            let name = command.get_name().to_owned();
+            clap_complete::generate(*shell, &mut command, name, &mut std::io::stdout());
+            return Ok(Status::Success);
+        }
+        Some(Command::Browse { dir }) => return browse(&matcher, Path::new(dir), &option).map(|()| Status::Success),
+        Some(Command::Index { dir, db, query }) => {
+            return update_index(&matcher, Path::new(dir), db.as_deref(), query.as_deref(), &option).map(|()| Status::Success);
+        }
+        Some(Command::Undo { dir }) => {
+            let _lock = lock_session(Path::new(dir), &option)?;
+            let restored = backup::undo_last_run(Path::new(dir), option.global.dry_run, option.global.io_buffer)?;
+            if restored.is_empty() {
+                println!("Nothing to undo");
+            }
+            for file_path in &restored {
+                println!("{} {}", if option.global.dry_run { "Would restore" } else { "Restored" }, file_path.display());
+            }
+            return Ok(Status::Success);
+        }
+        Some(Command::List(args)) => {
+            let option = option.with_input(&args.dir);
+            let listed = match (args.strings, args.by_extension) {
+                (true, _) => inspect::dump_all_strings(&matcher, &option, args.max_entries),
+                (_, true) => inspect::count_by_extension(&matcher, &option),
+                _ => inspect::list_sessions(&matcher, &option),
+            };
+            return listed.map(|()| Status::Success);
+        }
+        Some(Command::Grep(args)) => return inspect::grep(&matcher, &option.with_input(&args.dir), args),
+        Some(Command::Verify(args)) => return inspect::verify(&matcher, &option.with_input(&args.dir), args).map(|()| Status::Success),
+        Some(Command::Check(args)) => return check::check(&matcher, &option.with_input(&args.dir), args).map(|()| Status::Success),
+        Some(Command::Dump(args)) => return dump::dump(&option, args).map(|()| Status::Success),
+        Some(Command::Encode(args)) => {
+            let output = dump::encode(&option, args)?;
+            println!("{} {}", if option.global.dry_run { "Would write" } else { "Wrote" }, output.display());
+            return Ok(Status::Success);
+        }
+        Some(Command::Get(args)) => return keys::get(&matcher, &option.with_input(&args.dir), args).map(|()| Status::Success),
+        Some(Command::Set(args)) => {
+            return run_batch(option.with_input(&args.dir), |option| keys::set(&matcher, option, args),
+                |summary| format!("Set {} in {} of {} file(s)", args.key, summary.files_matched, summary.files_scanned));
+        }
+        Some(Command::FastResume(args)) => {
+            return run_batch(option.with_input(&args.dir), |option| resume::fast_resume_all(&matcher, option, args),
+                |summary| format!("Fast resumed {} of {} torrent(s)", summary.files_matched, summary.files_scanned));
+        }
+        Some(Command::Watch(args)) => {
+            let mut option = option.with_input(&args.dir);
+            let _lock = lock_for_run(&mut option)?;
+            return watch::watch(&matcher, &option, args).map(|()| Status::Success);
+        }
+        Some(Command::Serve(args)) => return serve::serve(&matcher, &option.with_input(&args.dir), args).map(|()| Status::Success),
+        Some(Command::Relabel(args)) => {
+            return run_batch(option.with_input(&args.dir), |option| label::relabel(&matcher, option, args),
+                |summary| format!("Relabeled {} of {} torrent(s)", summary.files_matched, summary.files_scanned));
+        }
+        Some(Command::Retrack(args)) => {
+            return run_batch(option.with_input(&args.dir), |option| retrack::retrack(&matcher, option, args),
+                |summary| format!("Retracked {} of {} torrent(s)", summary.files_matched, summary.files_scanned));
+        }
+        Some(Command::Convert(args)) => {
+            let option = option.with_input(&args.dir);
+            // The other client's session is read and the rtorrent session written to a new directory
+            if let (Some(client), Some(output_dir)) = (args.from, &args.output) {
+                let summary = clients::import(client, &option, Path::new(&args.dir), output_dir, &args.mappings)?;
+                write_report(&summary, &option)?;
+                if option.global.json {
+                    println!("{}", serde_json::to_string_pretty(&summary)?);
+                } else {
+                    println!("Converted {} of {} {} torrent(s) to rtorrent in {}", summary.files_matched, summary.files_scanned, client.display_name(), output_dir.display());
+                }
+                if summary.files_failed > 0 {
+                    return Err(exit::partial_failure(format!("{} torrent(s) could not be converted, the other torrents were", summary.files_failed)));
+                }
+                return Ok(Status::Success);
+            }
+
+            // Another client's session is written next to the rtorrent one, which is left untouched
+            if let (Some(client), Some(output_dir)) = (args.client, &args.output) {
+                let summary = clients::export(client, &matcher, &option, output_dir)?;
+                write_report(&summary, &option)?;
+                if option.global.json {
+                    println!("{}", serde_json::to_string_pretty(&summary)?);
+                } else {
+                    println!("Converted {} of {} torrent(s) to {} in {}", summary.files_matched, summary.files_scanned, client.display_name(), output_dir.display());
+                }
+                if summary.files_failed > 0 {
+                    return Err(exit::partial_failure(format!("{} torrent(s) could not be converted, the other torrents were", summary.files_failed)));
+                }
+                return Ok(Status::Success);
+            }
+
+            let to = args.to.context("Missing --to or --client")?;
+            return run_batch(option, |option| convert::convert(&matcher, option, to),
+                |summary| format!("Converted {} of {} file(s) to {:?}", summary.files_matched, summary.files_scanned, to));
+        }
+        Some(Command::Migrate(args)) => {
+            // Always in place, keeping the originals
+            let mut option = option.with_input(&args.dir);
+            option.replace.archive_originals_to = Some(args.backup_dir());
+            let _lock = lock_session(option.input_dir()?, &option)?;
+            if !option.replace.no_backup {
+                option.backup_run = Some(backup::new_run(option.input_dir()?)?);
+            }
+            return migrate::migrate(&matcher, &option, args).map(|()| Status::Success);
+        }
+    }
+
+    if option.replace.live {
+        let started = Instant::now();
+        let summary = live::replace_live(&option)?.timed(started.elapsed());
+        write_report(&summary, &option)?;
+        if option.global.json {
+            println!("{}", serde_json::to_string_pretty(&summary)?);
+        } else if summary.files_matched == 0 && summary.files_already_migrated == 0 && !option.replace.allow_empty {
+            warn!("No matching found.");
+        }
+        if !option.global.json && !option.global.quiet {
+            println!("{}", statistics(&summary));
+        }
+        return Ok(match_status(&summary, &option));
+    }
+
+    let input = option.replace.input_path.clone().unwrap_or_default();
+    if let Some(pre_cmd) = &option.replace.pre_cmd {
+        hooks::run(pre_cmd, &[("input", input.clone())], option.global.dry_run).context("The --pre-cmd failed, nothing was done")?;
+    }
+    let service = match &option.replace.manage_service {
+        Some(name) => {
+            let session_dirs: Vec<PathBuf> = option.replace.input_path.iter().chain(&option.replace.inputs).map(PathBuf::from).collect();
+            Some(ManagedService::stop(name, &session_dirs, Duration::from_secs(option.replace.service_timeout), option.global.dry_run)?)
+        }
+        None => None,
+    };
+    let mut status = replace_runs(&matcher, &option);
+    if let Some(service) = &service {
+        match (service.start(), &status) {
+            (Err(err), Ok(_)) => status = Err(err),
+            (Err(err), Err(_)) => warn!("{:#}", err),
+            (Ok(()), _) => {}
+        }
+    }
+    // A partial failure was notified with its report
+    if let (Err(err), Some(notify_url)) = (&status, &option.global.notify_url) {
+        let code = Status::of_error(err);
+        if code != Status::PartialFailure {
+            notify::send_failure(notify_url, err, code as i32);
+        }
+    }
+    let Some(post_cmd) = &option.replace.post_cmd else {
+        return status;
+    };
+    let code = status.as_ref().map_or_else(Status::of_error, |status| *status) as i32;
+    match (hooks::run(post_cmd, &[("input", input), ("status", code.to_string())], option.global.dry_run), status) {
+        (Err(err), Ok(_)) => Err(err.context("The --post-cmd failed")),
+        (Err(err), Err(status_err)) => {
+            warn!("The --post-cmd failed: {:#}", err);
+            Err(status_err)
+        }
+        (Ok(()), status) => status,
+    }
+}
+
+/// Replace in every input directory, once the hooks around the run are set up
+fn replace_runs(matcher: &FileMatcher, option: &RepToolOption) -> Result<Status> {
+    let mut option = option.clone();
+    if option.replace.transactional {
+        option.transaction = Some(Arc::default());
+    }
+    let option = &option;
+
+    // Only in place edits need a way back, an output path leaves the input untouched.
+    // Every directory is checked before the first one is touched.
+    let runs = option.input_runs()?;
+    let is_in_place = option.replace.output_path.is_empty() && !option.global.dry_run;
+    // Held until every directory is done
+    let mut locks = Vec::new();
+    if is_in_place {
+        for run in &runs {
+            locks.push(lock_session(run.input_dir()?, option)?);
+        }
+    }
+    // Dropped before the locks, the files that timed out while being written are done when they are released
+    let _late_writers = timeout::LateWriters;
+
+    let started = Instant::now();
+    let mut total = RunSummary::default();
+    for mut run in runs {
+        if is_in_place && !option.replace.no_backup {
+            run.backup_run = Some(backup::new_run(run.input_dir()?)?);
+        }
+        info!("Start replacing files in {} ...", run.input_dir()?.display());
+        let summary = match &run.replace.apply_manifest {
+            Some(manifest_path) => manifest::apply_manifest(matcher, &run, Path::new(manifest_path)),
+            None => replace_files(matcher, &run),
+        }.with_context(|| format!("Failed to modify files in {:?}", run.replace.input_path.as_deref().unwrap_or_default()))?;
+        let is_stopped = (option.global.fail_fast && summary.files_failed > 0) || interrupt::is_interrupted();
+        total.merge(summary);
+        if is_stopped {
+            break;
+        }
+    }
+    let summary = total.timed(started.elapsed());
+    // A staged file may still be coming, the transaction is only settled once it is
+    timeout::wait_for_late_writers();
+    if let Some(transaction) = &option.transaction {
+        if summary.files_failed > 0 || summary.files_timed_out > 0 || interrupt::is_interrupted() {
+            let rolled_back = transaction.rollback();
+            // With --batch-size the failed batch was rolled back already
+            if option.replace.batch_size.is_none() || rolled_back > 0 {
+                warn!("Rolled back the transaction, none of the {} staged file(s) was written", rolled_back);
+            }
+        } else {
+            transaction.commit()?;
+        }
+    }
+    print_summary(&summary, option);
+    write_report(&summary, option)?;
+    if option.global.json {
+        println!("{}", serde_json::to_string_pretty(&summary)?);
+    }
+    interrupted_status(&summary)?;
+    if summary.files_timed_out > 0 {
+        return Err(exit::partial_failure(format!("{} file(s) timed out", summary.files_timed_out)));
+    }
+    if summary.files_failed > 0 {
+        return Err(failures(&summary, option));
+    }
+
+    info!("File modification completed successfully");
+    Ok(match_status(&summary, option))
+}
+
+/// Success unless some files failed, for the commands where matching nothing is not a failure
+fn failure_status(summary: &RunSummary, option: &RepToolOption) -> Result<Status> {
+    interrupted_status(summary)?;
+    if summary.files_failed > 0 {
+        return Err(failures(summary, option));
+    }
+    Ok(Status::Success)
+}
+
+/// Fails when a signal stopped the run, whatever the files it processed
+fn interrupted_status(summary: &RunSummary) -> Result<()> {
+    if interrupt::is_interrupted() {
+        return Err(exit::interrupted(format!("Interrupted after {} file(s), the other files were not processed", summary.files_scanned)));
+    }
+    Ok(())
+}
+
+/// List the files that failed on stderr, the error returned tells how many and whether the run went on
+fn failures(summary: &RunSummary, option: &RepToolOption) -> anyhow::Error {
+    eprintln!("{} file(s) failed:", summary.files_failed);
+    for file_report in &summary.files {
+        if let Some(error) = &file_report.error {
+            ui::print_failed(&file_report.file, error);
+        }
+    }
+    if option.replace.transactional && option.replace.batch_size.is_some() {
+        exit::partial_failure(format!("{} file(s) could not be processed, their batch was rolled back, the batches before it were written", summary.files_failed))
+    } else if option.replace.transactional {
+        exit::partial_failure(format!("{} file(s) could not be processed, the transaction was rolled back and nothing was written", summary.files_failed))
+    } else if option.global.fail_fast {
+        exit::partial_failure("Stopped at the first failure, the files after it were not processed".to_owned())
+    } else {
+        exit::partial_failure(format!("{} file(s) could not be processed, the other files were", summary.files_failed))
+    }
+}
 
-    let extensions = ["rtorrent", "torrent", "libtorrent_resume"];
-    if option.verbose_mode {
-        info!("Start replacing files ...");
+/// No match is a status of its own so scripts can tell it from a run that changed something
+fn match_status(summary: &RunSummary, option: &RepToolOption) -> Status {
+    if summary.files_matched == 0 && summary.files_already_migrated == 0 && !option.replace.allow_empty {
+        Status::NoMatch
+    } else {
+        Status::Success
     }
-    replace_files(&extensions, &option)
-        .context("Failed to modify files")
-        .map(|_| info!("File modification completed successfully"))
 }
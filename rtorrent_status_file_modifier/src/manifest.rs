@@ -0,0 +1,150 @@
+use std::collections::btree_map::Entry;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use reptool_core::report::{FileReport, ModifyReport, RunSummary};
+use reptool_core::SessionFile;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::archive::archive_original;
+use crate::backup::backup_before_write;
+use crate::logging::log_changes;
+use crate::glob::{glob_match, is_glob, FileMatcher};
+use crate::{list_files, print_change, process_each, RepToolOption};
+
+/// One entry of a migration plan, e.g. `{"file": "<HASH>.torrent.rtorrent", "directory": "/mnt/new/path"}`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// File name relative to the input path, or a glob over the scanned files
+    pub file: String,
+    /// Exact value to set
+    #[serde(alias = "directory")]
+    pub value: String,
+    /// Field to set, defaults to --keyword
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+}
+
+/// A decoded file with the fields set so far, entries targeting the same file accumulate
+struct PlannedEdit {
+    session_file: SessionFile,
+    modifications: Vec<ModifyReport>,
+}
+
+/// Set the fields listed in a JSON manifest to their exact values, no search involved.
+/// Every entry is validated before anything is written, with --strict a single mismatch aborts the run.
+pub fn apply_manifest(matcher: &FileMatcher, option: &RepToolOption, manifest_path: &Path) -> Result<RunSummary> {
+    apply_entries(matcher, option, &read_manifest(manifest_path)?)
+}
+
+pub fn read_manifest(manifest_path: &Path) -> Result<Vec<ManifestEntry>> {
+    let content = fs::read_to_string(manifest_path).with_context(|| format!("Failed to read manifest: {:?}", manifest_path))?;
+    serde_json::from_str(&content).with_context(|| format!("Failed to parse manifest: {:?}", manifest_path))
+}
+
+/// Same as `apply_manifest` with the entries already loaded
+pub fn apply_entries(matcher: &FileMatcher, option: &RepToolOption, entries: &[ManifestEntry]) -> Result<RunSummary> {
+    let input_dir = option.input_dir()?;
+    let scanned_files = list_files(matcher, input_dir, option.max_depth())?;
+
+    let mut planned_edits: BTreeMap<PathBuf, PlannedEdit> = BTreeMap::new();
+    let mut mismatches = Vec::new();
+    for entry in entries {
+        let key = entry.key.as_deref().unwrap_or(option.keyword());
+
+        let file_paths: Vec<PathBuf> = if is_glob(&entry.file) {
+            scanned_files.iter()
+                .filter(|file_path| file_path.file_name().is_some_and(|name| glob_match(&entry.file, &name.to_string_lossy())))
+                .cloned()
+                .collect()
+        } else {
+            vec![input_dir.join(&entry.file)].into_iter().filter(|file_path| file_path.is_file()).collect()
+        };
+        if file_paths.is_empty() {
+            mismatches.push(format!("{}: no such file", entry.file));
+            continue;
+        }
+
+        for file_path in file_paths {
+            let planned_edit = match planned_edits.entry(file_path.clone()) {
+                Entry::Occupied(planned_edit) => planned_edit.into_mut(),
+                Entry::Vacant(vacant) => match SessionFile::open(&file_path, option.global.io_buffer) {
+                    Ok(session_file) => vacant.insert(PlannedEdit { session_file, modifications: Vec::new() }),
+                    Err(err) => {
+                        mismatches.push(format!("{}: {:#}", file_path.display(), err));
+                        continue;
+                    }
+                },
+            };
+            match planned_edit.session_file.set(key, &entry.value, option.global.encoding) {
+                Ok(Some(modification)) => planned_edit.modifications.push(modification),
+                Ok(None) => {}
+                Err(err) => mismatches.push(format!("{}: {:#}", file_path.display(), err)),
+            }
+        }
+    }
+
+    for mismatch in &mismatches {
+        warn!("Manifest mismatch: {}", mismatch);
+    }
+    if option.replace.strict && !mismatches.is_empty() {
+        bail!("{} manifest entries do not match the session files, nothing was written", mismatches.len());
+    }
+
+    if option.global.dry_run {
+        let file_reports = planned_edits.into_iter().map(|(file_path, planned_edit)| {
+            let file = file_path.display().to_string();
+            if !option.global.json {
+                for modification in &planned_edit.modifications {
+                    print_change(&file, modification);
+                }
+            }
+            FileReport { matched: !planned_edit.modifications.is_empty(), file, modifications: planned_edit.modifications, ..Default::default() }
+        }).collect();
+        return Ok(RunSummary::new(file_reports));
+    }
+
+    let output_dir = Path::new(&option.replace.output_path);
+    if !option.replace.output_path.is_empty() && !output_dir.exists() {
+        fs::create_dir_all(output_dir).with_context(|| format!("Failed to create output directory: {:?}", &option.replace.output_path))?;
+    }
+
+    let file_paths: Vec<PathBuf> = planned_edits.keys().cloned().collect();
+    let file_reports = process_each(file_paths, option, |file_path| {
+        let planned_edit = planned_edits.remove(file_path).expect("Planned edit of every file");
+        if planned_edit.modifications.is_empty() {
+            return Ok(Some(FileReport { file: file_path.display().to_string(), ..Default::default() }));
+        }
+
+        // Write next to the input by default, into the output path when given
+        let target_path = match file_path.strip_prefix(input_dir) {
+            Ok(relative_path) if !option.replace.output_path.is_empty() => output_dir.join(relative_path),
+            _ => file_path.to_owned(),
+        };
+        if let Some(parent) = target_path.parent().filter(|parent| !parent.exists()) {
+            fs::create_dir_all(parent).with_context(|| format!("Failed to create output directory: {:?}", parent))?;
+        }
+        let mut archived_to = None;
+        if let Some(archive_dir) = &option.replace.archive_originals_to {
+            archived_to = archive_original(file_path, input_dir, Path::new(archive_dir))?;
+        }
+        if target_path == file_path {
+            backup_before_write(option, file_path)?;
+        }
+        planned_edit.session_file.save_as(&target_path, option.global.io_buffer)?;
+        log_changes(&target_path.display().to_string(), &planned_edit.modifications);
+
+        Ok(Some(FileReport {
+            file: target_path.display().to_string(),
+            matched: true,
+            archived_to: archived_to.map(|archive_path| archive_path.display().to_string()),
+            modifications: planned_edit.modifications,
+            ..Default::default()
+        }))
+    });
+
+    Ok(RunSummary::new(file_reports))
+}
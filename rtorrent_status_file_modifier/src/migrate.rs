@@ -0,0 +1,229 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use clap::Args;
+use reptool_core::bencode::Value;
+use reptool_core::report::RunSummary;
+use reptool_core::SessionFile;
+use serde_json::json;
+
+use crate::glob::FileMatcher;
+use crate::manifest::{apply_entries, read_manifest, ManifestEntry};
+use crate::session::{load_sessions, Session};
+use crate::{list_files, write_report, RepToolOption};
+
+#[derive(Args, Clone)]
+pub struct MigrateArgs {
+    /// Session directory
+    pub dir : String,
+
+    /// Root to move away from, defaults to the root shared by every torrent
+    #[arg(long, value_name = "DIR", requires = "to")]
+    pub from : Option<String>,
+
+    /// Root to move the torrents to
+    #[arg(long, value_name = "DIR", conflicts_with = "plan_in")]
+    pub to : Option<String>,
+
+    /// Use this manifest as the plan instead of computing one
+    #[arg(long, value_name = "FILE")]
+    pub plan_in : Option<String>,
+
+    /// Save the plan as a manifest reusable with --plan-in or --apply-manifest
+    #[arg(long, value_name = "FILE")]
+    pub plan_out : Option<String>,
+
+    /// Where the originals are kept, defaults to DIR.reptool-backup next to the session directory
+    #[arg(long, value_name = "DIR")]
+    pub backup_dir : Option<String>,
+
+    /// Apply the plan without asking for confirmation
+    #[arg(long, short = 'y')]
+    pub yes : bool,
+}
+
+impl MigrateArgs {
+    pub fn backup_dir(&self) -> String {
+        self.backup_dir.clone().unwrap_or_else(|| format!("{}.reptool-backup", self.dir.trim_end_matches('/')))
+    }
+}
+
+/// Guided migration: survey the roots, plan, confirm, apply with backups, then verify what was written
+pub fn migrate(matcher: &FileMatcher, option: &RepToolOption, args: &MigrateArgs) -> Result<()> {
+    let input_dir = option.input_dir()?;
+    let sessions = load_sessions(&list_files(matcher, input_dir, option.max_depth())?, option.keyword(), option.global.encoding);
+
+    let roots = survey(&sessions);
+    if !option.global.json {
+        println!("Survey of {} torrent(s) by parent of {}:", sessions.len(), option.keyword());
+        for (root, count) in &roots {
+            println!("{:>8} {}", count, root);
+        }
+    }
+
+    let entries = match (&args.plan_in, &args.to) {
+        (Some(plan_in), _) => read_manifest(Path::new(plan_in))?,
+        (None, Some(to)) => {
+            let from = match &args.from {
+                Some(from) => from.trim_end_matches('/').to_owned(),
+                None => common_root(&sessions).context("The torrents share no common root, pass --from")?,
+            };
+            propose(&sessions, input_dir, &from, to)
+        }
+        (None, None) => {
+            println!("Pass --to (and optionally --from), or --plan-in, to plan a migration");
+            return Ok(());
+        }
+    };
+
+    let current: BTreeMap<String, &str> = sessions.iter()
+        .map(|session| (relative_name(&session.rtorrent_path, input_dir), session.directory.as_str()))
+        .collect();
+    if !option.global.json {
+        println!("Plan ({} change(s)):", entries.len());
+        for entry in &entries {
+            let old_value = current.get(&entry.file).copied().unwrap_or("?");
+            println!("  {}: {} -> {}", entry.file, old_value, entry.value);
+        }
+    }
+    if let Some(plan_out) = &args.plan_out {
+        fs::write(plan_out, serde_json::to_string_pretty(&entries)?).with_context(|| format!("Failed to write plan: {:?}", plan_out))?;
+    }
+    if entries.is_empty() {
+        println!("Nothing to migrate");
+        return Ok(());
+    }
+    if option.global.dry_run {
+        println!("Dry run, nothing was written");
+        return Ok(());
+    }
+
+    let backup_dir = args.backup_dir();
+    if !args.yes && !confirm(&format!("Apply {} change(s) with backups in {}?", entries.len(), backup_dir))? {
+        println!("Aborted, nothing was written");
+        return Ok(());
+    }
+
+    let summary = apply_entries(matcher, option, &entries)?;
+    write_report(&summary, option)?;
+    let (failures, missing_directories) = verify(&summary, option);
+
+    if option.global.json {
+        println!("{}", serde_json::to_string_pretty(&json!({
+            "roots": roots,
+            "plan": entries,
+            "backup_dir": backup_dir,
+            "summary": summary,
+            "verify_failures": failures,
+            "missing_directories": missing_directories,
+        }))?);
+    } else {
+        println!("Migrated {} file(s), originals kept in {}", summary.files_matched, backup_dir);
+        for failure in &failures {
+            println!("  verify failed: {}", failure);
+        }
+        for directory in &missing_directories {
+            println!("  data directory does not exist yet: {}", directory);
+        }
+    }
+
+    if !failures.is_empty() {
+        bail!("{} file(s) failed verification, restore them from {}", failures.len(), backup_dir);
+    }
+
+    Ok(())
+}
+
+/// Torrent count per parent directory of the keyword value
+fn survey(sessions: &[Session]) -> BTreeMap<String, usize> {
+    let mut roots = BTreeMap::new();
+    for session in sessions {
+        let root = Path::new(session.directory.trim_end_matches('/')).parent()
+            .map(|parent| parent.display().to_string())
+            .unwrap_or_default();
+        *roots.entry(root).or_default() += 1;
+    }
+
+    roots
+}
+
+/// Longest path shared by every non empty value, whole components only
+fn common_root(sessions: &[Session]) -> Option<String> {
+    let mut directories = sessions.iter().map(|session| &session.directory).filter(|directory| !directory.is_empty());
+    let mut root = PathBuf::from(directories.next()?);
+    for directory in directories {
+        while !Path::new(directory).starts_with(&root) {
+            if !root.pop() {
+                return None;
+            }
+        }
+    }
+
+    let root = root.display().to_string().trim_end_matches('/').to_owned();
+    (!root.is_empty()).then_some(root)
+}
+
+/// Move every value under `from` to the same place under `to`
+fn propose(sessions: &[Session], input_dir: &Path, from: &str, to: &str) -> Vec<ManifestEntry> {
+    let to = to.trim_end_matches('/');
+    sessions.iter().filter_map(|session| {
+        let rest = session.directory.strip_prefix(from).filter(|rest| rest.is_empty() || rest.starts_with('/'))?;
+        let value = format!("{}{}", to, rest);
+        if value == session.directory {
+            return None;
+        }
+        Some(ManifestEntry {
+            file: relative_name(&session.rtorrent_path, input_dir),
+            value,
+            key: None,
+        })
+    }).collect()
+}
+
+/// How manifests name a file, relative to the input path so subdirectories of a recursive scan are kept
+fn relative_name(file_path: &Path, input_dir: &Path) -> String {
+    file_path.strip_prefix(input_dir).unwrap_or(file_path).display().to_string()
+}
+
+fn confirm(question: &str) -> Result<bool> {
+    eprint!("{} [y/N] ", question);
+    io::stderr().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+/// Re-read every written file and check it holds the new values, also lists new directories missing on disk. A
+/// file that cannot be read back is one more failure, the others are still verified.
+fn verify(summary: &RunSummary, option: &RepToolOption) -> (Vec<String>, Vec<String>) {
+    let mut failures = Vec::new();
+    let mut missing_directories = Vec::new();
+    for file_report in &summary.files {
+        if file_report.modifications.is_empty() {
+            continue;
+        }
+
+        let session_file = match SessionFile::open(&file_report.file, option.global.io_buffer) {
+            Ok(session_file) => session_file,
+            Err(err) => {
+                failures.push(format!("{}: cannot be read back: {:#}", file_report.file, err));
+                continue;
+            }
+        };
+        for modification in &file_report.modifications {
+            let expected = option.global.encoding.encode(&modification.new_value).ok();
+            match session_file.value().as_dict().and_then(|dict| dict.get(modification.key.as_bytes())) {
+                Some(Value::Bytes(value)) if Some(value) == expected.as_ref() => {}
+                _ => failures.push(format!("{}: {} is not {:?}", file_report.file, modification.key, modification.new_value)),
+            }
+            if !Path::new(&modification.new_value).exists() {
+                missing_directories.push(modification.new_value.clone());
+            }
+        }
+    }
+
+    (failures, missing_directories)
+}
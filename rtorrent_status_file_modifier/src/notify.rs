@@ -0,0 +1,46 @@
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use reptool_core::report::RunSummary;
+use serde_json::json;
+use tracing::{info, warn};
+use ureq::Agent;
+
+const NOTIFY_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// POST the report of the run to `url`, after one message per failed file with `errors`. A notification that
+/// cannot be delivered is only warned about, the run itself went through.
+pub fn send_summary(url: &str, summary: &RunSummary, errors: bool) {
+    if errors {
+        for file_report in &summary.files {
+            if let Some(error) = &file_report.error {
+                send(url, &json!({ "event": "file_failed", "file": file_report.file, "error": error }));
+            }
+        }
+    }
+    match serde_json::to_value(summary) {
+        Ok(report) => send(url, &report),
+        Err(err) => warn!("Failed to serialize the report for {}: {:#}", url, err),
+    }
+}
+
+/// POST a run that failed before it had a report
+pub fn send_failure(url: &str, error: &anyhow::Error, status: i32) {
+    send(url, &json!({ "event": "run_failed", "error": format!("{:#}", error), "status": status }));
+}
+
+fn send(url: &str, body: &serde_json::Value) {
+    match post(url, body) {
+        Ok(()) => info!("Notified {}", url),
+        Err(err) => warn!("Failed to notify {}: {:#}", url, err),
+    }
+}
+
+fn post(url: &str, body: &serde_json::Value) -> Result<()> {
+    let agent: Agent = Agent::config_builder().timeout_global(Some(NOTIFY_TIMEOUT)).build().into();
+    agent.post(url)
+        .header("Content-Type", "application/json")
+        .send(serde_json::to_vec(body)?)
+        .with_context(|| format!("POST to {} failed", url))?;
+    Ok(())
+}
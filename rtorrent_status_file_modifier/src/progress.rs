@@ -0,0 +1,19 @@
+use std::io::{self, IsTerminal};
+
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+
+use crate::RepToolOption;
+
+/// Files scanned and modified so far with an ETA, drawn on stderr.
+/// Hidden with --quiet, when stdout is not a terminal, when stdout gets reports or dry run lines, and with --interactive.
+pub fn file_progress(file_count: usize, option: &RepToolOption) -> ProgressBar {
+    if option.global.quiet || option.global.json || option.global.dry_run || option.replace.interactive || !io::stdout().is_terminal() {
+        return ProgressBar::hidden();
+    }
+
+    let progress = ProgressBar::with_draw_target(Some(file_count as u64), ProgressDrawTarget::stderr());
+    progress.set_style(ProgressStyle::with_template("{wide_bar} {pos}/{len} scanned, {msg} modified, ETA {eta}")
+        .expect("Invalid progress template"));
+    progress.set_message("0");
+    progress
+}
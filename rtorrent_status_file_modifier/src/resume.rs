@@ -0,0 +1,414 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Context, Result};
+use clap::Args;
+use reptool_core::bencode::{self, Value};
+use reptool_core::file::{read_buffered, write_atomic, AtomicWriter};
+use reptool_core::stream::{self, Token};
+use reptool_core::report::{FileReport, RunSummary};
+use reptool_core::{ModifyReport, SessionFile};
+use tracing::{debug, warn};
+
+use crate::backup::backup_before_write;
+use crate::glob::FileMatcher;
+use crate::session::torrent_path_of;
+use crate::keys::session_files;
+use crate::{interrupt, RepToolOption};
+
+// Top level keys holding the verified piece state
+const PIECE_STATE_KEYS: [&[u8]; 3] = [b"bitfield", b"uncertain_pieces", b"uncertain_pieces.timestamp"];
+
+/// Clear the piece state of a .libtorrent_resume file so rtorrent rehashes the data on next load.
+/// The per file `mtime` is zeroed and `completed` reset, other fields are kept untouched.
+/// The file is streamed, the resume data of a torrent with many files can be tens of megabytes.
+pub fn reset_resume_file(file_path: &str, option: &RepToolOption) -> Result<bool> {
+    debug!("Resetting resume data: {}", file_path);
+
+    let file = File::open(file_path).with_context(|| format!("Failed to open file: {:?}", file_path))?;
+    let mut reader = stream::Reader::new(BufReader::with_capacity(option.global.io_buffer, file));
+    let mut writer = AtomicWriter::create(Path::new(file_path), option.global.io_buffer)?;
+    let is_changed = reset_piece_state(&mut reader, &mut writer).with_context(|| format!("Failed to decode file: {:?}", file_path))?;
+
+    // Dropping the writer leaves an unchanged file alone
+    if is_changed {
+        backup_before_write(option, Path::new(file_path))?;
+        writer.commit()?;
+    }
+
+    Ok(is_changed)
+}
+
+/// Copy the resume data without its piece state, whether there was any
+fn reset_piece_state(reader: &mut stream::Reader<impl BufRead>, writer: &mut impl Write) -> Result<bool> {
+    if reader.expect_token()? != Token::Dict {
+        bail!("Resume data is not a dictionary");
+    }
+    Token::Dict.write(writer)?;
+
+    let mut is_changed = false;
+    loop {
+        let length = match reader.expect_token()? {
+            Token::Bytes(length) => length,
+            Token::End => break,
+            _ => bail!("Dictionary key is not a string"),
+        };
+        let key = reader.read_bytes(length)?;
+        let value = reader.expect_token()?;
+        if PIECE_STATE_KEYS.contains(&key.as_slice()) {
+            reader.skip_value(value)?;
+            is_changed = true;
+            continue;
+        }
+
+        Token::Bytes(length).write(writer)?;
+        writer.write_all(&key)?;
+        if key == b"files" && value == Token::List {
+            is_changed |= reset_files(reader, writer)?;
+        } else {
+            reader.copy_value(value, writer)?;
+        }
+    }
+    Token::End.write(writer)?;
+    reader.finish()?;
+
+    Ok(is_changed)
+}
+
+/// Copy the `files` list with a zero `mtime` and `completed` in each entry, whether one was not zero
+fn reset_files(reader: &mut stream::Reader<impl BufRead>, writer: &mut impl Write) -> Result<bool> {
+    Token::List.write(writer)?;
+    let mut is_changed = false;
+    loop {
+        match reader.expect_token()? {
+            Token::End => break,
+            Token::Dict => {
+                Token::Dict.write(writer)?;
+                loop {
+                    let length = match reader.expect_token()? {
+                        Token::Bytes(length) => length,
+                        Token::End => break,
+                        _ => bail!("Dictionary key is not a string"),
+                    };
+                    let key = reader.read_bytes(length)?;
+                    let value = reader.expect_token()?;
+                    Token::Bytes(length).write(writer)?;
+                    writer.write_all(&key)?;
+                    if key == b"mtime" || key == b"completed" {
+                        reader.skip_value(value)?;
+                        is_changed |= value != Token::Integer(0);
+                        Token::Integer(0).write(writer)?;
+                    } else {
+                        reader.copy_value(value, writer)?;
+                    }
+                }
+                Token::End.write(writer)?;
+            }
+            other => reader.copy_value(other, writer)?,
+        }
+    }
+    Token::End.write(writer)?;
+
+    Ok(is_changed)
+}
+
+#[derive(Args, Clone)]
+pub struct FastResumeArgs {
+    /// Session directory
+    pub dir : String,
+
+    /// Look for the payload named after the torrent in DIR instead of the directory of the session file
+    #[arg(long, value_name = "DIR")]
+    pub data_dir : Option<PathBuf>,
+
+    /// Only the torrent with this info-hash, repeatable
+    #[arg(long, value_name = "HASH")]
+    pub hash : Vec<String>,
+}
+
+/// Mark every piece of the torrents of a session directory as done in their .libtorrent_resume, from the data on disk.
+/// A torrent with a missing file is reported and left alone, one with a file of the wrong size is an error.
+pub fn fast_resume_all(matcher: &FileMatcher, option: &RepToolOption, args: &FastResumeArgs) -> Result<RunSummary> {
+    let mut file_reports = Vec::new();
+    for session_path in session_files(matcher, option, &args.hash)? {
+        let resume_path = PathBuf::from(format!("{}.libtorrent_resume", torrent_path_of(&session_path).display()));
+        let mut report = FileReport { file: resume_path.display().to_string(), ..Default::default() };
+        match fast_resume_file(&session_path, &resume_path, args.data_dir.as_deref(), option) {
+            Ok(Ok(modification)) => {
+                report.matched = true;
+                report.modifications.push(modification);
+            }
+            Ok(Err(missing)) => {
+                warn!("Skipped {}: {} file(s) of the data missing, e.g. {}", session_path.display(), missing.len(), missing[0].display());
+                report.missing_data = missing.iter().map(|path| path.display().to_string()).collect();
+            }
+            Err(err) => {
+                warn!("Skipped {}: {:#}", session_path.display(), err);
+                report.error = Some(format!("{:#}", err));
+            }
+        }
+        let is_failed = report.error.is_some();
+        file_reports.push(report);
+        if (is_failed && option.global.fail_fast) || interrupt::is_interrupted() {
+            break;
+        }
+    }
+
+    Ok(RunSummary::new(file_reports))
+}
+
+/// Write the fast resume of one torrent, or the files of its data that are missing
+fn fast_resume_file(session_path: &Path, resume_path: &Path, data_dir: Option<&Path>, option: &RepToolOption) -> Result<Result<ModifyReport, Vec<PathBuf>>> {
+    let (info, data_path) = torrent_data(session_path, data_dir, option)?;
+    let files = payload_files(&info, &data_path)?;
+    let missing: Vec<PathBuf> = files.iter().filter(|(path, _)| !path.exists()).map(|(path, _)| path.clone()).collect();
+    if !missing.is_empty() {
+        return Ok(Err(missing));
+    }
+
+    let old_resume = if resume_path.exists() {
+        Some(SessionFile::open(resume_path, option.global.io_buffer).with_context(|| format!("Failed to open file: {:?}", resume_path))?)
+    } else {
+        None
+    };
+    let resume = fast_resume(&info, &files, old_resume.as_ref().map(SessionFile::value))?;
+
+    let chunks = match resume.as_dict().and_then(|resume| resume.get(b"bitfield".as_slice())) {
+        Some(Value::Integer(chunks)) => *chunks,
+        _ => 0,
+    };
+    let modification = ModifyReport {
+        key: String::from("bitfield"),
+        old_length: 0,
+        new_length: 0,
+        old_value: match old_resume.as_ref().and_then(|resume| resume.get("bitfield")) {
+            Some(Value::Integer(done)) => format!("{} of {} chunks", done, chunks),
+            Some(Value::Bytes(bitfield)) => format!("{} of {} chunks", bitfield.iter().map(|byte| byte.count_ones()).sum::<u32>(), chunks),
+            _ => String::from("none"),
+        },
+        new_value: format!("{} of {} chunks", chunks, chunks),
+    };
+    if option.global.dry_run {
+        if !option.global.json {
+            println!("{}: would mark {} chunk(s) done from {}", resume_path.display(), chunks, data_path.display());
+        }
+        return Ok(Ok(modification));
+    }
+
+    if old_resume.is_some() {
+        backup_before_write(option, resume_path)?;
+    }
+    write_atomic(resume_path, &bencode::encode(&resume), option.global.io_buffer)?;
+    debug!("Wrote fast resume: {}", resume_path.display());
+
+    Ok(Ok(modification))
+}
+
+/// The info dictionary of the .torrent next to `session_path` and the path of its payload, inside `data_dir` when
+/// given and else where the session file says
+pub fn torrent_data(session_path: &Path, data_dir: Option<&Path>, option: &RepToolOption) -> Result<(BTreeMap<Vec<u8>, Value>, PathBuf)> {
+    let torrent_path = torrent_path_of(session_path);
+    let torrent = bencode::decode(&read_buffered(&torrent_path, option.global.io_buffer).with_context(|| format!("Failed to read file: {:?}", torrent_path))?)
+        .with_context(|| format!("Failed to decode file: {:?}", torrent_path))?;
+    let Some(Value::Dict(info)) = torrent.as_dict().and_then(|torrent| torrent.get(b"info".as_slice())) else {
+        bail!("Missing info dictionary: {:?}", torrent_path);
+    };
+
+    let data_path = match data_dir {
+        Some(data_dir) => data_dir.join(info_name(info)?),
+        None => {
+            let session_file = SessionFile::open(session_path, option.global.io_buffer).with_context(|| format!("Failed to open file: {:?}", session_path))?;
+            let Some(Value::Bytes(directory)) = session_file.get("directory") else {
+                bail!("No directory in {:?}", session_path);
+            };
+            data_path_of(info, Path::new(&option.global.encoding.decode(directory)?))?
+        }
+    };
+
+    Ok((info.clone(), data_path))
+}
+
+/// The payload of a torrent stored in `directory`, rtorrent keeps the directory of a multi file torrent either
+/// with or without its name
+pub fn data_path_of(info: &BTreeMap<Vec<u8>, Value>, directory: &Path) -> Result<PathBuf> {
+    let name = info_name(info)?;
+    let is_named = directory.file_name().is_some_and(|file_name| *file_name == *name);
+    if info.contains_key(b"files".as_slice()) && is_named && !directory.join(&name).exists() {
+        Ok(directory.to_path_buf())
+    } else {
+        Ok(directory.join(name))
+    }
+}
+
+/// (path, length) of every file of the torrent whose payload is at `data_path`, in the order of the .torrent
+pub fn payload_files(info: &BTreeMap<Vec<u8>, Value>, data_path: &Path) -> Result<Vec<(PathBuf, u64)>> {
+    let Some(Value::List(files)) = info.get(b"files".as_slice()) else {
+        let Some(Value::Integer(length)) = info.get(b"length".as_slice()) else {
+            bail!("Torrent has neither files nor length");
+        };
+        return Ok(vec![(data_path.to_path_buf(), u64::try_from(*length)?)]);
+    };
+
+    files.iter().map(|file| {
+        let file = file.as_dict().context("File entry is not a dictionary")?;
+        let (Some(Value::List(components)), Some(Value::Integer(length))) = (file.get(b"path".as_slice()), file.get(b"length".as_slice())) else {
+            bail!("File entry without path or length");
+        };
+        let mut path = data_path.to_path_buf();
+        for component in components {
+            let Value::Bytes(component) = component else {
+                bail!("Path component is not a string");
+            };
+            path.push(String::from_utf8_lossy(component).as_ref());
+        }
+        Ok((path, u64::try_from(*length)?))
+    }).collect()
+}
+
+/// The .libtorrent_resume of a complete torrent whose `files` are on disk: every chunk done and the current mtime
+/// of every file, so rtorrent trusts the data without hashing it. The other fields of `old_resume` and the file
+/// priorities are kept.
+pub fn fast_resume(info: &BTreeMap<Vec<u8>, Value>, files: &[(PathBuf, u64)], old_resume: Option<&Value>) -> Result<Value> {
+    let piece_length = match info.get(b"piece length".as_slice()) {
+        Some(Value::Integer(piece_length)) if *piece_length > 0 => *piece_length as u64,
+        _ => bail!("Invalid piece length"),
+    };
+    let mut resume = match old_resume {
+        Some(Value::Dict(resume)) => resume.clone(),
+        Some(_) => bail!("Resume data is not a dictionary"),
+        None => BTreeMap::new(),
+    };
+    let old_files = match resume.get(b"files".as_slice()) {
+        Some(Value::List(old_files)) if old_files.len() == files.len() => old_files.clone(),
+        _ => Vec::new(),
+    };
+
+    let mut offset = 0;
+    let mut file_entries = Vec::with_capacity(files.len());
+    for (index, (path, length)) in files.iter().enumerate() {
+        let metadata = fs::metadata(path).with_context(|| format!("Failed to read metadata: {:?}", path))?;
+        if metadata.len() != *length {
+            bail!("Size of {:?} is {} instead of {}", path, metadata.len(), length);
+        }
+        let mtime = metadata.modified()?.duration_since(UNIX_EPOCH)?.as_secs() as i64;
+        // Every chunk the file has bytes in, a chunk shared with the neighbours counts for both
+        let completed = match length {
+            0 => 0,
+            _ => (offset + length - 1) / piece_length - offset / piece_length + 1,
+        };
+        offset += length;
+
+        let mut entry = match old_files.get(index) {
+            Some(Value::Dict(entry)) => entry.clone(),
+            _ => BTreeMap::new(),
+        };
+        entry.insert(b"completed".to_vec(), Value::Integer(completed as i64));
+        entry.insert(b"mtime".to_vec(), Value::Integer(mtime));
+        entry.entry(b"priority".to_vec()).or_insert(Value::Integer(1));
+        file_entries.push(Value::Dict(entry));
+    }
+
+    resume.insert(b"bitfield".to_vec(), Value::Integer(offset.div_ceil(piece_length) as i64));
+    resume.insert(b"files".to_vec(), Value::List(file_entries));
+    resume.remove(b"uncertain_pieces".as_slice());
+    resume.insert(b"uncertain_pieces.timestamp".to_vec(), Value::Integer(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64));
+
+    Ok(Value::Dict(resume))
+}
+
+pub fn info_name(info: &BTreeMap<Vec<u8>, Value>) -> Result<String> {
+    match info.get(b"name".as_slice()) {
+        Some(Value::Bytes(name)) => Ok(String::from_utf8_lossy(name).into_owned()),
+        _ => bail!("Missing name"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+
+    #[test]
+    fn reset_resume_file_clears_the_piece_state() {
+        let file_path = std::env::temp_dir().join(format!("reptool-test-{}.libtorrent_resume", std::process::id()));
+        fs::write(&file_path, b"d8:bitfieldi3e5:filesld9:completedi3e5:mtimei1700000000e8:priorityi1eee5:peers0:16:uncertain_piecesli1ee26:uncertain_pieces.timestampi1700000000ee").unwrap();
+        let option = RepToolOption::parse_from(["rtorrent_status_file_modifier"]);
+
+        let is_changed = reset_resume_file(file_path.to_str().unwrap(), &option).unwrap();
+        let content = fs::read(&file_path).unwrap();
+        fs::remove_file(&file_path).unwrap();
+
+        assert!(is_changed);
+        assert_eq!(content, b"d5:filesld9:completedi0e5:mtimei0e8:priorityi1eee5:peers0:e");
+        let resume = bencode::decode(&content).unwrap();
+        for key in PIECE_STATE_KEYS {
+            assert!(!resume.as_dict().unwrap().contains_key(key));
+        }
+    }
+
+    /// A session directory holding a torrent of `pay/a` (3 bytes) and `pay/b` (2 bytes) in pieces of 4 bytes, and
+    /// its data with the `(file, content)` of `data`
+    fn fast_resume_fixture(name: &str, data: &[(&str, &[u8])]) -> (PathBuf, PathBuf, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("reptool-test-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("data").join("pay")).unwrap();
+        for (file, content) in data {
+            fs::write(dir.join("data").join("pay").join(file), content).unwrap();
+        }
+        let torrent = [&b"d4:infod5:filesld6:lengthi3e4:pathl1:aeed6:lengthi2e4:pathl1:beee4:name3:pay12:piece lengthi4e6:pieces40:"[..], &[0; 40], b"ee"].concat();
+        fs::write(dir.join("HASH.torrent"), torrent).unwrap();
+        let directory = dir.join("data").join("pay").display().to_string();
+        fs::write(dir.join("HASH.torrent.rtorrent"), format!("d9:directory{}:{}e", directory.len(), directory)).unwrap();
+        (dir.clone(), dir.join("HASH.torrent.rtorrent"), dir.join("HASH.torrent.libtorrent_resume"))
+    }
+
+    #[test]
+    fn fast_resume_marks_every_chunk_of_a_complete_payload() {
+        let (dir, session_path, resume_path) = fast_resume_fixture("fast-resume", &[("a", b"aaa"), ("b", b"bb")]);
+        // The priority of the second file is kept, the stale piece state dropped
+        fs::write(&resume_path, b"d8:bitfieldi0e5:filesld8:priorityi1eed8:priorityi0eee16:uncertain_piecesli1eee").unwrap();
+        let option = RepToolOption::parse_from(["rtorrent_status_file_modifier"]);
+
+        let modification = fast_resume_file(&session_path, &resume_path, None, &option).unwrap().unwrap();
+        let resume = bencode::decode(&fs::read(&resume_path).unwrap()).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!((modification.old_value.as_str(), modification.new_value.as_str()), ("0 of 2 chunks", "2 of 2 chunks"));
+        let resume = resume.as_dict().unwrap();
+        assert_eq!(resume.get(b"bitfield".as_slice()), Some(&Value::Integer(2)));
+        assert!(!resume.contains_key(b"uncertain_pieces".as_slice()));
+        let Some(Value::List(files)) = resume.get(b"files".as_slice()) else {
+            panic!("Missing files");
+        };
+        // b starts in the first chunk and ends in the second one
+        let fields: Vec<_> = files.iter().map(|file| {
+            let file = file.as_dict().unwrap();
+            (file.get(b"completed".as_slice()).cloned(), file.get(b"priority".as_slice()).cloned())
+        }).collect();
+        assert_eq!(fields, [(Some(Value::Integer(1)), Some(Value::Integer(1))), (Some(Value::Integer(2)), Some(Value::Integer(0)))]);
+    }
+
+    #[test]
+    fn fast_resume_leaves_an_incomplete_payload_alone() {
+        let option = RepToolOption::parse_from(["rtorrent_status_file_modifier"]);
+
+        let (dir, session_path, resume_path) = fast_resume_fixture("fast-resume-missing", &[("a", b"aaa")]);
+        let missing = fast_resume_file(&session_path, &resume_path, None, &option).unwrap().unwrap_err();
+        let is_written = resume_path.exists();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(missing, [dir.join("data").join("pay").join("b")]);
+        assert!(!is_written);
+
+        // A file still being downloaded has the wrong size
+        let (dir, session_path, resume_path) = fast_resume_fixture("fast-resume-short", &[("a", b"aaa"), ("b", b"b")]);
+        let result = fast_resume_file(&session_path, &resume_path, None, &option);
+        let is_written = resume_path.exists();
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(result.is_err());
+        assert!(!is_written);
+    }
+}
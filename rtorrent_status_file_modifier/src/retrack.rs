@@ -0,0 +1,178 @@
+use anyhow::{ensure, Context, Result};
+use clap::Args;
+use reptool_core::bencode::{self, Value};
+use reptool_core::file::{read_buffered, write_atomic};
+use reptool_core::report::{FileReport, RunSummary};
+use reptool_core::{Anchor, Encoding, ModifyReport, ReplaceOptions, Rule};
+
+use crate::backup::backup_before_write;
+use crate::filter::TorrentFilter;
+use crate::glob::FileMatcher;
+use crate::logging::log_changes;
+use crate::{list_files, print_change, process_each, RepToolOption};
+
+#[derive(Args, Clone)]
+pub struct RetrackArgs {
+    /// Session directory
+    pub dir : String,
+
+    /// Search and replace pair of the announce URLs, OLD=NEW or a sed expression s#OLD#NEW#[gi], e.g. the old
+    /// passkey and the new one or the old domain and the new one. Repeatable, the first pair found in a URL is applied
+    #[arg(short = 'e', long = "map", value_name = "OLD=NEW", required = true)]
+    pub mappings : Vec<String>,
+
+    /// Only retrack the torrents whose name matches this regular expression
+    #[arg(long, value_name = "REGEX")]
+    pub name_filter : Option<String>,
+
+    /// Only retrack the torrents with an announce URL matching this regular expression
+    #[arg(long, value_name = "REGEX")]
+    pub tracker_filter : Option<String>,
+}
+
+/// Rewrite the `announce` and `announce-list` URLs of the .torrent files of the selected torrents, in place. Only
+/// these two values are re-encoded and spliced into the original bytes, the info dictionary and every other field
+/// are kept byte for byte so the info-hash, and the names of the session files, stay the same.
+pub fn retrack(matcher: &FileMatcher, option: &RepToolOption, args: &RetrackArgs) -> Result<RunSummary> {
+    let rules = args.mappings.iter().map(|mapping| Rule::parse(mapping)).collect::<Result<Vec<_>>>()?;
+    let options = ReplaceOptions {
+        rules: &rules,
+        patterns: &[],
+        encoding: Encoding::Utf8,
+        only_from: &[],
+        base_only: false,
+        all_occurrences: false,
+        anchor: Anchor::Anywhere,
+        ignore_case: false,
+        path_style: None,
+        normalize: None,
+    };
+
+    let mut filter_option = option.clone();
+    filter_option.replace.name_filter = args.name_filter.clone();
+    filter_option.replace.tracker_filter = args.tracker_filter.clone();
+    let file_paths: Vec<_> = list_files(matcher, option.input_dir()?, option.max_depth())?.into_iter()
+        .filter(|file_path| file_path.to_str().expect("Invalid file name").ends_with(".torrent"))
+        .collect();
+
+    let file_reports = process_each(TorrentFilter::new(&filter_option)?.retain(file_paths), option, |file_path| {
+        let file = file_path.display().to_string();
+        let data = read_buffered(file_path, option.global.io_buffer).with_context(|| format!("Failed to read file: {:?}", file_path))?;
+        let mut torrent = bencode::decode(&data).with_context(|| format!("Failed to decode file: {:?}", file_path))?;
+        let modifications = rewrite_urls(&mut torrent, &options);
+
+        if option.global.dry_run && !option.global.json {
+            for modification in &modifications {
+                print_change(&file, modification);
+            }
+        }
+        let mut bytes_written = 0;
+        if !modifications.is_empty() && !option.global.dry_run {
+            let spliced = splice_urls(&data, &torrent)?;
+            ensure!(bencode::decode(&spliced).ok().as_ref() == Some(&torrent), "Refused to write {:?}, the new URLs do not decode back", file_path);
+            backup_before_write(option, file_path)?;
+            write_atomic(file_path, &spliced, option.global.io_buffer)?;
+            bytes_written = spliced.len() as u64;
+            log_changes(&file, &modifications);
+        }
+
+        Ok(Some(FileReport { matched: !modifications.is_empty(), file, modifications, bytes_written, ..Default::default() }))
+    });
+
+    Ok(RunSummary::new(file_reports))
+}
+
+/// `data` with the values of `announce` and `announce-list` replaced by those of `torrent`, every other byte kept
+fn splice_urls(data: &[u8], torrent: &Value) -> Result<Vec<u8>> {
+    let mut fields = Vec::new();
+    for key in [b"announce".as_slice(), b"announce-list".as_slice()] {
+        let (Some(raw), Some(value)) = (bencode::raw_field(data, key)?, torrent.as_dict().and_then(|dict| dict.get(key))) else {
+            continue;
+        };
+        let start = raw.as_ptr() as usize - data.as_ptr() as usize;
+        fields.push((start, start + raw.len(), bencode::encode(value)));
+    }
+    fields.sort_by_key(|(start, _, _)| *start);
+
+    let mut spliced = Vec::with_capacity(data.len());
+    let mut copied = 0;
+    for (start, end, encoded) in fields {
+        spliced.extend_from_slice(&data[copied..start]);
+        spliced.extend_from_slice(&encoded);
+        copied = end;
+    }
+    spliced.extend_from_slice(&data[copied..]);
+
+    Ok(spliced)
+}
+
+/// Apply `options` to `announce` and every URL of `announce-list`, reported as `announce-list[0][1]`
+fn rewrite_urls(torrent: &mut Value, options: &ReplaceOptions) -> Vec<ModifyReport> {
+    let Value::Dict(dict) = torrent else {
+        return Vec::new();
+    };
+
+    let mut modifications = Vec::new();
+    if let Some(url) = dict.get_mut(b"announce".as_slice()) {
+        modifications.extend(rewrite_url(url, "announce".to_owned(), options));
+    }
+    if let Some(Value::List(tiers)) = dict.get_mut(b"announce-list".as_slice()) {
+        for (tier_index, tier) in tiers.iter_mut().enumerate() {
+            let Value::List(tier) = tier else {
+                continue;
+            };
+            for (index, url) in tier.iter_mut().enumerate() {
+                modifications.extend(rewrite_url(url, format!("announce-list[{}][{}]", tier_index, index), options));
+            }
+        }
+    }
+
+    modifications
+}
+
+fn rewrite_url(url: &mut Value, key: String, options: &ReplaceOptions) -> Option<ModifyReport> {
+    let Value::Bytes(bytes) = url else {
+        return None;
+    };
+    let new_bytes = options.apply_bytes(bytes).filter(|new_bytes| new_bytes != bytes)?;
+    let modification = ModifyReport {
+        key,
+        old_value: String::from_utf8_lossy(bytes).into_owned(),
+        new_value: String::from_utf8_lossy(&new_bytes).into_owned(),
+        old_length: bytes.len(),
+        new_length: new_bytes.len(),
+    };
+    *bytes = new_bytes;
+
+    Some(modification)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splice_keeps_a_non_canonical_info_dictionary() {
+        // The keys of info are not sorted, re-encoding the whole file would change the info-hash
+        let data = b"d8:announce13:http://tr/ann13:announce-listll13:http://tr/annee4:infod4:name1:x6:lengthi1eee";
+        let mut torrent = bencode::decode(data).unwrap();
+        let rules = [Rule::parse("http://tr/=https://new.tr/").unwrap()];
+        let options = ReplaceOptions {
+            rules: &rules,
+            patterns: &[],
+            encoding: Encoding::Utf8,
+            only_from: &[],
+            base_only: false,
+            all_occurrences: false,
+            anchor: Anchor::Anywhere,
+            ignore_case: false,
+            path_style: None,
+            normalize: None,
+        };
+
+        assert_eq!(rewrite_urls(&mut torrent, &options).len(), 2);
+        let spliced = splice_urls(data, &torrent).unwrap();
+        assert_eq!(spliced, b"d8:announce18:https://new.tr/ann13:announce-listll18:https://new.tr/annee4:infod4:name1:x6:lengthi1eee");
+        assert_eq!(bencode::raw_field(&spliced, b"info").unwrap(), bencode::raw_field(data, b"info").unwrap());
+    }
+}
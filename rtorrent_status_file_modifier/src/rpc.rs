@@ -0,0 +1,164 @@
+use std::collections::HashSet;
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use regex::Regex;
+
+const RPC_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A scalar from an XML-RPC response, rtorrent only returns strings and integers for the calls we make
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RpcValue {
+    String(String),
+    Integer(i64),
+}
+
+/// A torrent loaded in the running rtorrent
+#[derive(Debug, Clone)]
+pub struct Torrent {
+    /// Info-hash, upper case hex
+    pub hash: String,
+    /// `d.directory_base`, the value kept in the `directory` field of the session file
+    pub directory: String,
+    pub is_open: bool,
+    pub is_active: bool,
+}
+
+/// Minimal XML-RPC over HTTP client, rtorrent is usually exposed through a web server's SCGI bridge at /RPC2
+pub struct RpcClient {
+    host: String,
+    port: u16,
+    path: String,
+}
+
+impl RpcClient {
+    pub fn new(url: &str) -> Result<Self> {
+        let rest = url.strip_prefix("http://").with_context(|| format!("Only http:// XML-RPC urls are supported: {:?}", url))?;
+        let (authority, path) = match rest.find('/') {
+            Some(index) => (&rest[..index], &rest[index..]),
+            None => (rest, "/RPC2"),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse().with_context(|| format!("Invalid port in url: {:?}", url))?),
+            None => (authority, 80),
+        };
+
+        Ok(RpcClient { host: host.to_owned(), port, path: path.to_owned() })
+    }
+
+    /// Call `method` with string parameters and return every scalar of the response in document order
+    pub fn call(&self, method: &str, params: &[&str]) -> Result<Vec<RpcValue>> {
+        let mut body = format!("<?xml version=\"1.0\"?><methodCall><methodName>{}</methodName><params>", escape(method));
+        for param in params {
+            body.push_str(&format!("<param><value><string>{}</string></value></param>", escape(param)));
+        }
+        body.push_str("</params></methodCall>");
+
+        let address = (self.host.as_str(), self.port).to_socket_addrs()?.next()
+            .with_context(|| format!("Failed to resolve {}", self.host))?;
+        let mut stream = TcpStream::connect_timeout(&address, RPC_TIMEOUT)
+            .with_context(|| format!("Failed to connect to {}:{}", self.host, self.port))?;
+        stream.set_read_timeout(Some(RPC_TIMEOUT))?;
+        stream.set_write_timeout(Some(RPC_TIMEOUT))?;
+
+        // HTTP/1.0 keeps the response free of chunked encoding
+        let request = format!(
+            "POST {} HTTP/1.0\r\nHost: {}\r\nContent-Type: text/xml\r\nContent-Length: {}\r\n\r\n{}",
+            self.path, self.host, body.len(), body
+        );
+        stream.write_all(request.as_bytes())?;
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).context("Failed to read XML-RPC response")?;
+        let (head, body) = response.split_once("\r\n\r\n").context("Malformed HTTP response")?;
+        let status = head.lines().next().unwrap_or_default();
+        if !status.contains(" 200") {
+            bail!("XML-RPC request failed: {}", status);
+        }
+
+        parse_response(body)
+    }
+
+    /// Info-hashes (upper case hex) of the torrents rtorrent reports as active
+    pub fn active_hashes(&self) -> Result<HashSet<String>> {
+        let values = self.call("d.multicall2", &["", "main", "d.hash=", "d.is_active="])?;
+
+        let mut hashes = HashSet::new();
+        for row in values.chunks(2) {
+            if let [RpcValue::String(hash), RpcValue::Integer(active)] = row {
+                if *active != 0 {
+                    hashes.insert(hash.to_uppercase());
+                }
+            } else {
+                bail!("Unexpected d.multicall2 row: {:?}", row);
+            }
+        }
+
+        Ok(hashes)
+    }
+
+    pub fn torrents(&self) -> Result<Vec<Torrent>> {
+        let values = self.call("d.multicall2", &["", "main", "d.hash=", "d.directory_base=", "d.is_open=", "d.is_active="])?;
+
+        values.chunks(4).map(|row| match row {
+            [RpcValue::String(hash), RpcValue::String(directory), RpcValue::Integer(is_open), RpcValue::Integer(is_active)] => Ok(Torrent {
+                hash: hash.to_uppercase(),
+                directory: directory.clone(),
+                is_open: *is_open != 0,
+                is_active: *is_active != 0,
+            }),
+            _ => bail!("Unexpected d.multicall2 row: {:?}", row),
+        }).collect()
+    }
+
+    /// Move a loaded torrent to `directory` and persist it to its session file.
+    /// rtorrent refuses to change the directory of an open download, so it is closed around the change
+    /// and started again if it was active, even when the change failed.
+    pub fn set_directory(&self, torrent: &Torrent, directory: &str) -> Result<()> {
+        let hash = torrent.hash.as_str();
+        if torrent.is_active {
+            self.call("d.stop", &[hash])?;
+        }
+        let changed = (|| {
+            if torrent.is_open {
+                self.call("d.close", &[hash])?;
+            }
+            self.call("d.directory_base.set", &[hash, directory])?;
+            self.call("d.save_full_session", &[hash])
+        })();
+        if torrent.is_active {
+            let started = self.call("d.start", &[hash]);
+            changed?;
+            started.context("Failed to start the torrent again")?;
+        } else {
+            changed?;
+        }
+
+        Ok(())
+    }
+}
+
+fn parse_response(body: &str) -> Result<Vec<RpcValue>> {
+    if body.contains("<fault>") {
+        bail!("XML-RPC fault: {}", body);
+    }
+
+    let re = Regex::new(r"<(string|i4|i8|int)>([^<]*)</(?:string|i4|i8|int)>|<string/>").expect("Failed to construct regex pattern");
+    re.captures_iter(body).map(|cap| {
+        match cap.get(1).map(|tag| tag.as_str()) {
+            Some("string") => Ok(RpcValue::String(unescape(&cap[2]))),
+            Some(_) => Ok(RpcValue::Integer(cap[2].trim().parse().with_context(|| format!("Invalid integer {:?}", &cap[2]))?)),
+            None => Ok(RpcValue::String(String::new())),
+        }
+    }).collect()
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<").replace("&gt;", ">").replace("&quot;", "\"").replace("&apos;", "'").replace("&amp;", "&")
+}
@@ -0,0 +1,265 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use regex::Regex;
+use reptool_core::bencode::Value;
+use reptool_core::{Anchor, Encoding, ModifyReport, ReplaceOptions, ReplaceReport, Rule, SessionFile};
+use serde::Deserialize;
+
+use crate::label::{self, LABEL_KEY};
+use crate::template::TemplateVars;
+
+/// Which of the matching rules of a torrent are applied
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Mode {
+    /// Only the first rule whose conditions hold
+    #[default]
+    FirstMatch,
+    /// Every rule whose conditions hold, in order, each one rewriting what the previous ones left
+    AllMatch,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RulesFile {
+    #[serde(default)]
+    mode: Mode,
+    #[serde(default)]
+    rule: Vec<RuleSpec>,
+}
+
+/// A `[[rule]]` of the rules file as written
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct RuleSpec {
+    tracker: Option<String>,
+    label: Option<String>,
+    path_prefix: Option<String>,
+    name: Option<String>,
+    rewrite: Option<String>,
+    set_label: Option<String>,
+}
+
+/// A rule of --rules, its conditions must all hold for its actions to apply
+#[derive(Debug, Clone)]
+struct ConditionalRule {
+    /// Matches one of the announce URLs of the .torrent
+    tracker: Option<Regex>,
+    /// The ruTorrent label, empty for none
+    label: Option<String>,
+    /// The keyword field starts with it
+    path_prefix: Option<String>,
+    /// Matches `info.name` of the .torrent
+    name: Option<Regex>,
+    /// Replaces the path prefix, or the whole keyword value without one. Placeholders are resolved per torrent.
+    rewrite: Option<String>,
+    /// New label, placeholders are resolved per torrent
+    set_label: Option<String>,
+}
+
+/// The rules of a --rules file, evaluated per torrent in order
+#[derive(Debug, Clone)]
+pub struct RuleSet {
+    mode: Mode,
+    rules: Vec<ConditionalRule>,
+}
+
+impl RuleSet {
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path).with_context(|| format!("Failed to read rules file: {:?}", path))?;
+        let rules_file: RulesFile = toml::from_str(&content).with_context(|| format!("Failed to parse rules file: {:?}", path))?;
+
+        let mut rules = Vec::new();
+        for (index, spec) in rules_file.rule.into_iter().enumerate() {
+            let regex = |pattern: Option<String>, field: &str| pattern
+                .map(|pattern| Regex::new(&pattern).with_context(|| format!("Invalid {} in rule {} of {:?}: {:?}", field, index + 1, path, pattern)))
+                .transpose();
+            if spec.rewrite.is_none() && spec.set_label.is_none() {
+                bail!("Rule {} of {:?} has no rewrite or set-label", index + 1, path);
+            }
+            rules.push(ConditionalRule {
+                tracker: regex(spec.tracker, "tracker")?,
+                label: spec.label,
+                path_prefix: spec.path_prefix,
+                name: regex(spec.name, "name")?,
+                rewrite: spec.rewrite,
+                set_label: spec.set_label,
+            });
+        }
+        if rules.is_empty() {
+            bail!("No [[rule]] in rules file: {:?}", path);
+        }
+
+        Ok(RuleSet { mode: rules_file.mode, rules })
+    }
+
+    /// The rules of --per-tracker-map: `prefix` becomes the directory of the first HOST=DIR of `map` whose host is
+    /// the one of an announce URL or a parent domain of it, or `fallback` for the torrents of other trackers
+    pub fn per_tracker(map: &[String], prefix: &str, fallback: Option<&str>) -> Result<Self> {
+        let mut rules = Vec::new();
+        for mapping in map {
+            let (host, directory) = mapping.split_once('=')
+                .filter(|(host, directory)| !host.trim().is_empty() && !directory.is_empty())
+                .with_context(|| format!("Invalid --per-tracker-map entry, expected HOST=DIR: {:?}", mapping))?;
+            let pattern = format!(r"(?i)^[a-z][a-z0-9+.-]*://([^/@]*@)?([^/@]*\.)?{}(:\d+)?([/?#]|$)", regex::escape(host.trim()));
+            rules.push(ConditionalRule {
+                tracker: Some(Regex::new(&pattern).with_context(|| format!("Invalid host in --per-tracker-map: {:?}", host))?),
+                label: None,
+                path_prefix: Some(prefix.to_owned()),
+                name: None,
+                rewrite: Some(directory.to_owned()),
+                set_label: None,
+            });
+        }
+        if let Some(fallback) = fallback {
+            rules.push(ConditionalRule {
+                tracker: None,
+                label: None,
+                path_prefix: Some(prefix.to_owned()),
+                name: None,
+                rewrite: Some(fallback.to_owned()),
+                set_label: None,
+            });
+        }
+
+        Ok(RuleSet { mode: Mode::FirstMatch, rules })
+    }
+
+    /// Apply the actions of the rules whose conditions hold for the torrent of `session_file`. The conditions are
+    /// evaluated on the torrent as it was read, before any rule changed it. `options` gives everything but the rules.
+    pub fn apply(&self, session_file: &mut SessionFile, keywords: &[String], options: &ReplaceOptions, io_buffer: usize) -> Result<ReplaceReport> {
+        let mut report = ReplaceReport { keys: session_file.keys_matching_any(keywords)?, ..Default::default() };
+        // Only the .torrent.rtorrent holds the keyword, the label is not added to the other files
+        if report.keys.is_empty() {
+            return Ok(report);
+        }
+        let values: Vec<String> = report.keys.iter()
+            .filter_map(|key| match session_file.get(key) {
+                Some(Value::Bytes(bytes)) => Some(options.encoding.decode(bytes).unwrap_or_else(|_| String::from_utf8_lossy(bytes).into_owned())),
+                _ => None,
+            })
+            .collect();
+        let vars = TemplateVars::new(session_file, io_buffer);
+        let file_path = session_file.path().to_path_buf();
+
+        for rule in self.rules.iter().filter(|rule| rule.matches(&vars, &values)) {
+            if let Some(rewrite) = &rule.rewrite {
+                // Without a prefix the whole value of the first keyword field is replaced
+                let find = rule.path_prefix.clone().or_else(|| values.first().cloned());
+                if let Some(find) = find {
+                    let rules = [Rule { find, replace: vars.expand(rewrite, &file_path)?, ignore_case: false }];
+                    let rewritten = session_file.replace(keywords, &ReplaceOptions { rules: &rules, patterns: &[], anchor: Anchor::Prefix, ..*options })?;
+                    for modification in rewritten.modifications {
+                        merge(&mut report.modifications, modification);
+                    }
+                    report.outside_roots.extend(rewritten.outside_roots);
+                    report.already_migrated.extend(rewritten.already_migrated);
+                }
+            }
+            if let Some(set_label) = &rule.set_label {
+                let new_label = label::encode(&vars.expand(set_label, &file_path)?);
+                if let Some(modification) = session_file.insert(LABEL_KEY, Value::Bytes(new_label), Encoding::Utf8)? {
+                    merge(&mut report.modifications, modification);
+                }
+            }
+            if self.mode == Mode::FirstMatch {
+                break;
+            }
+        }
+
+        Ok(report)
+    }
+}
+
+impl ConditionalRule {
+    fn matches(&self, vars: &TemplateVars, values: &[String]) -> bool {
+        self.tracker.as_ref().is_none_or(|tracker| vars.announce_urls().iter().any(|url| tracker.is_match(url)))
+            && self.label.as_deref().is_none_or(|label| vars.label().unwrap_or_default() == label)
+            && self.path_prefix.as_deref().is_none_or(|prefix| values.iter().any(|value| value.starts_with(prefix)))
+            && self.name.as_ref().is_none_or(|name| vars.name().is_some_and(|torrent_name| name.is_match(torrent_name)))
+    }
+}
+
+/// A field rewritten by several rules is reported once, from its original value to its last one
+pub fn merge(modifications: &mut Vec<ModifyReport>, modification: ModifyReport) {
+    match modifications.iter_mut().find(|existing| existing.key == modification.key) {
+        Some(existing) => {
+            existing.new_value = modification.new_value;
+            existing.new_length = modification.new_length;
+        }
+        None => modifications.push(modification),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule_set(name: &str, content: &str) -> RuleSet {
+        let path = std::env::temp_dir().join(format!("reptool-test-{}-{}.toml", name, std::process::id()));
+        fs::write(&path, content).unwrap();
+        let rule_set = RuleSet::load(&path);
+        fs::remove_file(&path).unwrap();
+        rule_set.unwrap()
+    }
+
+    fn apply(rule_set: &RuleSet, content: &[u8]) -> Vec<(String, String)> {
+        let mut session_file = SessionFile::decode("HASH.torrent.rtorrent", content).unwrap();
+        let options = ReplaceOptions {
+            rules: &[],
+            patterns: &[],
+            encoding: Encoding::Utf8,
+            only_from: &[],
+            base_only: false,
+            all_occurrences: false,
+            anchor: Anchor::Anywhere,
+            ignore_case: false,
+            path_style: None,
+            normalize: None,
+        };
+        let report = rule_set.apply(&mut session_file, &["directory".to_owned()], &options, 0).unwrap();
+        report.modifications.into_iter().map(|modification| (modification.key, modification.new_value)).collect()
+    }
+
+    const RULES: &str = r#"
+        [[rule]]
+        path-prefix = "/other"
+        rewrite = "/never"
+
+        [[rule]]
+        path-prefix = "/mnt/old"
+        rewrite = "/mnt/new"
+
+        [[rule]]
+        path-prefix = "/mnt"
+        set-label = "moved"
+    "#;
+
+    #[test]
+    fn first_match_applies_only_the_first_rule_that_holds() {
+        let modifications = apply(&rule_set("first", RULES), b"d9:directory12:/mnt/old/abce");
+        assert_eq!(modifications, [("directory".to_owned(), "/mnt/new/abc".to_owned())]);
+    }
+
+    #[test]
+    fn all_match_applies_every_rule_that_holds() {
+        let rule_set = rule_set("all", &format!("mode = \"all-match\"\n{}", RULES));
+        let modifications = apply(&rule_set, b"d9:directory12:/mnt/old/abce");
+        assert_eq!(modifications, [("directory".to_owned(), "/mnt/new/abc".to_owned()), (LABEL_KEY.to_owned(), "moved".to_owned())]);
+
+        // The rules whose conditions do not hold are still skipped
+        assert_eq!(apply(&rule_set, b"d9:directory7:/other/e"), [("directory".to_owned(), "/never/".to_owned())]);
+    }
+
+    #[test]
+    fn load_rejects_a_rule_without_action() {
+        let path = std::env::temp_dir().join(format!("reptool-test-empty-{}.toml", std::process::id()));
+        fs::write(&path, "[[rule]]\npath-prefix = \"/mnt\"\n").unwrap();
+        let loaded = RuleSet::load(&path);
+        fs::remove_file(&path).unwrap();
+
+        assert!(loaded.is_err());
+    }
+}
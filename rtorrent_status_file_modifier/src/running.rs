@@ -0,0 +1,119 @@
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
+use tracing::{info, warn};
+
+// rtorrent keeps this file in its session directory while it runs, it holds `<hostname>:+<pid>`
+const LOCK_FILE_NAME: &str = "rtorrent.lock";
+
+// Ours, in the same format so a stale one is detected the same way
+const RUN_LOCK_FILE_NAME: &str = ".reptool.lock";
+
+const LOCK_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Refuse to edit a session directory a running rtorrent owns, it rewrites the session files on shutdown
+/// and the changes would be lost. A lock left behind by a dead process on this host is ignored.
+pub fn ensure_not_running(session_dir: &Path, force: bool) -> Result<()> {
+    let lock_path = session_dir.join(LOCK_FILE_NAME);
+    let Ok(content) = fs::read_to_string(&lock_path) else {
+        return Ok(());
+    };
+    let owner = content.trim();
+
+    if is_stale(owner) {
+        warn!("Ignoring stale {} left by {}", lock_path.display(), owner);
+        return Ok(());
+    }
+    if force {
+        warn!("rtorrent seems to be running ({} held by {}), editing anyway because of --force", lock_path.display(), owner);
+        return Ok(());
+    }
+
+    bail!("rtorrent seems to be running ({} held by {}), stop it first or pass --force", lock_path.display(), owner)
+}
+
+/// Whether a live rtorrent holds the lock of the session directory
+pub fn is_running(session_dir: &Path) -> bool {
+    fs::read_to_string(session_dir.join(LOCK_FILE_NAME)).is_ok_and(|content| !is_stale(content.trim()))
+}
+
+/// Keeps other reptool runs out of a session directory until dropped, a cron run and a manual one would otherwise
+/// interleave their writes to the same files
+pub struct RunLock {
+    path: PathBuf,
+}
+
+impl RunLock {
+    /// Take the lock of `session_dir`. A lock held by a live run fails at once unless `wait` is given, then it is
+    /// waited for, forever without a number of seconds. A lock left behind by a dead run is taken over.
+    pub fn acquire(session_dir: &Path, wait: Option<Option<u64>>) -> Result<Self> {
+        let path = session_dir.join(RUN_LOCK_FILE_NAME);
+        let started = Instant::now();
+        let mut is_waiting = false;
+        loop {
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(mut file) => {
+                    file.write_all(owner().as_bytes()).with_context(|| format!("Failed to write lock: {:?}", path))?;
+                    return Ok(RunLock { path });
+                }
+                Err(err) if err.kind() == ErrorKind::AlreadyExists => {}
+                Err(err) => return Err(err).with_context(|| format!("Failed to create lock: {:?}", path)),
+            }
+
+            // The holder may have released it in between
+            let Ok(content) = fs::read_to_string(&path) else {
+                continue;
+            };
+            let holder = content.trim();
+            if is_stale(holder) {
+                warn!("Taking over stale {} left by {}", path.display(), holder);
+                fs::remove_file(&path).with_context(|| format!("Failed to remove stale lock: {:?}", path))?;
+                continue;
+            }
+            match wait {
+                None => bail!("Another run ({}) is editing {}, pass --wait to wait for it", holder, session_dir.display()),
+                Some(Some(seconds)) if started.elapsed() >= Duration::from_secs(seconds) => {
+                    bail!("Another run ({}) is still editing {} after {}s", holder, session_dir.display(), seconds)
+                }
+                Some(_) if !is_waiting => {
+                    info!("Waiting for another run ({}) to finish with {}", holder, session_dir.display());
+                    is_waiting = true;
+                }
+                Some(_) => {}
+            }
+            thread::sleep(LOCK_POLL_INTERVAL);
+        }
+    }
+}
+
+impl Drop for RunLock {
+    fn drop(&mut self) {
+        if let Err(err) = fs::remove_file(&self.path) {
+            warn!("Failed to remove lock {}: {}", self.path.display(), err);
+        }
+    }
+}
+
+/// `<hostname>:+<pid>` of this process, as rtorrent writes it
+fn owner() -> String {
+    let hostname = fs::read_to_string("/proc/sys/kernel/hostname").unwrap_or_default();
+    format!("{}:+{}", hostname.trim(), process::id())
+}
+
+/// Only a lock of this host whose process is gone is known to be stale
+fn is_stale(owner: &str) -> bool {
+    let Some((hostname, pid)) = owner.rsplit_once(":+") else {
+        return false;
+    };
+    let Ok(pid) = pid.parse::<u32>() else {
+        return false;
+    };
+    let local_hostname = fs::read_to_string("/proc/sys/kernel/hostname").unwrap_or_default();
+
+    hostname == local_hostname.trim() && Path::new("/proc/self").exists() && !Path::new(&format!("/proc/{}", pid)).exists()
+}
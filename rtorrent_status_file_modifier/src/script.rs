@@ -0,0 +1,86 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Context, Result};
+use reptool_core::bencode::Value;
+use reptool_core::file::read_buffered;
+use reptool_core::{bencode, Encoding, ModifyReport, SessionFile};
+use rhai::{Array, Blob, Dynamic, Engine, Map, Scope, AST};
+
+use crate::session::torrent_path_of;
+
+// Called once per .torrent.rtorrent
+const ENTRY_POINT: &str = "rewrite";
+
+/// A --script, compiled once and run on every .torrent.rtorrent. The script defines `fn rewrite(session, torrent)`
+/// getting the session and the .torrent as maps, `()` without a .torrent, and returning a map of the top level
+/// fields to set, strings or integers, or `()` to leave the file as it is.
+pub struct Script {
+    engine: Engine,
+    ast: AST,
+}
+
+impl Script {
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path).with_context(|| format!("Failed to read script: {:?}", path))?;
+        let engine = Engine::new();
+        let ast = engine.compile(&content).map_err(|err| anyhow!("{}", err)).with_context(|| format!("Failed to compile script: {:?}", path))?;
+        if !ast.iter_functions().any(|function| function.name == ENTRY_POINT && function.params.len() == 2) {
+            bail!("Script {:?} defines no fn {}(session, torrent)", path, ENTRY_POINT);
+        }
+
+        Ok(Script { engine, ast })
+    }
+
+    /// Run the script on `session_file` and set the fields it returns
+    pub fn apply(&self, session_file: &mut SessionFile, encoding: Encoding, io_buffer: usize) -> Result<Vec<ModifyReport>> {
+        let file_path = session_file.path().to_path_buf();
+        let torrent = read_buffered(&torrent_path_of(&file_path), io_buffer).ok()
+            .and_then(|data| bencode::decode(&data).ok())
+            .map_or(Dynamic::UNIT, |torrent| to_dynamic(&torrent, encoding));
+        let session = to_dynamic(session_file.value(), encoding);
+
+        let returned: Dynamic = self.engine.call_fn(&mut Scope::new(), &self.ast, ENTRY_POINT, (session, torrent))
+            .map_err(|err| anyhow!("{}", err))
+            .with_context(|| format!("Script failed on {}", file_path.display()))?;
+        if returned.is_unit() {
+            return Ok(Vec::new());
+        }
+        let type_name = returned.type_name();
+        let Some(fields) = returned.try_cast::<Map>() else {
+            bail!("Script returned a {} for {}, expected a map or ()", type_name, file_path.display());
+        };
+
+        let mut modifications = Vec::new();
+        for (key, value) in fields {
+            let value = if value.is_string() {
+                let text = value.into_string().map_err(|err| anyhow!("{}", err))?;
+                Value::Bytes(encoding.encode(&text).with_context(|| format!("Value of {} returned by the script", key))?)
+            } else if value.is_int() {
+                Value::Integer(value.as_int().map_err(|err| anyhow!("{}", err))?)
+            } else if value.is_blob() {
+                Value::Bytes(value.cast::<Blob>())
+            } else {
+                bail!("Script returned a {} for {} of {}, expected a string or an integer", value.type_name(), key, file_path.display());
+            };
+            modifications.extend(session_file.insert(&key, value, encoding)?);
+        }
+
+        Ok(modifications)
+    }
+}
+
+/// Strings readable in `encoding` become Rhai strings, the others blobs
+fn to_dynamic(value: &Value, encoding: Encoding) -> Dynamic {
+    match value {
+        Value::Integer(integer) => Dynamic::from_int(*integer),
+        Value::Bytes(bytes) => match encoding.decode(bytes) {
+            Ok(text) if !text.chars().any(|c| c.is_control()) => Dynamic::from(text),
+            _ => Dynamic::from_blob(bytes.clone()),
+        },
+        Value::List(list) => Dynamic::from_array(list.iter().map(|item| to_dynamic(item, encoding)).collect::<Array>()),
+        Value::Dict(dict) => Dynamic::from_map(dict.iter()
+            .map(|(key, item)| (String::from_utf8_lossy(key).into(), to_dynamic(item, encoding)))
+            .collect::<Map>()),
+    }
+}
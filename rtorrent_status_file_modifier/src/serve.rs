@@ -0,0 +1,325 @@
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use clap::Args;
+use reptool_core::report::RunSummary;
+use serde::Deserialize;
+use serde_json::json;
+use tracing::{info, warn};
+
+use crate::glob::FileMatcher;
+use crate::inspect::sessions_json;
+use crate::session::load_sessions;
+use crate::{collect_rules, list_files, lock_for_run, replace_files, RepToolOption};
+
+#[derive(Args, Clone)]
+pub struct ServeArgs {
+    /// Session directory
+    pub dir : String,
+
+    /// Address to listen on, keep it on a trusted interface
+    #[arg(long, value_name = "ADDRESS", default_value = "127.0.0.1:7070")]
+    pub listen : String,
+
+    /// Require every request to carry this token as `Authorization: Bearer TOKEN`
+    #[arg(long, value_name = "TOKEN")]
+    pub token : Option<String>,
+}
+
+// Requests are small JSON documents, anything bigger is a mistake
+const MAX_BODY_LEN: usize = 1024 * 1024;
+// The request line and the headers, together
+const MAX_HEAD_LEN: u64 = 16 * 1024;
+const MAX_HEADERS: usize = 64;
+// The reports of the older applies are dropped
+const MAX_REPORTS: usize = 100;
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Body of POST /preview and POST /apply
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ReplaceRequest {
+    /// OLD=NEW pairs, tried in order like -e
+    mappings: Vec<String>,
+    /// Only these info-hashes, like --hash
+    #[serde(default)]
+    hash: Vec<String>,
+}
+
+struct Request {
+    method: String,
+    path: String,
+    host: Option<String>,
+    origin: Option<String>,
+    content_type: Option<String>,
+    authorization: Option<String>,
+    body: Vec<u8>,
+}
+
+/// Serve the REST API one request at a time, so two applies never run at once:
+///
+/// - `GET /torrents`: the torrents as list --json shows them
+/// - `POST /preview`: the report of a dry run of `{"mappings": ["OLD=NEW"], "hash": []}`
+/// - `POST /apply`: the same run in place, its report is kept
+/// - `GET /reports` and `GET /reports/<id>`: the reports of the last applies since the server started
+///
+/// A page open in a browser must not be able to drive the API: the Host must be the listen address, an Origin must
+/// be that address too and the POST bodies must be sent as application/json, which a page cannot do without CORS.
+pub fn serve(matcher: &FileMatcher, option: &RepToolOption, args: &ServeArgs) -> Result<()> {
+    let listener = TcpListener::bind(&args.listen).with_context(|| format!("Failed to listen on {}", args.listen))?;
+    let local_addr = listener.local_addr()?;
+    if !local_addr.ip().is_loopback() && args.token.is_none() {
+        warn!("Listening on {} without --token, anyone reaching it can edit the session", args.listen);
+    }
+    // On every interface the clients may use any of its addresses, only the token keeps other sites out
+    let mut hosts = Vec::new();
+    if local_addr.ip().is_unspecified() {
+        if args.token.is_none() {
+            bail!("Listening on {} needs --token, the Host of the requests cannot be checked", args.listen);
+        }
+    } else {
+        hosts.push(local_addr.to_string());
+    }
+    if local_addr.ip().is_loopback() {
+        hosts.push(format!("localhost:{}", local_addr.port()));
+    }
+    println!("Serving {} on http://{}", option.input_dir()?.display(), listener.local_addr()?);
+
+    let mut reports = Reports::default();
+    for stream in listener.incoming() {
+        let mut stream = match stream {
+            Ok(stream) => stream,
+            Err(err) => {
+                warn!("Failed to accept connection: {}", err);
+                continue;
+            }
+        };
+        if let Err(err) = stream.set_read_timeout(Some(CLIENT_TIMEOUT)) {
+            warn!("Failed to set the read timeout: {}", err);
+            continue;
+        }
+        let (status, body) = match read_request(&mut stream) {
+            Ok(request) => {
+                info!("{} {}", request.method, request.path);
+                match refuse(&request, &hosts, args.token.as_deref()) {
+                    Some(refused) => refused,
+                    None => route(matcher, option, &request, &mut reports).unwrap_or_else(|err| (500, json!({ "error": format!("{:#}", err) }))),
+                }
+            }
+            Err(err) => (400, json!({ "error": format!("{:#}", err) })),
+        };
+        if let Err(err) = respond(&mut stream, status, &body) {
+            warn!("Failed to send response: {:#}", err);
+        }
+    }
+
+    Ok(())
+}
+
+/// Why a request is turned away before being routed, `None` when it may go through. No `hosts` takes any Host.
+fn refuse(request: &Request, hosts: &[String], token: Option<&str>) -> Option<(u16, serde_json::Value)> {
+    // A name resolving to the server by DNS rebinding still sends its own name as Host
+    let Some(host) = request.host.as_ref().filter(|host| hosts.is_empty() || hosts.contains(host)) else {
+        return Some((403, json!({ "error": format!("Host {:?} is not the listen address", request.host.as_deref().unwrap_or_default()) })));
+    };
+    if let Some(origin) = request.origin.as_ref().filter(|origin| **origin != format!("http://{}", host)) {
+        return Some((403, json!({ "error": format!("Origin {:?} is not allowed", origin) })));
+    }
+    if let Some(token) = token {
+        if request.authorization.as_deref() != Some(&format!("Bearer {}", token)) {
+            return Some((401, json!({ "error": "Missing or wrong token" })));
+        }
+    }
+    // A form or a plain fetch of another page cannot send application/json without a CORS preflight
+    let is_json = request.content_type.as_deref()
+        .is_some_and(|content_type| content_type.split(';').next().unwrap_or_default().trim().eq_ignore_ascii_case("application/json"));
+    if request.method == "POST" && !is_json {
+        return Some((415, json!({ "error": "The body must be sent as application/json" })));
+    }
+    None
+}
+
+/// The reports of the last `MAX_REPORTS` applies, numbered from 1 since the server started
+#[derive(Default)]
+struct Reports {
+    kept: VecDeque<(usize, RunSummary)>,
+    count: usize,
+}
+
+impl Reports {
+    /// Keep `summary`, dropping the oldest report when full, its id
+    fn push(&mut self, summary: RunSummary) -> usize {
+        self.count += 1;
+        if self.kept.len() == MAX_REPORTS {
+            self.kept.pop_front();
+        }
+        self.kept.push_back((self.count, summary));
+        self.count
+    }
+
+    fn get(&self, id: usize) -> Option<&RunSummary> {
+        self.kept.iter().find(|(kept_id, _)| *kept_id == id).map(|(_, summary)| summary)
+    }
+}
+
+fn route(matcher: &FileMatcher, option: &RepToolOption, request: &Request, reports: &mut Reports) -> Result<(u16, serde_json::Value)> {
+    let path = request.path.split('?').next().unwrap_or_default().trim_end_matches('/');
+    Ok(match (request.method.as_str(), path) {
+        ("GET", "/torrents") => {
+            let file_paths = list_files(matcher, option.input_dir()?, option.max_depth())?;
+            (200, sessions_json(&load_sessions(&file_paths, option.keyword(), option.global.encoding)))
+        }
+        ("POST", "/preview" | "/apply") => {
+            let replace_request: ReplaceRequest = match serde_json::from_slice(&request.body) {
+                Ok(replace_request) => replace_request,
+                Err(err) => return Ok((400, json!({ "error": format!("Invalid request body: {}", err) }))),
+            };
+            let summary = run(matcher, option, &replace_request, path == "/apply")?;
+            let mut body = serde_json::to_value(&summary)?;
+            if path == "/apply" {
+                body["id"] = json!(reports.push(summary));
+            }
+            (200, body)
+        }
+        ("GET", "/reports") => (200, reports.kept.iter().map(|(id, summary)| json!({
+            "id": id,
+            "files_scanned": summary.files_scanned,
+            "files_matched": summary.files_matched,
+            "files_failed": summary.files_failed,
+        })).collect()),
+        ("GET", path) if path.starts_with("/reports/") => {
+            match path["/reports/".len()..].parse::<usize>().ok().and_then(|id| reports.get(id)) {
+                Some(summary) => (200, serde_json::to_value(summary)?),
+                None => (404, json!({ "error": format!("No such report, only the last {} are kept", MAX_REPORTS) })),
+            }
+        }
+        (_, "/torrents" | "/preview" | "/apply" | "/reports") => (405, json!({ "error": "Method not allowed" })),
+        _ => (404, json!({ "error": "Not found" })),
+    })
+}
+
+/// A replace run with the rules of the request, in place when `apply` and else a dry run
+fn run(matcher: &FileMatcher, option: &RepToolOption, replace_request: &ReplaceRequest, apply: bool) -> Result<RunSummary> {
+    let mut option = option.clone();
+    option.replace.mapping = replace_request.mappings.clone();
+    option.replace.hash = replace_request.hash.clone();
+    option.rules = collect_rules(&option)?;
+    // The report is the response, nothing goes to stdout
+    option.global.json = true;
+    option.global.dry_run = !apply;
+    let _lock = lock_for_run(&mut option)?;
+
+    replace_files(matcher, &option)
+}
+
+/// Read one request, the request line and headers are bounded by `MAX_HEAD_LEN` and `MAX_HEADERS`, the body by
+/// `MAX_BODY_LEN`
+fn read_request(stream: &mut impl Read) -> Result<Request> {
+    let mut reader = BufReader::new(stream.take(MAX_HEAD_LEN));
+    let mut line = String::new();
+    read_head_line(&mut reader, &mut line).context("Failed to read request")?;
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(path)) = (parts.next(), parts.next()) else {
+        bail!("Malformed request line: {:?}", line.trim_end());
+    };
+    let (method, path) = (method.to_owned(), path.to_owned());
+
+    let mut content_length = 0;
+    let (mut host, mut origin, mut content_type, mut authorization) = (None, None, None, None);
+    for header_count in 0.. {
+        line.clear();
+        read_head_line(&mut reader, &mut line).context("Failed to read request headers")?;
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if header_count == MAX_HEADERS {
+            bail!("More than {} request headers", MAX_HEADERS);
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            let value = value.trim();
+            match name.to_ascii_lowercase().as_str() {
+                "content-length" => content_length = value.parse().with_context(|| format!("Invalid Content-Length: {:?}", value))?,
+                "host" => host = Some(value.to_owned()),
+                "origin" => origin = Some(value.to_owned()),
+                "content-type" => content_type = Some(value.to_owned()),
+                "authorization" => authorization = Some(value.to_owned()),
+                _ => {}
+            }
+        }
+    }
+    if content_length > MAX_BODY_LEN {
+        bail!("Request body of {} bytes is too large", content_length);
+    }
+
+    // What the head left of the limit may be too little for the body, the part already buffered is not counted
+    reader.get_mut().set_limit(content_length as u64);
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body).context("Failed to read request body")?;
+    Ok(Request { method, path, host, origin, content_type, authorization, body })
+}
+
+/// One line of the request head into `line`, fails when the head goes past `MAX_HEAD_LEN`
+fn read_head_line(reader: &mut impl BufRead, line: &mut String) -> Result<()> {
+    if reader.read_line(line)? == 0 || !line.ends_with('\n') {
+        bail!("Request head cut short or larger than {} bytes", MAX_HEAD_LEN);
+    }
+    Ok(())
+}
+
+fn respond(stream: &mut TcpStream, status: u16, body: &serde_json::Value) -> Result<()> {
+    let reason = match status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        415 => "Unsupported Media Type",
+        _ => "Internal Server Error",
+    };
+    let body = serde_json::to_string_pretty(body)?;
+    write!(stream, "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", status, reason, body.len(), body)?;
+    stream.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn read_request_reads_the_head_and_body() {
+        let mut stream = Cursor::new(b"POST /apply HTTP/1.1\r\nHost: 127.0.0.1:7070\r\nContent-Type: application/json\r\nContent-Length: 2\r\n\r\n{}".to_vec());
+        let request = read_request(&mut stream).unwrap();
+
+        assert_eq!((request.method.as_str(), request.path.as_str()), ("POST", "/apply"));
+        assert_eq!((request.host.as_deref(), request.body.as_slice()), (Some("127.0.0.1:7070"), &b"{}"[..]));
+    }
+
+    #[test]
+    fn read_request_bounds_the_head() {
+        let long_line = [b"GET /torrents HTTP/1.1\r\nX: ".as_slice(), &vec![b'x'; MAX_HEAD_LEN as usize], b"\r\n\r\n"].concat();
+        assert!(read_request(&mut Cursor::new(long_line)).is_err());
+
+        let many_headers = [b"GET /torrents HTTP/1.1\r\n".as_slice(), &b"X: x\r\n".repeat(MAX_HEADERS + 1), b"\r\n"].concat();
+        assert!(read_request(&mut Cursor::new(many_headers)).is_err());
+    }
+
+    #[test]
+    fn reports_keep_only_the_last_applies() {
+        let mut reports = Reports::default();
+        for _ in 0..MAX_REPORTS + 5 {
+            reports.push(RunSummary::default());
+        }
+
+        assert_eq!(reports.kept.len(), MAX_REPORTS);
+        assert!(reports.get(5).is_none());
+        assert!(reports.get(6).is_some() && reports.get(MAX_REPORTS + 5).is_some());
+    }
+}
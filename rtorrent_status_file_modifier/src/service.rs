@@ -0,0 +1,71 @@
+use std::path::PathBuf;
+use std::process::Command;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
+use tracing::info;
+
+use crate::running;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// A systemd unit stopped for the run by --manage-service, started again once the run is over
+pub struct ManagedService {
+    name: String,
+    was_active: bool,
+    dry_run: bool,
+}
+
+impl ManagedService {
+    /// Stop the unit and wait until rtorrent released the lock of every session directory, so it flushed the
+    /// session files. Nothing is left stopped when this fails.
+    pub fn stop(name: &str, session_dirs: &[PathBuf], timeout: Duration, dry_run: bool) -> Result<Self> {
+        let was_active = systemctl(&["is-active", "--quiet", name]).is_ok();
+        let service = ManagedService { name: name.to_owned(), was_active, dry_run };
+        if !was_active {
+            info!("Service {} is not active, it is left stopped", name);
+            return Ok(service);
+        }
+        if dry_run {
+            info!("Would stop service {}", name);
+            return Ok(service);
+        }
+
+        info!("Stopping service {}", name);
+        systemctl(&["stop", name]).with_context(|| format!("Failed to stop service {}, nothing was done", name))?;
+        let started = Instant::now();
+        while let Some(session_dir) = session_dirs.iter().find(|session_dir| running::is_running(session_dir)) {
+            if started.elapsed() >= timeout {
+                service.start()?;
+                bail!("rtorrent still holds the lock of {} {}s after service {} was stopped, it was started again and nothing was done",
+                    session_dir.display(), timeout.as_secs(), name);
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+
+        Ok(service)
+    }
+
+    /// Start the unit again when it was running before the run
+    pub fn start(&self) -> Result<()> {
+        if !self.was_active {
+            return Ok(());
+        }
+        if self.dry_run {
+            info!("Would start service {}", self.name);
+            return Ok(());
+        }
+
+        info!("Starting service {}", self.name);
+        systemctl(&["start", &self.name]).with_context(|| format!("Failed to start service {} again", self.name))
+    }
+}
+
+fn systemctl(args: &[&str]) -> Result<()> {
+    let status = Command::new("systemctl").args(args).status().context("Failed to run systemctl")?;
+    if !status.success() {
+        bail!("systemctl {} failed with {}", args.join(" "), status);
+    }
+    Ok(())
+}
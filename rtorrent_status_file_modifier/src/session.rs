@@ -0,0 +1,203 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use reptool_core::bencode::{self, Value};
+use reptool_core::file::{read_buffered, DEFAULT_IO_BUFFER};
+use reptool_core::{Encoding, InfoHash, ModifyReport, SessionFile};
+use tracing::warn;
+
+use crate::template::host_of;
+use crate::{info_hash_of, label};
+
+/// The files rtorrent keeps per torrent in its session directory, all named after the info-hash
+#[derive(Debug, Default, Clone)]
+pub struct Companions {
+    pub torrent: Option<PathBuf>,
+    pub rtorrent: Option<PathBuf>,
+    pub resume: Option<PathBuf>,
+}
+
+/// Group session files by their `<HASH>.torrent` base name
+pub fn group_companions(file_paths: &[PathBuf]) -> BTreeMap<String, Companions> {
+    let mut groups: BTreeMap<String, Companions> = BTreeMap::new();
+    for file_path in file_paths {
+        let Some(file_name) = file_path.file_name().and_then(|name| name.to_str()) else {
+            continue;
+        };
+
+        if let Some(base) = file_name.strip_suffix(".rtorrent") {
+            groups.entry(base.to_owned()).or_default().rtorrent = Some(file_path.clone());
+        } else if let Some(base) = file_name.strip_suffix(".libtorrent_resume") {
+            groups.entry(base.to_owned()).or_default().resume = Some(file_path.clone());
+        } else if file_name.ends_with(".torrent") {
+            groups.entry(file_name.to_owned()).or_default().torrent = Some(file_path.clone());
+        }
+    }
+
+    groups
+}
+
+impl Companions {
+    pub fn session_files(&self) -> impl Iterator<Item = &Path> {
+        self.rtorrent.iter().chain(self.resume.iter()).map(PathBuf::as_path)
+    }
+}
+
+/// One torrent of the session directory as shown to the user
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub hash: String,
+    /// `info.name` of the .torrent, the hash when there is no .torrent
+    pub name: String,
+    /// Current value of the keyword field
+    pub directory: String,
+    pub rtorrent_path: PathBuf,
+    /// Total length of the files of the .torrent
+    pub size: Option<u64>,
+    /// The `complete` flag rtorrent keeps in the .torrent.rtorrent
+    pub complete: Option<bool>,
+    /// Computed from the .torrent, it should match `hash`
+    pub info_hash: Option<InfoHash>,
+    /// The ruTorrent label, decoded
+    pub label: Option<String>,
+    /// Host of the first tracker of the .torrent
+    pub tracker_host: Option<String>,
+}
+
+/// Load every torrent having a .torrent.rtorrent, files that fail to decode are skipped with a warning
+pub fn load_sessions(file_paths: &[PathBuf], key: &str, encoding: Encoding) -> Vec<Session> {
+    let mut sessions = Vec::new();
+    for companions in group_companions(file_paths).into_values() {
+        let Some(rtorrent_path) = companions.rtorrent else {
+            continue;
+        };
+        let session_file = match SessionFile::open(&rtorrent_path, DEFAULT_IO_BUFFER) {
+            Ok(session_file) => session_file,
+            Err(err) => {
+                warn!("Skipped {}: {:#}", rtorrent_path.display(), err);
+                continue;
+            }
+        };
+
+        let hash = info_hash_of(&rtorrent_path);
+        let directory = match session_file.value().as_dict().and_then(|dict| dict.get(key.as_bytes())) {
+            Some(Value::Bytes(bytes)) => encoding.decode(bytes).unwrap_or_else(|_| String::from_utf8_lossy(bytes).into_owned()),
+            _ => String::new(),
+        };
+        let torrent_data = companions.torrent.as_deref().and_then(|torrent_path| read_buffered(torrent_path, DEFAULT_IO_BUFFER).ok());
+        let info_hash = torrent_data.as_deref().and_then(|data| InfoHash::of_torrent(data).ok());
+        let torrent = torrent_data.as_deref().and_then(|data| bencode::decode(data).ok());
+        let tracker_host = torrent.as_ref().and_then(|torrent| announce_urls(torrent).first().and_then(|url| host_of(url)));
+        let info = torrent.and_then(|torrent| torrent.as_dict()?.get(b"info".as_slice()).cloned());
+        let info = info.as_ref().and_then(Value::as_dict);
+        let name = match info.and_then(|info| info.get(b"name".as_slice())) {
+            Some(Value::Bytes(name)) => String::from_utf8_lossy(name).into_owned(),
+            _ => hash.clone(),
+        };
+        let size = info.and_then(total_length);
+        let complete = complete_flag(session_file.value());
+
+        let label = match session_file.value().as_dict().and_then(|dict| dict.get(b"custom1".as_slice())) {
+            Some(Value::Bytes(label)) if !label.is_empty() => Some(label::decode(label)),
+            _ => None,
+        };
+
+        sessions.push(Session { hash, name, directory, rtorrent_path, size, complete, info_hash, label, tracker_host });
+    }
+
+    sessions
+}
+
+/// The `<HASH>.torrent` next to a session file
+pub fn torrent_path_of(file_path: &Path) -> PathBuf {
+    let file_name = file_path.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+    let base = file_name.find(".torrent").map_or(&*file_name, |index| &file_name[..index]);
+    file_path.with_file_name(format!("{}.torrent", base))
+}
+
+/// `info.name` of a .torrent
+pub fn torrent_name(torrent: &Value) -> Option<String> {
+    match torrent.as_dict()?.get(b"info".as_slice())?.as_dict()?.get(b"name".as_slice())? {
+        Value::Bytes(name) => Some(String::from_utf8_lossy(name).into_owned()),
+        _ => None,
+    }
+}
+
+/// `announce` and every tier of `announce-list` of a .torrent
+pub fn announce_urls(torrent: &Value) -> Vec<String> {
+    let Some(dict) = torrent.as_dict() else {
+        return Vec::new();
+    };
+
+    let mut urls = Vec::new();
+    if let Some(Value::Bytes(url)) = dict.get(b"announce".as_slice()) {
+        urls.push(String::from_utf8_lossy(url).into_owned());
+    }
+    if let Some(Value::List(tiers)) = dict.get(b"announce-list".as_slice()) {
+        for tier in tiers {
+            let Value::List(tier) = tier else {
+                continue;
+            };
+            urls.extend(tier.iter().filter_map(|url| match url {
+                Value::Bytes(url) => Some(String::from_utf8_lossy(url).into_owned()),
+                _ => None,
+            }));
+        }
+    }
+
+    urls
+}
+
+/// Whether the torrent of the `<HASH>.torrent` path has all its data, from the `complete` flag of its
+/// .torrent.rtorrent or else the `bitfield` of its .libtorrent_resume, None when neither says
+pub fn completion(torrent_path: &Path, io_buffer: usize) -> Option<bool> {
+    let companion = |suffix: &str| {
+        let mut file_name = torrent_path.file_name()?.to_os_string();
+        file_name.push(suffix);
+        let data = read_buffered(&torrent_path.with_file_name(file_name), io_buffer).ok()?;
+        bencode::decode(&data).ok()
+    };
+    if let Some(complete) = companion(".rtorrent").as_ref().and_then(complete_flag) {
+        return Some(complete);
+    }
+    // libtorrent writes the number of chunks done when that is all or none of them, the bitmap of the chunks otherwise
+    match companion(".libtorrent_resume")?.as_dict()?.get(b"bitfield".as_slice())? {
+        Value::Integer(chunks) => Some(*chunks != 0),
+        Value::Bytes(_) => Some(false),
+        _ => None,
+    }
+}
+
+fn complete_flag(rtorrent: &Value) -> Option<bool> {
+    match rtorrent.as_dict()?.get(b"complete".as_slice())? {
+        Value::Integer(complete) => Some(*complete != 0),
+        _ => None,
+    }
+}
+
+/// `length` of a single file torrent, the sum of the `files` lengths of a multi file one
+fn total_length(info: &BTreeMap<Vec<u8>, Value>) -> Option<u64> {
+    if let Some(Value::Integer(length)) = info.get(b"length".as_slice()) {
+        return u64::try_from(*length).ok();
+    }
+    let Some(Value::List(files)) = info.get(b"files".as_slice()) else {
+        return None;
+    };
+    files.iter().map(|file| match file.as_dict()?.get(b"length".as_slice())? {
+        Value::Integer(length) => u64::try_from(*length).ok(),
+        _ => None,
+    }).sum()
+}
+
+/// Set the top level string `key` of a session file to `value` and write it atomically
+#[cfg_attr(not(feature = "tui"), allow(dead_code))]
+pub fn modify_session_file(file_path: &Path, key: &str, value: &str, encoding: Encoding) -> Result<Option<ModifyReport>> {
+    let mut session_file = SessionFile::open(file_path, DEFAULT_IO_BUFFER)?;
+    let modification = session_file.set(key, value, encoding)?;
+    if modification.is_some() {
+        session_file.save(DEFAULT_IO_BUFFER)?;
+    }
+
+    Ok(modification)
+}
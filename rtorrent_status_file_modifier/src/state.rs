@@ -0,0 +1,148 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use anyhow::{Context, Result};
+use reptool_core::file::{read_buffered, write_atomic, DEFAULT_IO_BUFFER};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::info;
+
+/// Name of the state file in the input directory when --state-file is not given
+pub const DEFAULT_STATE_FILE: &str = ".reptool-state.json";
+
+/// What the previous runs left each session file as, for --since-last-run. The state only holds for the rules and
+/// the output path it was recorded with, other ones start over from every file. With an output path the input files
+/// are never rewritten, the copy written from each one is recorded with it.
+pub struct StateFile {
+    path: PathBuf,
+    state: State,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct State {
+    fingerprint: String,
+    files: BTreeMap<PathBuf, FileState>,
+}
+
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct FileState {
+    modified_ns: u128,
+    sha256: String,
+    /// The copy in the output path and its state
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    output: Option<(PathBuf, Box<FileState>)>,
+}
+
+impl FileState {
+    fn of(file_path: &Path) -> Result<Self> {
+        Ok(FileState { modified_ns: modified_ns(file_path)?, sha256: sha256(file_path)?, output: None })
+    }
+
+    /// Same modification time or else same content
+    fn matches(&self, file_path: &Path) -> bool {
+        modified_ns(file_path).is_ok_and(|modified_ns| modified_ns == self.modified_ns)
+            || sha256(file_path).is_ok_and(|sha256| sha256 == self.sha256)
+    }
+}
+
+impl StateFile {
+    /// The state recorded at `path` under `fingerprint`, empty when there is none yet or the rules changed
+    pub fn load(path: &Path, fingerprint: &str) -> Result<Self> {
+        let mut state = State::default();
+        if path.exists() {
+            let content = fs::read_to_string(path).with_context(|| format!("Failed to read state file: {:?}", path))?;
+            state = serde_json::from_str(&content).with_context(|| format!("Failed to parse state file: {:?}", path))?;
+        }
+        if state.fingerprint != fingerprint {
+            if !state.files.is_empty() {
+                info!("The rules or the output path changed since the last run, processing every file");
+            }
+            state = State { fingerprint: fingerprint.to_owned(), files: BTreeMap::new() };
+        }
+
+        Ok(StateFile { path: path.to_path_buf(), state })
+    }
+
+    /// Whether the file, and its copy at `output_path` when written to one, are as the last run left them
+    pub fn is_unchanged(&self, file_path: &Path, output_path: Option<&Path>) -> bool {
+        let Some(recorded) = self.state.files.get(file_path) else {
+            return false;
+        };
+        let is_output_unchanged = match (output_path, &recorded.output) {
+            (None, None) => true,
+            (Some(output_path), Some((recorded_path, recorded_output))) => output_path == recorded_path && recorded_output.matches(output_path),
+            _ => false,
+        };
+        is_output_unchanged && recorded.matches(file_path)
+    }
+
+    /// Remember the files as they are now, once processed, with the copy each one was written to if any
+    pub fn record<'a>(&mut self, file_paths: impl IntoIterator<Item = (&'a PathBuf, Option<PathBuf>)>) -> Result<()> {
+        for (file_path, output_path) in file_paths {
+            let mut file_state = FileState::of(file_path)?;
+            if let Some(output_path) = output_path {
+                file_state.output = Some((output_path.clone(), Box::new(FileState::of(&output_path)?)));
+            }
+            self.state.files.insert(file_path.clone(), file_state);
+        }
+        Ok(())
+    }
+
+    /// Write the state back, without the files that are gone
+    pub fn save(&mut self) -> Result<()> {
+        self.state.files.retain(|file_path, _| file_path.exists());
+        write_atomic(&self.path, serde_json::to_string_pretty(&self.state)?.as_bytes(), DEFAULT_IO_BUFFER)
+            .with_context(|| format!("Failed to write state file: {:?}", self.path))
+    }
+}
+
+/// Identifies what a run does to the files, `description` being every option that changes the outcome
+pub fn fingerprint(description: &str) -> String {
+    to_hex(&Sha256::digest(description.as_bytes()))
+}
+
+fn modified_ns(file_path: &Path) -> Result<u128> {
+    let modified = fs::metadata(file_path).and_then(|metadata| metadata.modified())
+        .with_context(|| format!("Failed to read modification time: {:?}", file_path))?;
+    Ok(modified.duration_since(UNIX_EPOCH).map_or(0, |duration| duration.as_nanos()))
+}
+
+fn sha256(file_path: &Path) -> Result<String> {
+    let content = read_buffered(file_path, DEFAULT_IO_BUFFER).with_context(|| format!("Failed to read file: {:?}", file_path))?;
+    Ok(to_hex(&Sha256::digest(&content)))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn an_output_copy_that_changed_is_processed_again() {
+        let dir = std::env::temp_dir().join(format!("reptool-test-state-{}", std::process::id()));
+        fs::create_dir_all(dir.join("out")).unwrap();
+        let (input_path, output_path) = (dir.join("HASH.torrent.rtorrent"), dir.join("out").join("HASH.torrent.rtorrent"));
+        fs::write(&input_path, b"d9:directory4:/olde").unwrap();
+        fs::write(&output_path, b"d9:directory4:/newe").unwrap();
+
+        let mut state_file = StateFile::load(&dir.join(DEFAULT_STATE_FILE), "rules").unwrap();
+        state_file.record([(&input_path, Some(output_path.clone()))]).unwrap();
+        state_file.save().unwrap();
+        let state_file = StateFile::load(&dir.join(DEFAULT_STATE_FILE), "rules").unwrap();
+        let is_unchanged = state_file.is_unchanged(&input_path, Some(&output_path));
+        let is_unchanged_elsewhere = state_file.is_unchanged(&input_path, Some(&dir.join("HASH.torrent.rtorrent")));
+        let is_unchanged_in_place = state_file.is_unchanged(&input_path, None);
+        fs::write(&output_path, b"d9:directory6:/otheree").unwrap();
+        let is_unchanged_after_edit = state_file.is_unchanged(&input_path, Some(&output_path));
+        let is_unchanged_other_rules = StateFile::load(&dir.join(DEFAULT_STATE_FILE), "other rules").unwrap().is_unchanged(&input_path, Some(&output_path));
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(is_unchanged);
+        assert!(!is_unchanged_elsewhere && !is_unchanged_in_place && !is_unchanged_after_edit && !is_unchanged_other_rules);
+    }
+}
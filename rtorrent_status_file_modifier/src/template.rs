@@ -0,0 +1,143 @@
+use std::path::Path;
+
+use anyhow::{bail, Result};
+use reptool_core::bencode::Value;
+use reptool_core::{Rule, SessionFile};
+
+use crate::{info_hash_of, label};
+use crate::session::{announce_urls, torrent_name, torrent_path_of};
+
+const PLACEHOLDERS: [&str; 4] = ["{name}", "{hash}", "{label}", "{tracker_host}"];
+
+/// What the placeholders of a replace string resolve to for one torrent, `None` when the torrent does not say
+pub struct TemplateVars {
+    name: Option<String>,
+    hash: String,
+    label: Option<String>,
+    tracker_host: Option<String>,
+    announce_urls: Vec<String>,
+}
+
+/// Whether a replace string of the run has to be resolved per torrent
+pub fn has_placeholders(rules: &[Rule]) -> bool {
+    rules.iter().any(|rule| PLACEHOLDERS.iter().any(|placeholder| rule.replace.contains(placeholder)))
+}
+
+impl TemplateVars {
+    /// Read the variables of the torrent of `session_file` from it and from its companion .torrent
+    pub fn new(session_file: &SessionFile, io_buffer: usize) -> Self {
+        let file_path = session_file.path();
+        let torrent = SessionFile::open(torrent_path_of(file_path), io_buffer).ok();
+        let label = match session_file.value().as_dict().and_then(|dict| dict.get(b"custom1".as_slice())) {
+            Some(Value::Bytes(label)) if !label.is_empty() => Some(label::decode(label)),
+            _ => None,
+        };
+        let announce_urls = torrent.as_ref().map(|torrent| announce_urls(torrent.value())).unwrap_or_default();
+
+        TemplateVars {
+            name: torrent.as_ref().and_then(|torrent| torrent_name(torrent.value())),
+            hash: info_hash_of(file_path),
+            label,
+            tracker_host: announce_urls.first().and_then(|url| host_of(url)),
+            announce_urls,
+        }
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    pub fn label(&self) -> Option<&str> {
+        self.label.as_deref()
+    }
+
+    /// Every announce URL of the .torrent, empty without one
+    pub fn announce_urls(&self) -> &[String] {
+        &self.announce_urls
+    }
+
+    /// `rules` with the placeholders of their replace strings resolved, fails when a used variable is unknown
+    pub fn expand_rules(&self, rules: &[Rule], file_path: &Path) -> Result<Vec<Rule>> {
+        rules.iter().map(|rule| Ok(Rule { replace: self.expand(&rule.replace, file_path)?, ..rule.clone() })).collect()
+    }
+
+    /// `text` with its placeholders resolved in one pass, a value holding a placeholder is not resolved again.
+    /// Fails when a used variable is unknown.
+    pub fn expand(&self, text: &str, file_path: &Path) -> Result<String> {
+        let mut expanded = String::with_capacity(text.len());
+        let mut rest = text;
+        while let Some(start) = rest.find('{') {
+            expanded.push_str(&rest[..start]);
+            rest = &rest[start..];
+            let Some(placeholder) = PLACEHOLDERS.iter().find(|placeholder| rest.starts_with(**placeholder)) else {
+                expanded.push('{');
+                rest = &rest[1..];
+                continue;
+            };
+            let Some(value) = self.value_of(placeholder) else {
+                bail!("No value for {} in {}", placeholder, file_path.display());
+            };
+            expanded.push_str(value);
+            rest = &rest[placeholder.len()..];
+        }
+        expanded.push_str(rest);
+
+        Ok(expanded)
+    }
+
+    fn value_of(&self, placeholder: &str) -> Option<&str> {
+        match placeholder {
+            "{name}" => self.name.as_deref(),
+            "{hash}" => Some(self.hash.as_str()),
+            "{label}" => self.label.as_deref(),
+            "{tracker_host}" => self.tracker_host.as_deref(),
+            _ => None,
+        }
+    }
+}
+
+/// Host part of a tracker URL, e.g. `tracker.example.org` for `udp://user@tracker.example.org:6969/announce`,
+/// or `::1` without its brackets for `http://[::1]:6969/announce`
+pub fn host_of(url: &str) -> Option<String> {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let authority = rest.split(['/', '?', '#']).next()?.rsplit('@').next()?;
+    let host = match authority.strip_prefix('[') {
+        Some(bracketed) => bracketed.split(']').next()?,
+        None => authority.split(':').next()?,
+    };
+    (!host.is_empty()).then(|| host.to_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(name: &str) -> TemplateVars {
+        TemplateVars { name: Some(name.to_owned()), hash: String::from("ABCD"), label: None, tracker_host: None, announce_urls: Vec::new() }
+    }
+
+    #[test]
+    fn expand_does_not_resolve_the_values_again() {
+        let file_path = Path::new("ABCD.torrent.rtorrent");
+        assert_eq!(vars("{hash}").expand("/data/{name}/{hash}", file_path).unwrap(), "/data/{hash}/ABCD");
+        // {label} is unknown but only comes from the name
+        assert_eq!(vars("x{label}").expand("/data/{name}", file_path).unwrap(), "/data/x{label}");
+        assert_eq!(vars("x").expand("/data/{other}/{", file_path).unwrap(), "/data/{other}/{");
+        assert!(vars("x").expand("/data/{label}", file_path).is_err());
+    }
+
+    #[test]
+    fn host_of_reads_names_and_addresses() {
+        for (url, host) in [
+            ("udp://user@Tracker.Example.org:6969/announce", Some("tracker.example.org")),
+            ("http://tracker.example.org/announce?passkey=x", Some("tracker.example.org")),
+            ("http://tracker.example.org?passkey=x", Some("tracker.example.org")),
+            ("http://[::1]:6969/announce", Some("::1")),
+            ("http://[2001:DB8::1]/announce", Some("2001:db8::1")),
+            ("http://10.0.0.1:80/announce", Some("10.0.0.1")),
+            ("http:///announce", None),
+        ] {
+            assert_eq!(host_of(url).as_deref(), host, "{}", url);
+        }
+    }
+}
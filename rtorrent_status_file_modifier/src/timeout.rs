@@ -0,0 +1,77 @@
+use std::sync::{Mutex, MutexGuard, PoisonError, TryLockError};
+use std::thread::JoinHandle;
+
+use anyhow::{bail, Result};
+use tracing::info;
+
+/// The workers of the files that timed out while being written, waited for before the run lock is released
+static LATE_WRITERS: Mutex<Vec<JoinHandle<()>>> = Mutex::new(Vec::new());
+
+/// Stands between the worker of a file processed with --file-timeout and its writes: once the file timed out the
+/// worker writes nothing, and a file being written when it times out is known to be
+#[derive(Default)]
+pub struct WriteGate {
+    cancelled: Mutex<bool>,
+}
+
+impl WriteGate {
+    /// Held by the worker while it writes, fails once the file timed out
+    pub fn enter(&self) -> Result<MutexGuard<'_, bool>> {
+        let cancelled = self.cancelled.lock().unwrap_or_else(PoisonError::into_inner);
+        if *cancelled {
+            bail!("Timed out before the file was written, it was left untouched");
+        }
+        Ok(cancelled)
+    }
+
+    /// Keep the worker from writing, `false` when it is writing already
+    pub fn cancel(&self) -> bool {
+        match self.cancelled.try_lock() {
+            Ok(mut cancelled) => *cancelled = true,
+            Err(TryLockError::Poisoned(poisoned)) => *poisoned.into_inner() = true,
+            Err(TryLockError::WouldBlock) => return false,
+        }
+        true
+    }
+}
+
+/// Wait for `worker` before the run lock is released, its file timed out while being written
+pub fn keep_late_writer(worker: JoinHandle<()>) {
+    LATE_WRITERS.lock().unwrap_or_else(PoisonError::into_inner).push(worker);
+}
+
+/// Wait for the files that timed out while being written to be done
+pub fn wait_for_late_writers() {
+    let workers = std::mem::take(&mut *LATE_WRITERS.lock().unwrap_or_else(PoisonError::into_inner));
+    if !workers.is_empty() {
+        info!("Waiting for {} timed out file(s) still being written", workers.len());
+    }
+    for worker in workers {
+        let _ = worker.join();
+    }
+}
+
+/// Waits for the late writers when dropped, declared after the run locks so it is dropped before them
+pub struct LateWriters;
+
+impl Drop for LateWriters {
+    fn drop(&mut self) {
+        wait_for_late_writers();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_gate_refuses_writes_once_cancelled() {
+        let gate = WriteGate::default();
+        let writing = gate.enter().unwrap();
+        assert!(!gate.cancel());
+        drop(writing);
+
+        assert!(gate.cancel());
+        assert!(gate.enter().is_err());
+    }
+}
@@ -0,0 +1,117 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use anyhow::{Context, Result};
+use reptool_core::{Encoding, ModifyReport, SessionFile};
+use tracing::{info, warn};
+
+use crate::data::{self, Relocated};
+
+// Next to the session file until the commit renames it over the original
+const STAGED_SUFFIX: &str = ".reptool-staged";
+// The original during the commit, to put back when a rename fails
+const ROLLBACK_SUFFIX: &str = ".reptool-rollback";
+
+/// The files rewritten by a --transactional run, staged next to the originals and only renamed over them once
+/// every file of the run, or of the batch with --batch-size, went through
+#[derive(Default)]
+pub struct Transaction {
+    staged: Mutex<Vec<PathBuf>>,
+    /// The payloads moved by --move-data or --link-data for the staged files, put back on a rollback
+    relocated: Mutex<Vec<Relocated>>,
+}
+
+impl Transaction {
+    /// Write the new content of `session_file` next to it, checked to decode back to the same value with
+    /// `modifications` in place, the number of bytes written
+    pub fn stage(&self, session_file: &SessionFile, modifications: &[ModifyReport], encoding: Encoding, io_buffer: usize) -> Result<u64> {
+        let target = session_file.path();
+        let staged_path = suffixed(target, STAGED_SUFFIX);
+        let written = session_file.save_checked_as(&staged_path, modifications, encoding, io_buffer)
+            .with_context(|| format!("Failed to stage file: {:?}", staged_path))?;
+        self.staged.lock().expect("Transaction poisoned").push(target.to_path_buf());
+
+        Ok(written)
+    }
+
+    /// Keep the payloads moved for a staged file, to put them back if the transaction is rolled back
+    pub fn keep_relocated(&self, relocated: Vec<Relocated>) {
+        self.relocated.lock().expect("Transaction poisoned").extend(relocated);
+    }
+
+    /// Rename every staged file over its original, or put all the originals back when one rename fails
+    pub fn commit(&self) -> Result<usize> {
+        let targets = std::mem::take(&mut *self.staged.lock().expect("Transaction poisoned"));
+        let relocated = std::mem::take(&mut *self.relocated.lock().expect("Transaction poisoned"));
+        if targets.is_empty() {
+            return Ok(0);
+        }
+        let mut committed: Vec<PathBuf> = Vec::with_capacity(targets.len());
+        for target in &targets {
+            if let Err(err) = commit_one(target) {
+                for done in committed.iter().rev() {
+                    restore(done);
+                }
+                for target in targets.iter().filter(|target| !committed.contains(*target)) {
+                    remove_staged(target);
+                }
+                data::undo_all(&relocated);
+                return Err(err.context("Failed to commit, every file was rolled back"));
+            }
+            committed.push(target.clone());
+        }
+        for target in &committed {
+            let rollback_path = suffixed(target, ROLLBACK_SUFFIX);
+            if let Err(err) = fs::remove_file(&rollback_path) {
+                warn!("Failed to remove {}: {}", rollback_path.display(), err);
+            }
+        }
+
+        info!("Committed {} file(s)", committed.len());
+        Ok(committed.len())
+    }
+
+    /// Drop the staged files, the originals were never touched, and put back the payloads moved for them
+    pub fn rollback(&self) -> usize {
+        let targets = std::mem::take(&mut *self.staged.lock().expect("Transaction poisoned"));
+        for target in &targets {
+            remove_staged(target);
+        }
+        data::undo_all(&std::mem::take(&mut *self.relocated.lock().expect("Transaction poisoned")));
+        targets.len()
+    }
+}
+
+fn commit_one(target: &Path) -> Result<()> {
+    let rollback_path = suffixed(target, ROLLBACK_SUFFIX);
+    // A hard link keeps the original without copying it, where the file system has them
+    if fs::hard_link(target, &rollback_path).is_err() {
+        fs::copy(target, &rollback_path).with_context(|| format!("Failed to keep original: {:?}", target))?;
+    }
+    fs::rename(suffixed(target, STAGED_SUFFIX), target).with_context(|| format!("Failed to commit file: {:?}", target))
+}
+
+fn restore(target: &Path) {
+    let rollback_path = suffixed(target, ROLLBACK_SUFFIX);
+    if let Err(err) = fs::rename(&rollback_path, target) {
+        warn!("Failed to roll back {}, the original is kept at {}: {}", target.display(), rollback_path.display(), err);
+    }
+}
+
+fn remove_staged(target: &Path) {
+    let staged_path = suffixed(target, STAGED_SUFFIX);
+    if staged_path.exists() {
+        if let Err(err) = fs::remove_file(&staged_path) {
+            warn!("Failed to remove {}: {}", staged_path.display(), err);
+        }
+    }
+    // A commit that failed on this file leaves its link behind
+    let _ = fs::remove_file(suffixed(target, ROLLBACK_SUFFIX));
+}
+
+fn suffixed(path: &Path, suffix: &str) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(suffix);
+    path.with_file_name(file_name)
+}
@@ -0,0 +1,171 @@
+use std::path::PathBuf;
+
+use anyhow::Result;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Modifier, Style};
+use ratatui::widgets::{Block, Paragraph, Row, Table, TableState};
+use ratatui::{DefaultTerminal, Frame};
+use reptool_core::Encoding;
+
+use crate::session::{load_sessions, modify_session_file, Session};
+
+enum Mode {
+    Normal,
+    Filter,
+    Edit(String),
+    Confirm(String),
+}
+
+struct Browser {
+    sessions: Vec<Session>,
+    key: String,
+    encoding: Encoding,
+    filter: String,
+    /// Indexes into `sessions` matching the filter
+    visible: Vec<usize>,
+    state: TableState,
+    mode: Mode,
+    status: String,
+    should_quit: bool,
+}
+
+/// Browse the torrents of a session directory, filter them and edit their directory in place
+pub fn browse(file_paths: &[PathBuf], key: &str, encoding: Encoding) -> Result<()> {
+    let sessions = load_sessions(file_paths, key, encoding);
+    let mut browser = Browser {
+        visible: (0..sessions.len()).collect(),
+        sessions,
+        key: key.to_owned(),
+        encoding,
+        filter: String::new(),
+        state: TableState::default().with_selected(Some(0)),
+        mode: Mode::Normal,
+        status: String::new(),
+        should_quit: false,
+    };
+
+    ratatui::run(|terminal| browser.run(terminal))
+}
+
+impl Browser {
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> Result<()> {
+        while !self.should_quit {
+            terminal.draw(|frame| self.render(frame))?;
+            if let Event::Key(key_event) = event::read()? {
+                if key_event.kind == KeyEventKind::Press {
+                    self.handle_key(key_event.code);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn selected(&self) -> Option<usize> {
+        self.state.selected().and_then(|row| self.visible.get(row).copied())
+    }
+
+    fn apply_filter(&mut self) {
+        let filter = self.filter.to_lowercase();
+        self.visible = self.sessions.iter().enumerate()
+            .filter(|(_, session)| {
+                [&session.name, &session.hash, &session.directory].iter().any(|field| field.to_lowercase().contains(&filter))
+            })
+            .map(|(index, _)| index)
+            .collect();
+        self.state.select(if self.visible.is_empty() { None } else { Some(0) });
+    }
+
+    fn handle_key(&mut self, code: KeyCode) {
+        match (&mut self.mode, code) {
+            (Mode::Normal, KeyCode::Char('q') | KeyCode::Esc) => self.should_quit = true,
+            (Mode::Normal, KeyCode::Down | KeyCode::Char('j')) => self.state.select_next(),
+            (Mode::Normal, KeyCode::Up | KeyCode::Char('k')) => self.state.select_previous(),
+            (Mode::Normal, KeyCode::Char('/')) => self.mode = Mode::Filter,
+            (Mode::Normal, KeyCode::Char('e') | KeyCode::Enter) => {
+                if let Some(index) = self.selected() {
+                    self.mode = Mode::Edit(self.sessions[index].directory.clone());
+                }
+            }
+
+            (Mode::Filter, KeyCode::Char(c)) => {
+                self.filter.push(c);
+                self.apply_filter();
+            }
+            (Mode::Filter, KeyCode::Backspace) => {
+                self.filter.pop();
+                self.apply_filter();
+            }
+            (Mode::Filter, KeyCode::Enter | KeyCode::Esc) => self.mode = Mode::Normal,
+
+            (Mode::Edit(value), KeyCode::Char(c)) => value.push(c),
+            (Mode::Edit(value), KeyCode::Backspace) => {
+                value.pop();
+            }
+            (Mode::Edit(value), KeyCode::Enter) => self.mode = Mode::Confirm(std::mem::take(value)),
+            (Mode::Edit(_), KeyCode::Esc) => self.mode = Mode::Normal,
+
+            (Mode::Confirm(value), KeyCode::Char('y')) => {
+                let value = std::mem::take(value);
+                self.save(value);
+                self.mode = Mode::Normal;
+            }
+            (Mode::Confirm(_), _) => {
+                self.status = String::from("Cancelled");
+                self.mode = Mode::Normal;
+            }
+
+            _ => {}
+        }
+    }
+
+    fn save(&mut self, value: String) {
+        let Some(index) = self.selected() else {
+            return;
+        };
+
+        let session = &mut self.sessions[index];
+        self.status = match modify_session_file(&session.rtorrent_path, &self.key, &value, self.encoding) {
+            Ok(Some(_)) => {
+                session.directory = value;
+                format!("Saved {}", session.name)
+            }
+            Ok(None) => format!("{} is unchanged", session.name),
+            Err(err) => format!("Failed to save {}: {:#}", session.name, err),
+        };
+    }
+
+    fn render(&mut self, frame: &mut Frame) {
+        let [table_area, input_area, status_area] = Layout::vertical([
+            Constraint::Min(1),
+            Constraint::Length(1),
+            Constraint::Length(1),
+        ]).areas(frame.area());
+
+        let rows = self.visible.iter().map(|&index| {
+            let session = &self.sessions[index];
+            Row::new([session.name.as_str(), session.hash.as_str(), session.directory.as_str()])
+        });
+        let table = Table::new(rows, [Constraint::Percentage(35), Constraint::Length(40), Constraint::Fill(1)])
+            .header(Row::new(["Name", "Hash", self.key.as_str()]).style(Style::new().add_modifier(Modifier::BOLD)))
+            .block(Block::bordered().title(format!(" {} / {} torrents ", self.visible.len(), self.sessions.len())))
+            .row_highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(table, table_area, &mut self.state);
+
+        let input = match &self.mode {
+            Mode::Normal if self.filter.is_empty() => String::new(),
+            Mode::Normal | Mode::Filter => format!("Filter: {}", self.filter),
+            Mode::Edit(value) => format!("New {}: {}", self.key, value),
+            Mode::Confirm(value) => format!("Write {} = {:?}? [y/N]", self.key, value),
+        };
+        frame.render_widget(Paragraph::new(input), input_area);
+
+        let status = if self.status.is_empty() {
+            "q quit  j/k move  / filter  e edit"
+        } else {
+            self.status.as_str()
+        };
+        frame.render_widget(Paragraph::new(status), status_area);
+    }
+}
@@ -0,0 +1,90 @@
+use std::env;
+use std::io::{self, IsTerminal};
+use std::sync::OnceLock;
+
+use reptool_core::report::ModifyReport;
+
+pub const RED: &str = "\x1b[31m";
+pub const GREEN: &str = "\x1b[32m";
+pub const YELLOW: &str = "\x1b[33m";
+pub const CYAN: &str = "\x1b[36m";
+const RESET: &str = "\x1b[0m";
+
+/// When the output is colored
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ColorChoice {
+    /// Only on a terminal, and never with NO_COLOR set
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+// Set once from the command line before anything is printed
+static CHOICE: OnceLock<(ColorChoice, bool)> = OnceLock::new();
+
+/// Use `choice` for everything printed from now on, `quiet` dropping the skipped files
+pub fn init(choice: ColorChoice, quiet: bool) {
+    let _ = CHOICE.set((choice, quiet));
+}
+
+pub fn stdout_colors() -> bool {
+    colors(io::stdout().is_terminal())
+}
+
+pub fn stderr_colors() -> bool {
+    colors(io::stderr().is_terminal())
+}
+
+fn colors(is_terminal: bool) -> bool {
+    match CHOICE.get().map_or(ColorChoice::Auto, |(choice, _)| *choice) {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        ColorChoice::Auto => is_terminal && env::var_os("NO_COLOR").is_none(),
+    }
+}
+
+/// `text` in the color `code` when `color`
+pub fn paint(code: &str, text: &str, color: bool) -> String {
+    if color { format!("{}{}{}", code, text, RESET) } else { text.to_owned() }
+}
+
+/// A rewritten field on stdout, the file in green and the changed part of the value from red to green
+pub fn print_change(file_path: &str, modification: &ModifyReport) {
+    let color = stdout_colors();
+    let values = if color {
+        let (prefix, old, new, suffix) = split_change(&modification.old_value, &modification.new_value);
+        format!("\"{}{}{}\" -> \"{}{}{}\"", prefix, paint(RED, old, true), suffix, prefix, paint(GREEN, new, true), suffix)
+    } else {
+        format!("{:?} -> {:?}", modification.old_value, modification.new_value)
+    };
+    println!("{}: {} {} (length {} -> {})", paint(GREEN, file_path, color), modification.key, values,
+        modification.old_length, modification.new_length);
+}
+
+/// A file left alone although it matched, in yellow on stderr unless --quiet
+pub fn print_skipped(file_path: &str, reason: &str) {
+    if CHOICE.get().is_some_and(|(_, quiet)| *quiet) {
+        return;
+    }
+    eprintln!("{}", paint(YELLOW, &format!("Skipped {}: {}", file_path, reason), stderr_colors()));
+}
+
+/// A file that could not be processed, in red on stderr
+pub fn print_failed(file_path: &str, error: &str) {
+    eprintln!("  {}", paint(RED, &format!("{}: {}", file_path, error), stderr_colors()));
+}
+
+/// Common prefix, changed parts and common suffix of two values, split on character boundaries
+pub fn split_change<'a>(old: &'a str, new: &'a str) -> (&'a str, &'a str, &'a str, &'a str) {
+    let prefix_len: usize = old.chars().zip(new.chars())
+        .take_while(|(a, b)| a == b)
+        .map(|(a, _)| a.len_utf8())
+        .sum();
+    let suffix_len: usize = old[prefix_len..].chars().rev().zip(new[prefix_len..].chars().rev())
+        .take_while(|(a, b)| a == b)
+        .map(|(a, _)| a.len_utf8())
+        .sum();
+
+    (&old[..prefix_len], &old[prefix_len..old.len() - suffix_len], &new[prefix_len..new.len() - suffix_len], &old[old.len() - suffix_len..])
+}
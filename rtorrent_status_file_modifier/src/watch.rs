@@ -0,0 +1,142 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use clap::Args;
+use tracing::info;
+
+use crate::glob::FileMatcher;
+use crate::{list_files, print_change, run_file, RepToolOption};
+
+#[derive(Args, Clone)]
+pub struct WatchArgs {
+    /// Session directory, its subdirectories are not watched
+    pub dir : String,
+
+    /// Search and replace pair, repeatable, the mappings of the config file apply when none is given
+    #[arg(short = 'e', long = "map", value_name = "OLD=NEW")]
+    pub mapping : Vec<String>,
+
+    /// Read OLD=NEW pairs from FILE, one per line, blank lines and lines starting with # are ignored
+    #[arg(long, value_name = "FILE")]
+    pub map_file : Option<String>,
+
+    /// Scan the directory every SECONDS instead of using inotify, for network filesystems and systems without it
+    #[arg(long, value_name = "SECONDS")]
+    pub poll : Option<u64>,
+}
+
+// Systems without inotify are scanned this often
+const DEFAULT_POLL_SECONDS: u64 = 5;
+
+/// Apply the rules to every .torrent.rtorrent written to the session directory, until killed.
+/// Our own rewrite of a file shows up as one more change of it, that one is skipped.
+pub fn watch(matcher: &FileMatcher, option: &RepToolOption, args: &WatchArgs) -> Result<()> {
+    let dir = option.input_dir()?;
+    if option.rules.is_empty() {
+        bail!("No rules to apply, give -e, --map-file or mappings in the config file");
+    }
+    if !option.global.json {
+        println!("Watching {} for new session files, {} rule(s)", dir.display(), option.rules.len());
+    }
+
+    let protected_hashes = HashSet::new();
+    let mut own_writes = HashSet::new();
+    let mut handle = |file_path: PathBuf| -> Result<()> {
+        if !file_path.to_string_lossy().ends_with(".torrent.rtorrent") || own_writes.remove(&file_path) {
+            return Ok(());
+        }
+
+        let file_report = run_file(&file_path, option, &protected_hashes);
+        if file_report.matched && !option.global.dry_run {
+            own_writes.insert(file_path);
+        }
+        if option.global.json {
+            println!("{}", serde_json::to_string(&file_report)?);
+        } else if !option.global.dry_run {
+            for modification in &file_report.modifications {
+                print_change(&file_report.file, modification);
+            }
+        }
+        Ok(())
+    };
+
+    #[cfg(target_os = "linux")]
+    if args.poll.is_none() {
+        return inotify(dir, &mut handle);
+    }
+    let seconds = args.poll.unwrap_or(DEFAULT_POLL_SECONDS).max(1);
+    poll(matcher, dir, Duration::from_secs(seconds), &mut handle)
+}
+
+/// Call `handle` with every file closed after writing or moved into `dir`
+#[cfg(target_os = "linux")]
+fn inotify(dir: &Path, handle: &mut dyn FnMut(PathBuf) -> Result<()>) -> Result<()> {
+    use std::ffi::{CString, OsStr};
+    use std::fs::File;
+    use std::io::{self, Read};
+    use std::os::fd::FromRawFd;
+    use std::os::unix::ffi::OsStrExt;
+    use tracing::warn;
+
+    // struct inotify_event { int wd; uint32_t mask; uint32_t cookie; uint32_t len; char name[]; }
+    const HEADER_LEN: usize = 16;
+
+    // SAFETY: no pointer is passed, the descriptor is owned by `events` right after
+    let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC) };
+    if fd == -1 {
+        return Err(io::Error::last_os_error()).context("Failed to start inotify, try --poll");
+    }
+    // SAFETY: `fd` is a fresh descriptor nothing else owns
+    let mut events = unsafe { File::from_raw_fd(fd) };
+    let path = CString::new(dir.as_os_str().as_bytes())?;
+    // SAFETY: `path` is a NUL terminated string alive for the duration of the call
+    if unsafe { libc::inotify_add_watch(fd, path.as_ptr(), libc::IN_CLOSE_WRITE | libc::IN_MOVED_TO) } == -1 {
+        return Err(io::Error::last_os_error()).with_context(|| format!("Failed to watch directory: {:?}", dir));
+    }
+
+    let mut buffer = vec![0u8; 64 * 1024];
+    loop {
+        let read = events.read(&mut buffer).context("Failed to read inotify events")?;
+        let mut offset = 0;
+        while offset + HEADER_LEN <= read {
+            let field = |index: usize| u32::from_ne_bytes(buffer[offset + 4 * index..offset + 4 * index + 4].try_into().expect("4 bytes"));
+            let (mask, name_len) = (field(1), field(3) as usize);
+            if mask & libc::IN_Q_OVERFLOW != 0 {
+                warn!("Too many changes at once, some session files were not seen");
+            }
+            // The name is padded with NUL bytes
+            let name = &buffer[offset + HEADER_LEN..offset + HEADER_LEN + name_len];
+            let name = &name[..name.iter().position(|&byte| byte == 0).unwrap_or(name.len())];
+            if !name.is_empty() {
+                handle(dir.join(OsStr::from_bytes(name)))?;
+            }
+            offset += HEADER_LEN + name_len;
+        }
+    }
+}
+
+/// Call `handle` with every session file whose modification time changed since the previous scan
+fn poll(matcher: &FileMatcher, dir: &Path, interval: Duration, handle: &mut dyn FnMut(PathBuf) -> Result<()>) -> Result<()> {
+    let modified_times = |dir: &Path| -> Result<HashMap<PathBuf, _>> {
+        Ok(list_files(matcher, dir, 0)?.into_iter()
+            .filter_map(|file_path| {
+                let modified = file_path.metadata().ok()?.modified().ok()?;
+                Some((file_path, modified))
+            })
+            .collect())
+    };
+
+    let mut seen = modified_times(dir)?;
+    info!("Polling {} every {:?}", dir.display(), interval);
+    loop {
+        thread::sleep(interval);
+        for (file_path, modified) in modified_times(dir)? {
+            if seen.insert(file_path.clone(), modified) != Some(modified) {
+                handle(file_path)?;
+            }
+        }
+    }
+}
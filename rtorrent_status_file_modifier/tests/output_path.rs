@@ -0,0 +1,25 @@
+use std::fs;
+use std::process::Command;
+
+#[test]
+fn output_path_equal_to_input_path_edits_in_place() {
+    let dir = std::env::temp_dir().join(format!("reptool-output-path-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let file_path = dir.join("0123456789ABCDEF0123456789ABCDEF01234567.torrent.rtorrent");
+    fs::write(&file_path, b"d9:directory10:/mnt/data/e").unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_rtorrent_status_file_modifier"))
+        .arg(&dir).arg("/mnt/data/").arg("/srv/")
+        .arg("--output-path").arg(&dir)
+        .args(["--no-config", "--no-backup", "--color", "never"])
+        .output()
+        .unwrap();
+    let content = fs::read(&file_path).unwrap();
+    let entries = fs::read_dir(&dir).unwrap().count();
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Output path is the input path, modifying the input files in place"));
+    assert_eq!(content, b"d9:directory5:/srv/e");
+    assert_eq!(entries, 1);
+}